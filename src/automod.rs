@@ -0,0 +1,779 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use log::{info, warn};
+use poise::serenity_prelude::{
+    ChannelId, Colour, CreateAllowedMentions, CreateEmbed, CreateEmbedFooter, CreateMessage, EditMember, GuildId,
+    Mentionable, Message, Timestamp, UserId,
+};
+use poise::{ChoiceParameter, CreateReply};
+use regex::Regex;
+use crate::{commands, log_queue, serenity, tools};
+use crate::{Context, Data, Error};
+
+static INVITE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(discord\.gg|discord(app)?\.com/invite)/[a-z0-9-]+").unwrap()
+});
+
+/// How long the spam rule looks back, in seconds
+const SPAM_WINDOW: i64 = 10;
+/// How many identical messages within the spam window count as spam
+const SPAM_DUPLICATES: usize = 3;
+/// Messages shorter than this are never judged on their caps
+const CAPS_MIN_LETTERS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, poise::ChoiceParameter)]
+pub enum Rule {
+    #[name = "Banned words and regexes"]
+    Words,
+    #[name = "Invite links"]
+    Invites,
+    #[name = "Mass mentions"]
+    Mentions,
+    #[name = "Spam and duplicate messages"]
+    Spam,
+    #[name = "Excessive caps"]
+    Caps,
+    #[name = "Zalgo text"]
+    Zalgo,
+}
+
+impl Rule {
+    const ALL: [Rule; 6] = [Rule::Words, Rule::Invites, Rule::Mentions, Rule::Spam, Rule::Caps, Rule::Zalgo];
+
+    /// Name used in the database
+    pub fn key(&self) -> &'static str {
+        match self {
+            Rule::Words => "words",
+            Rule::Invites => "invites",
+            Rule::Mentions => "mentions",
+            Rule::Spam => "spam",
+            Rule::Caps => "caps",
+            Rule::Zalgo => "zalgo",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|r| r.key() == key)
+    }
+
+    /// Threshold used when none is configured. Rules that don't use a threshold return 0.
+    pub fn default_threshold(&self) -> u32 {
+        match self {
+            Rule::Words | Rule::Invites => 0,
+            Rule::Mentions => 5, // Mentions per message
+            Rule::Spam => 6, // Messages per spam window
+            Rule::Caps => 70, // Percentage of capital letters
+            Rule::Zalgo => 10, // Combining characters per message
+        }
+    }
+}
+
+/// What to do with a message that breaks a rule, ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, poise::ChoiceParameter)]
+pub enum Action {
+    #[name = "Log only"]
+    Log,
+    #[name = "Delete"]
+    Delete,
+    #[name = "Delete and warn"]
+    Warn,
+    #[name = "Delete and time out"]
+    Timeout,
+}
+
+impl Action {
+    /// Name used in the database
+    pub fn key(&self) -> &'static str {
+        match self {
+            Action::Log => "log",
+            Action::Delete => "delete",
+            Action::Warn => "warn",
+            Action::Timeout => "timeout",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        [Action::Log, Action::Delete, Action::Warn, Action::Timeout].into_iter().find(|a| a.key() == key)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RuleConfig {
+    pub action: Action,
+    pub threshold: u32,
+}
+
+/// The parts of a message the rule engine looks at
+pub struct MessageInfo<'a> {
+    pub content: &'a str,
+    /// Users and roles mentioned, @everyone counts as one
+    pub mentions: usize,
+    /// Messages the author sent within the spam window, including this one
+    pub recent_messages: usize,
+    /// Messages with this exact content the author sent within the spam window, including this one
+    pub duplicate_messages: usize,
+}
+
+#[derive(Debug)]
+pub struct Violation {
+    pub rule: Rule,
+    pub action: Action,
+    pub detail: String,
+}
+
+/// All automod rules of a guild, ready to be evaluated
+#[derive(Default)]
+pub struct RuleSet {
+    pub rules: HashMap<Rule, RuleConfig>,
+    words: Option<Regex>,
+    patterns: Vec<Regex>,
+}
+
+impl RuleSet {
+    /// Build a rule set from database rows. Invalid rows and patterns are skipped.
+    pub fn build(rules: Vec<(String, String, Option<i64>)>, patterns: Vec<(String, bool)>) -> Self {
+        let mut set = RuleSet::default();
+        for (rule, action, threshold) in rules {
+            let (rule, action) = match (Rule::from_key(&rule), Action::from_key(&action)) {
+                (Some(r), Some(a)) => (r, a),
+                _ => {
+                    warn!("Ignoring unknown automod rule \"{}\" with action \"{}\"", rule, action);
+                    continue;
+                }
+            };
+            // A threshold of 0 would make every message a violation
+            let threshold = threshold.and_then(|t| u32::try_from(t).ok())
+                .filter(|t| *t > 0)
+                .unwrap_or(rule.default_threshold());
+            set.rules.insert(rule, RuleConfig { action, threshold });
+        }
+
+        let words = patterns.iter().filter(|(_, is_regex)| !is_regex)
+            .map(|(w, _)| regex::escape(w))
+            .collect::<Vec<String>>();
+        if !words.is_empty() {
+            set.words = Regex::new(&format!(r"(?i)\b(?:{})\b", words.join("|"))).ok();
+        }
+        for (pattern, _) in patterns.iter().filter(|(_, is_regex)| *is_regex) {
+            match Regex::new(pattern) {
+                Ok(r) => set.patterns.push(r),
+                Err(e) => warn!("Ignoring invalid automod regex \"{}\": {}", pattern, e),
+            }
+        }
+        set
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Check a message against every enabled rule
+    pub fn evaluate(&self, message: &MessageInfo) -> Vec<Violation> {
+        let mut violations = vec![];
+        for (rule, config) in &self.rules {
+            let detail = match rule {
+                Rule::Words => self.words.iter().chain(self.patterns.iter())
+                    .find_map(|r| r.find(message.content))
+                    .map(|m| format!("Contains \"{}\"", m.as_str())),
+                Rule::Invites => INVITE_REGEX.find(message.content)
+                    .map(|m| format!("Contains invite {}", m.as_str())),
+                Rule::Mentions => (message.mentions >= config.threshold as usize)
+                    .then(|| format!("{} mentions", message.mentions)),
+                Rule::Spam => {
+                    if message.duplicate_messages >= SPAM_DUPLICATES {
+                        Some(format!("Same message sent {} times", message.duplicate_messages))
+                    } else if message.recent_messages >= config.threshold as usize {
+                        Some(format!("{} messages in {} seconds", message.recent_messages, SPAM_WINDOW))
+                    } else {
+                        None
+                    }
+                }
+                Rule::Caps => caps_percentage(message.content)
+                    .filter(|p| *p >= config.threshold)
+                    .map(|p| format!("{}% capital letters", p)),
+                Rule::Zalgo => {
+                    let count = zalgo_count(message.content);
+                    (count >= config.threshold as usize).then(|| format!("{} combining characters", count))
+                }
+            };
+            if let Some(detail) = detail {
+                violations.push(Violation { rule: *rule, action: config.action, detail });
+            }
+        }
+        violations
+    }
+}
+
+/// Percentage of letters that are uppercase, or None if the text is too short to judge
+pub fn caps_percentage(text: &str) -> Option<u32> {
+    let letters = text.chars().filter(|c| c.is_alphabetic()).collect::<Vec<char>>();
+    if letters.len() < CAPS_MIN_LETTERS {
+        return None;
+    }
+    let upper = letters.iter().filter(|c| c.is_uppercase()).count();
+    Some((upper * 100 / letters.len()) as u32)
+}
+
+/// Count combining characters, which stacked on top of each other make zalgo text
+pub fn zalgo_count(text: &str) -> usize {
+    text.chars()
+        .filter(|c| matches!(*c as u32, 0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x20D0..=0x20FF | 0xFE20..=0xFE2F))
+        .count()
+}
+
+/// (unix timestamp, content) of an author's recent messages
+type MessageHistory = VecDeque<(i64, String)>;
+
+/// Per guild rule sets and recent message history
+#[derive(Default)]
+pub struct AutomodState {
+    rule_sets: Mutex<HashMap<GuildId, Arc<RuleSet>>>,
+    history: Mutex<HashMap<(GuildId, UserId), MessageHistory>>,
+    /// When the history of authors that went quiet was last dropped
+    pruned_at: AtomicI64,
+}
+
+impl AutomodState {
+    /// Get the rule set of a guild, loading it from the database if it isn't cached
+    pub async fn rule_set(&self, data: &Data, guild_id: GuildId) -> Result<Arc<RuleSet>, Error> {
+        if let Some(set) = self.rule_sets.lock().unwrap().get(&guild_id) {
+            return Ok(set.clone());
+        }
+        let set = Arc::new(RuleSet::build(
+            data.database.get_automod_rules(&guild_id).await?,
+            data.database.get_automod_patterns(&guild_id).await?,
+        ));
+        self.rule_sets.lock().unwrap().insert(guild_id, set.clone());
+        Ok(set)
+    }
+
+    /// Forget the cached rule set of a guild after its configuration changed
+    pub fn invalidate(&self, guild_id: GuildId) {
+        self.rule_sets.lock().unwrap().remove(&guild_id);
+    }
+
+    /// Remember a message and return (messages in window, identical messages in window)
+    pub fn record_message(&self, guild_id: GuildId, user_id: UserId, content: &str, now: i64) -> (usize, usize) {
+        let mut history = self.history.lock().unwrap();
+        // Going through every author's history is only needed once per window
+        if now - self.pruned_at.load(Ordering::Relaxed) > SPAM_WINDOW {
+            history.retain(|_, h| h.back().is_some_and(|(t, _)| now - t <= SPAM_WINDOW));
+            self.pruned_at.store(now, Ordering::Relaxed);
+        }
+        let messages = history.entry((guild_id, user_id)).or_default();
+        while messages.front().is_some_and(|(t, _)| now - t > SPAM_WINDOW) {
+            messages.pop_front();
+        }
+        messages.push_back((now, content.to_string()));
+        let duplicates = messages.iter().filter(|(_, c)| c == content).count();
+        (messages.len(), duplicates)
+    }
+}
+
+// Event handlers ->
+
+/// Run a new message through the automod rules of its guild
+pub async fn on_message(ctx: &serenity::Context, data: &Data, message: &Message) -> Result<(), Error> {
+    let guild_id = match message.guild_id {
+        Some(g) => g,
+        None => return Ok(()),
+    };
+    if message.author.bot {
+        return Ok(());
+    }
+
+    // Moderators are exempt
+    let is_moderator = ctx.cache.guild(guild_id)
+        .and_then(|g| g.members.get(&message.author.id).map(|m| g.member_permissions(m)))
+        .is_some_and(|p| p.manage_messages());
+    if is_moderator {
+        return Ok(());
+    }
+
+    let rule_set = data.automod.rule_set(data, guild_id).await?;
+    if rule_set.is_empty() {
+        return Ok(());
+    }
+
+    let (recent_messages, duplicate_messages) = if rule_set.rules.contains_key(&Rule::Spam) {
+        data.automod.record_message(guild_id, message.author.id, &message.content, chrono::Utc::now().timestamp())
+    } else {
+        (0, 0)
+    };
+    let violations = rule_set.evaluate(&MessageInfo {
+        content: &message.content,
+        mentions: message.mentions.len() + message.mention_roles.len() + message.mention_everyone as usize,
+        recent_messages,
+        duplicate_messages,
+    });
+    let action = match violations.iter().map(|v| v.action).max() {
+        Some(a) => a,
+        None => return Ok(()),
+    };
+    let reasons = violations.iter()
+        .map(|v| format!("{}: {}", v.rule.name(), v.detail))
+        .collect::<Vec<String>>()
+        .join("\n");
+    info!("Automod {:?} on message ID {} by user ID {}", action, message.id, message.author.id);
+
+    if action >= Action::Delete {
+        if let Err(e) = message.delete(&ctx.http).await {
+            warn!("Automod failed to delete message ID {}: {}", message.id, e);
+        }
+    }
+    if action == Action::Warn {
+        // Only name the rules, the details would repeat what was just removed. They go to the log channel instead.
+        let rules = violations.iter().map(|v| v.rule.name()).collect::<Vec<&str>>();
+        message.channel_id.send_message(&ctx.http, CreateMessage::new()
+            .content(format!(
+                "{}, your message was removed by automod. Rules broken: {}",
+                message.author.mention(),
+                rules.join(", ")
+            ))
+            .allowed_mentions(CreateAllowedMentions::new().users([message.author.id]))
+        ).await?;
+    }
+    if action == Action::Timeout {
+        let minutes = data.database.get_guild_value(&guild_id, &"config.automod_timeout").await?
+            .and_then(|m| m.parse::<i64>().ok())
+            .unwrap_or(10);
+        let until = Timestamp::from_unix_timestamp(chrono::Utc::now().timestamp() + minutes * 60)?;
+        guild_id.edit_member(&ctx.http, message.author.id,
+            EditMember::new().disable_communication_until_datetime(until).audit_log_reason("Automod")
+        ).await?;
+    }
+
+    // Log the event
+    if let Some(log_channel) = data.database.get_guild_value(&guild_id, &"config.automod_log").await? {
+        if let Some(log_channel_id) = tools::to_channel(log_channel.as_str()) {
+            log_queue::send(&ctx.http, log_channel_id,
+                CreateEmbed::new()
+                    .title("🛡️ Automod")
+                    .description(format!("**Text:** {}", message.content))
+                    .field("Rules broken:", reasons, false)
+                    .field("Action:", action.name(), true)
+                    .field("Author:", message.author.mention().to_string(), true)
+                    .field("Channel:", message.channel_id.mention().to_string(), true)
+                    .color(Colour::ORANGE)
+                    .footer(CreateEmbedFooter::new(format!("Message ID: {}", message.id)))
+            );
+        }
+    }
+    Ok(())
+}
+
+// Commands ->
+
+/// Automatically moderate messages
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    subcommands(
+        "automod_enable",
+        "automod_disable",
+        "automod_filter",
+        "automod_log",
+        "automod_timeout",
+        "automod_status"
+    ),
+    subcommand_required
+)]
+pub async fn automod(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Enable a rule or change its settings
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "enable"
+)]
+pub async fn automod_enable(
+    ctx: Context<'_>,
+    #[description = "Rule to enable"]
+    rule: Rule,
+    #[description = "What to do with messages that break the rule"]
+    action: Action,
+    #[description = "Mentions per message, messages per 10 seconds, caps percentage or combining characters"]
+    #[min = 1]
+    threshold: Option<u32>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    ctx.data()
+        .database
+        .set_automod_rule(&guild_id, rule.key(), action.key(), threshold.map(i64::from))
+        .await?;
+    ctx.data().automod.invalidate(guild_id);
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Automod rule \"{}\" enabled with action \"{}\".", rule.name(), action.name()))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Disable a rule
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "disable"
+)]
+pub async fn automod_disable(
+    ctx: Context<'_>,
+    #[description = "Rule to disable"]
+    rule: Rule,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    ctx.data().database.delete_automod_rule(&guild_id, rule.key()).await?;
+    ctx.data().automod.invalidate(guild_id);
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Automod rule \"{}\" disabled.", rule.name()))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Manage banned words and regexes
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "filter",
+    subcommands("automod_filter_add", "automod_filter_remove", "automod_filter_list"),
+    subcommand_required
+)]
+pub async fn automod_filter(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Ban a word or regex
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "add"
+)]
+pub async fn automod_filter_add(
+    ctx: Context<'_>,
+    #[description = "Word or regular expression"]
+    pattern: String,
+    #[description = "Treat the pattern as a regular expression. Defaults to false"]
+    regex: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let regex = regex.unwrap_or(false);
+    if regex {
+        if let Err(e) = Regex::new(&pattern) {
+            ctx.send(
+                CreateReply::default()
+                    .content(format!("Invalid regular expression:\n```\n{}\n```", e))
+                    .ephemeral(true),
+            )
+            .await?;
+            return Ok(());
+        }
+    }
+    let added = ctx.data().database.add_automod_pattern(&guild_id, &pattern, regex).await?;
+    ctx.data().automod.invalidate(guild_id);
+    ctx.send(
+        CreateReply::default()
+            .content(if added { "Filter added." } else { "That filter already exists." }.to_string())
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Unban a word or regex
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "remove"
+)]
+pub async fn automod_filter_remove(
+    ctx: Context<'_>,
+    #[description = "Word or regular expression, exactly as it was added"]
+    pattern: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let removed = ctx.data().database.delete_automod_pattern(&guild_id, &pattern).await?;
+    ctx.data().automod.invalidate(guild_id);
+    ctx.send(
+        CreateReply::default()
+            .content(if removed { "Filter removed." } else { "That filter doesn't exist." }.to_string())
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Show all banned words and regexes
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "list"
+)]
+pub async fn automod_filter_list(ctx: Context<'_>) -> Result<(), Error> {
+    let patterns = ctx.data().database.get_automod_patterns(&ctx.guild_id().unwrap()).await?;
+    let text = if patterns.is_empty() {
+        String::from("No filters configured.")
+    } else {
+        patterns.iter()
+            .map(|(p, is_regex)| format!("- `{}`{}", p, if *is_regex { " (regex)" } else { "" }))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+    ctx.send(
+        CreateReply::default()
+            .embed(CreateEmbed::new().title("🛡️ Automod filters").description(text).color(Colour::BLITZ_BLUE))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Set the channel automod actions are logged to
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "log"
+)]
+pub async fn automod_log(
+    ctx: Context<'_>,
+    #[description = "Channel to log automod actions to. Leave empty to stop logging"]
+    channel: Option<ChannelId>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let text = match channel {
+        Some(channel) => {
+            if !commands::check_channel_writable(ctx, channel).await? {
+                return Ok(());
+            }
+            ctx.data().database.set_guild_value(&guild_id, &"config.automod_log", &channel).await?;
+            format!("Logging automod actions to {}", channel.mention())
+        }
+        None => {
+            ctx.data().database.delete_guild_value(&guild_id, &"config.automod_log").await?;
+            String::from("Automod logging disabled.")
+        }
+    };
+    ctx.send(CreateReply::default().content(text).ephemeral(true)).await?;
+    Ok(())
+}
+
+/// Set how long the timeout action lasts
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "timeout"
+)]
+pub async fn automod_timeout(
+    ctx: Context<'_>,
+    #[description = "Timeout duration in minutes"]
+    #[min = 1]
+    #[max = 40320]
+    minutes: u32,
+) -> Result<(), Error> {
+    ctx.data()
+        .database
+        .set_guild_value(&ctx.guild_id().unwrap(), &"config.automod_timeout", &minutes)
+        .await?;
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Automod timeouts now last {} minutes.", minutes))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Show the automod configuration
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "status"
+)]
+pub async fn automod_status(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let rule_set = ctx.data().automod.rule_set(ctx.data(), guild_id).await?;
+    let log_channel = ctx.data().database.get_guild_value(&guild_id, &"config.automod_log").await?;
+
+    let mut embed = CreateEmbed::new().title("🛡️ Automod").color(Colour::BLITZ_BLUE);
+    for rule in Rule::ALL {
+        let value = match rule_set.rules.get(&rule) {
+            None => String::from("Disabled"),
+            Some(c) if rule.default_threshold() == 0 => c.action.name().to_string(),
+            Some(c) => format!("{} (threshold {})", c.action.name(), c.threshold),
+        };
+        embed = embed.field(rule.name(), value, true);
+    }
+    embed = embed.field("Log channel:", match log_channel {
+        Some(c) => format!("<#{}>", c),
+        None => String::from("None"),
+    }, false);
+
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule_set(rules: &[(&str, &str, Option<i64>)], patterns: &[(&str, bool)]) -> RuleSet {
+        RuleSet::build(
+            rules.iter().map(|(r, a, t)| (r.to_string(), a.to_string(), *t)).collect(),
+            patterns.iter().map(|(p, r)| (p.to_string(), *r)).collect(),
+        )
+    }
+
+    fn message(content: &str) -> MessageInfo<'_> {
+        MessageInfo { content, mentions: 0, recent_messages: 1, duplicate_messages: 1 }
+    }
+
+    fn broken_rules(set: &RuleSet, message: &MessageInfo) -> Vec<Rule> {
+        set.evaluate(message).iter().map(|v| v.rule).collect()
+    }
+
+    #[test]
+    fn banned_words_and_regexes() {
+        let patterns = [("darn", false), ("c.d", false), (r"fr[e3]{2}\s*nitro", true)];
+        let set = rule_set(&[("words", "delete", None)], &patterns);
+        let cases = [
+            ("oh darn it", true),
+            ("DARN", true),
+            ("darning socks", false),
+            ("c.d", true),
+            ("cxd", false),
+            ("get fr33 nitro here", true),
+            ("free nitro", true),
+            ("nothing to see", false),
+        ];
+        for (content, broken) in cases {
+            assert_eq!(broken_rules(&set, &message(content)) == [Rule::Words], broken, "{}", content);
+        }
+    }
+
+    #[test]
+    fn invalid_rows_are_skipped() {
+        let rules = [("words", "delete", None), ("nope", "delete", None), ("caps", "explode", None)];
+        let set = rule_set(&rules, &[("(", true)]);
+        assert_eq!(set.rules.keys().collect::<Vec<&Rule>>(), [&Rule::Words]);
+        assert!(set.evaluate(&message("((((")).is_empty());
+    }
+
+    #[test]
+    fn invites() {
+        let set = rule_set(&[("invites", "warn", None)], &[]);
+        let cases = [
+            ("join discord.gg/abc123", true),
+            ("https://discord.com/invite/Abc-1", true),
+            ("https://DISCORDAPP.COM/invite/x", true),
+            ("discord.com/channels/1/2", false),
+            ("discord gg", false),
+        ];
+        for (content, broken) in cases {
+            assert_eq!(broken_rules(&set, &message(content)) == [Rule::Invites], broken, "{}", content);
+        }
+    }
+
+    #[test]
+    fn mass_mentions() {
+        let set = rule_set(&[("mentions", "timeout", Some(3))], &[]);
+        for (mentions, broken) in [(0, false), (2, false), (3, true), (10, true)] {
+            let info = MessageInfo { mentions, ..message("hi") };
+            assert_eq!(broken_rules(&set, &info) == [Rule::Mentions], broken, "{} mentions", mentions);
+        }
+    }
+
+    #[test]
+    fn caps() {
+        assert_eq!(caps_percentage("SHORT"), None);
+        assert_eq!(caps_percentage("ABCDEFGHIJ"), Some(100));
+        assert_eq!(caps_percentage("ABCDEfghij 123 !!"), Some(50));
+        assert_eq!(caps_percentage("abcdefghij"), Some(0));
+
+        let set = rule_set(&[("caps", "delete", None)], &[]);
+        let cases = [
+            ("STOP SHOUTING AT ME", true),
+            ("HELLO THERE FRIEND ok", true),
+            ("Hello There Friend", false),
+            ("LOL OK", false),
+        ];
+        for (content, broken) in cases {
+            assert_eq!(broken_rules(&set, &message(content)) == [Rule::Caps], broken, "{}", content);
+        }
+        let strict = rule_set(&[("caps", "delete", Some(15))], &[]);
+        assert_eq!(broken_rules(&strict, &message("Hello There Friend")), [Rule::Caps]);
+    }
+
+    #[test]
+    fn zalgo() {
+        let zalgo = "h\u{0315}\u{0321}e\u{0336}\u{0358}l\u{0322}\u{0327}l\u{0300}o\u{1DC0}\u{20D0}";
+        assert_eq!(zalgo_count("hello"), 0);
+        assert_eq!(zalgo_count("café"), 0);
+        assert_eq!(zalgo_count("cafe\u{0301}"), 1);
+        assert_eq!(zalgo_count(zalgo), 9);
+
+        let set = rule_set(&[("zalgo", "delete", Some(5))], &[]);
+        assert_eq!(broken_rules(&set, &message(zalgo)), [Rule::Zalgo]);
+        assert!(set.evaluate(&message("cafe\u{0301}")).is_empty());
+    }
+
+    #[test]
+    fn zero_threshold_uses_default() {
+        let set = rule_set(&[("mentions", "log", Some(0))], &[]);
+        assert_eq!(set.rules[&Rule::Mentions].threshold, Rule::Mentions.default_threshold());
+        assert!(set.evaluate(&message("hi")).is_empty());
+    }
+
+    #[test]
+    fn spam_and_duplicates() {
+        let state = AutomodState::default();
+        let set = rule_set(&[("spam", "timeout", Some(4))], &[]);
+        let (guild, user, other) = (GuildId::new(1), UserId::new(2), UserId::new(3));
+        let send = |user, content: &str, now| {
+            let (recent_messages, duplicate_messages) = state.record_message(guild, user, content, now);
+            let info = MessageInfo { content, mentions: 0, recent_messages, duplicate_messages };
+            broken_rules(&set, &info) == [Rule::Spam]
+        };
+
+        assert!(!send(user, "a", 0));
+        assert!(!send(user, "a", 1));
+        assert!(send(user, "a", 2), "third duplicate");
+        // Someone else's messages don't count
+        assert!(!send(other, "a", 2));
+        // A window later the history is forgotten
+        assert!(!send(user, "a", 2 + SPAM_WINDOW + 1));
+
+        let start = 100;
+        for (i, content) in ["1", "2", "3"].iter().enumerate() {
+            assert!(!send(user, content, start + i as i64));
+        }
+        assert!(send(user, "4", start + 3), "fourth message within the window");
+        assert!(!send(user, "5", start + 3 + SPAM_WINDOW + 1));
+    }
+
+    #[test]
+    fn quiet_authors_are_forgotten() {
+        let state = AutomodState::default();
+        let guild = GuildId::new(1);
+        for user in 1..=5 {
+            state.record_message(guild, UserId::new(user), "hi", 0);
+        }
+        state.record_message(guild, UserId::new(6), "hi", SPAM_WINDOW * 2);
+        assert_eq!(state.history.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,324 @@
+use std::time::Duration;
+use log::{info, warn};
+use poise::serenity_prelude::{
+    Colour, CreateEmbed, GuildId, GuildMemberUpdateEvent, Http, Member, Mentionable, Role, RoleId, UserId,
+};
+use poise::CreateReply;
+use crate::database::Database;
+use crate::{serenity, shutdown, tools};
+use crate::{Context, Data, Error};
+
+/// How often to check for delayed assignments that are due
+const ASSIGN_INTERVAL: Duration = Duration::from_secs(30);
+/// Member value holding when a delayed assignment is due, as a unix timestamp
+const DUE_KEY: &str = "autorole.due";
+
+/// Parse a comma separated list of role IDs as stored in the database
+fn parse_roles(value: &str) -> Vec<RoleId> {
+    value.split(',').filter_map(tools::to_role).collect()
+}
+
+/// Format a list of role IDs for storage in the database
+fn join_roles(roles: &[RoleId]) -> String {
+    roles.iter().map(|r| r.to_string()).collect::<Vec<String>>().join(",")
+}
+
+async fn configured_roles(database: &Database, guild_id: GuildId) -> Result<Vec<RoleId>, Error> {
+    Ok(match database.get_guild_value(&guild_id, &"config.autorole_roles").await? {
+        Some(v) => parse_roles(&v),
+        None => vec![],
+    })
+}
+
+async fn is_screening_enabled(data: &Data, guild_id: GuildId) -> Result<bool, Error> {
+    Ok(data.database.get_guild_value(&guild_id, &"config.autorole_screening").await?.is_some())
+}
+
+/// Give a member the configured roles. Returns how many of them were assigned.
+async fn assign_roles(http: &Http, database: &Database, guild_id: GuildId, user_id: UserId) -> Result<usize, Error> {
+    let roles = configured_roles(database, guild_id).await?;
+    let mut assigned = 0;
+    for role in &roles {
+        // Fails if the member left in the meantime or the role is above ours, neither is fatal
+        match http.add_member_role(guild_id, user_id, *role, Some("Auto-role")).await {
+            Ok(()) => assigned += 1,
+            Err(e) => {
+                warn!("Failed to assign auto-role {} to user ID {} in guild ID {}: {}", role, user_id, guild_id, e)
+            }
+        }
+    }
+    if assigned > 0 {
+        info!("Assigned {}/{} auto-roles to user ID {} in guild ID {}", assigned, roles.len(), user_id, guild_id);
+    }
+    Ok(assigned)
+}
+
+/// Give a member the configured roles now, or remember when to give them if there's a delay.
+///
+/// Delayed assignments are stored in the database so they survive restarts, `assign_due_roles` picks them up.
+async fn schedule_roles(http: &Http, data: &Data, guild_id: GuildId, user_id: UserId) -> Result<(), Error> {
+    if configured_roles(&data.database, guild_id).await?.is_empty() {
+        return Ok(());
+    }
+    let delay = data.database.get_guild_value(&guild_id, &"config.autorole_delay").await?
+        .and_then(|d| d.parse::<i64>().ok())
+        .unwrap_or(0);
+
+    if delay > 0 {
+        let due = chrono::Utc::now().timestamp() + delay * 60;
+        data.database.set_member_value(&guild_id, &user_id, &DUE_KEY, &due).await?;
+    } else {
+        assign_roles(http, &data.database, guild_id, user_id).await?;
+    }
+    Ok(())
+}
+
+/// Give the roles of delayed assignments once they're due, forever
+pub async fn assign_due_roles(ctx: serenity::Context, database: Database) {
+    let mut interval = tokio::time::interval(ASSIGN_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown::requested() => break,
+        }
+        let pending = match database.get_members_with_value(&DUE_KEY).await {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("Failed to get pending auto-role assignments: {}", e);
+                continue;
+            }
+        };
+        let now = chrono::Utc::now().timestamp();
+        for (guild_id, user_id, due) in pending {
+            if due.parse::<i64>().is_ok_and(|d| d > now) {
+                continue;
+            }
+            // Forget the assignment either way, so a member who left doesn't get retried forever
+            if let Err(e) = database.delete_member_value(&guild_id, &user_id, &DUE_KEY).await {
+                warn!(
+                    "Failed to delete pending auto-role assignment of user ID {} in guild ID {}: {}",
+                    user_id, guild_id, e
+                );
+                continue;
+            }
+            let (Some(guild_id), Some(user_id)) = (
+                tools::to_snowflake(&guild_id).map(GuildId::from),
+                tools::to_snowflake(&user_id).map(UserId::from),
+            ) else {
+                continue;
+            };
+            if let Err(e) = assign_roles(&ctx.http, &database, guild_id, user_id).await {
+                warn!("Failed to assign auto-roles to user ID {} in guild ID {}: {}", user_id, guild_id, e);
+            }
+        }
+    }
+}
+
+// Event handlers ->
+
+/// Assign roles to a new member, unless we have to wait for them to pass membership screening
+pub async fn on_member_join(ctx: &serenity::Context, data: &Data, member: &Member) -> Result<(), Error> {
+    if member.pending && is_screening_enabled(data, member.guild_id).await? {
+        return Ok(()); // on_member_update will pick it up later
+    }
+    schedule_roles(&ctx.http, data, member.guild_id, member.user.id).await
+}
+
+/// Assign roles to a member who just passed membership screening.
+///
+/// Only works if the member was cached before the update, otherwise we can't tell it was a screening pass.
+pub async fn on_member_update(
+    ctx: &serenity::Context,
+    data: &Data,
+    old: &Option<Member>,
+    event: &GuildMemberUpdateEvent,
+) -> Result<(), Error> {
+    let passed_screening = match old {
+        Some(o) => o.pending && !event.pending,
+        None => false,
+    };
+    if !passed_screening || !is_screening_enabled(data, event.guild_id).await? {
+        return Ok(());
+    }
+    schedule_roles(&ctx.http, data, event.guild_id, event.user.id).await
+}
+
+// Commands ->
+
+/// Automatically give roles to new members
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    subcommands("autorole_add", "autorole_remove", "autorole_list", "autorole_delay", "autorole_screening"),
+    subcommand_required
+)]
+pub async fn autorole(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Give a role to every new member
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "add"
+)]
+pub async fn autorole_add(
+    ctx: Context<'_>,
+    #[description = "Role to give to new members"]
+    role: Role,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    if role.managed || role.id.get() == guild_id.get() {
+        ctx.send(
+            CreateReply::default()
+                .content("That role can't be assigned by a bot.".to_string())
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let mut roles = configured_roles(&ctx.data().database, guild_id).await?;
+    if !roles.contains(&role.id) {
+        roles.push(role.id);
+        ctx.data()
+            .database
+            .set_guild_value(&guild_id, &"config.autorole_roles", &join_roles(&roles))
+            .await?;
+    }
+    ctx.send(
+        CreateReply::default()
+            .content(format!("New members will receive {}", role.mention()))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Stop giving a role to new members
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "remove"
+)]
+pub async fn autorole_remove(
+    ctx: Context<'_>,
+    #[description = "Role to stop giving to new members"]
+    role: Role,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let mut roles = configured_roles(&ctx.data().database, guild_id).await?;
+    roles.retain(|r| *r != role.id);
+    if roles.is_empty() {
+        ctx.data().database.delete_guild_value(&guild_id, &"config.autorole_roles").await?;
+    } else {
+        ctx.data()
+            .database
+            .set_guild_value(&guild_id, &"config.autorole_roles", &join_roles(&roles))
+            .await?;
+    }
+    ctx.send(
+        CreateReply::default()
+            .content(format!("New members will no longer receive {}", role.mention()))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Show the auto-role configuration
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "list"
+)]
+pub async fn autorole_list(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let roles = configured_roles(&ctx.data().database, guild_id).await?;
+    let delay = ctx.data().database.get_guild_value(&guild_id, &"config.autorole_delay").await?
+        .unwrap_or("0".to_string());
+    let screening = is_screening_enabled(ctx.data(), guild_id).await?;
+
+    let role_list = if roles.is_empty() {
+        String::from("None")
+    } else {
+        roles.iter().map(|r| r.mention().to_string()).collect::<Vec<String>>().join("\n")
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title("🏷️ Auto-roles")
+                    .field("Roles:", role_list, false)
+                    .field("Delay:", format!("{} minutes", delay), true)
+                    .field("Wait for screening:", if screening { "Yes" } else { "No" }, true)
+                    .color(Colour::BLITZ_BLUE),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Wait some time before giving new members their roles
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "delay"
+)]
+pub async fn autorole_delay(
+    ctx: Context<'_>,
+    #[description = "Minutes to wait after joining. 0 disables the delay"]
+    #[max = 1440]
+    minutes: u32,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    if minutes == 0 {
+        ctx.data().database.delete_guild_value(&guild_id, &"config.autorole_delay").await?;
+    } else {
+        ctx.data().database.set_guild_value(&guild_id, &"config.autorole_delay", &minutes).await?;
+    }
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Auto-role delay set to {} minutes.", minutes))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Only give roles once new members have passed membership screening
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "screening"
+)]
+pub async fn autorole_screening(
+    ctx: Context<'_>,
+    #[description = "Wait until the member accepted the server rules"]
+    enabled: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    if enabled {
+        ctx.data().database.set_guild_value(&guild_id, &"config.autorole_screening", &"").await?;
+    } else {
+        ctx.data().database.delete_guild_value(&guild_id, &"config.autorole_screening").await?;
+    }
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Auto-roles will {}wait for membership screening.",
+                if enabled { "" } else { "no longer " }
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
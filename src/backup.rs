@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::{error, info, warn};
+use poise::CreateReply;
+use crate::database::{Backend, Database};
+use crate::shutdown;
+use crate::{Context, Error};
+
+/// Backups are named after the time they were made, e.g. `backup-20240131-235959.sqlite`
+const PREFIX: &str = "backup-";
+const EXTENSION: &str = ".sqlite";
+/// Files SQLite keeps next to the database, which hold changes that aren't in the database file yet
+const JOURNAL_SUFFIXES: [&str; 3] = ["-journal", "-wal", "-shm"];
+
+#[derive(Clone)]
+pub struct BackupSettings {
+    pub dir: PathBuf,
+    /// Time between scheduled backups. Zero disables them.
+    pub interval: Duration,
+    /// How many backups to keep, the oldest ones are deleted first
+    pub retention: usize,
+}
+
+/// Back up the database while it's in use, then delete the backups that exceed the retention
+pub async fn create(database: &Database, settings: &BackupSettings) -> Result<PathBuf, Error> {
+    std::fs::create_dir_all(&settings.dir)
+        .map_err(|e| format!("Can't create backup directory {}: {}", settings.dir.display(), e))?;
+    let name = format!("{}{}{}", PREFIX, chrono::Utc::now().format("%Y%m%d-%H%M%S"), EXTENSION);
+    let path = settings.dir.join(name);
+    // Write under a temporary name first, so a failed backup never looks like a complete one
+    let partial = path.with_extension("partial");
+    let _ = std::fs::remove_file(&partial);
+    if let Err(e) = database.backup_to(&partial.to_string_lossy()).await {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e.into());
+    }
+    std::fs::rename(&partial, &path)?;
+    info!("Backed up the database to {}", path.display());
+
+    let removed = remove_old_backups(settings)?;
+    if removed > 0 {
+        info!("Removed {} old backup(s)", removed);
+    }
+    Ok(path)
+}
+
+fn remove_old_backups(settings: &BackupSettings) -> std::io::Result<usize> {
+    let mut backups = std::fs::read_dir(&settings.dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_str().is_some_and(|n| n.starts_with(PREFIX) && n.ends_with(EXTENSION)))
+        .map(|e| e.path())
+        .collect::<Vec<PathBuf>>();
+    // The timestamp in the name sorts chronologically
+    backups.sort();
+    let excess = backups.len().saturating_sub(settings.retention.max(1));
+    for old in &backups[..excess] {
+        std::fs::remove_file(old)?;
+    }
+    Ok(excess)
+}
+
+/// Back up the database on a schedule, until the bot shuts down
+pub async fn run_scheduled(database: Database, settings: BackupSettings) {
+    if settings.interval.is_zero() {
+        return;
+    }
+    if database.backend() != Backend::Sqlite {
+        info!("Scheduled backups are off, back up PostgreSQL databases with pg_dump");
+        return;
+    }
+    let mut interval = tokio::time::interval(settings.interval);
+    interval.tick().await; // The first tick completes immediately, and a fresh start doesn't need a backup
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown::requested() => break,
+        }
+        if let Err(e) = create(&database, &settings).await {
+            error!("Scheduled backup failed: {}", e);
+        }
+    }
+}
+
+/// Replace the database file with a backup, after checking that the backup is usable.
+/// The current database is kept next to it. Only run this while the bot is stopped.
+pub async fn restore(db_path: &str, backup_path: &str) -> Result<(), String> {
+    if Backend::of(db_path) != Backend::Sqlite {
+        return Err(String::from("Only SQLite databases can be restored, use pg_restore for PostgreSQL"));
+    }
+    if !Path::new(backup_path).is_file() {
+        return Err(format!("Backup {} doesn't exist", backup_path));
+    }
+    let problems = Database::check_file(backup_path)
+        .await
+        .map_err(|e| format!("Backup {} can't be read as a database: {}", backup_path, e))?;
+    if !problems.is_empty() {
+        return Err(format!("Backup {} is damaged:\n{}", backup_path, problems.join("\n")));
+    }
+    info!("Backup {} is valid", backup_path);
+
+    if Path::new(db_path).exists() {
+        let kept = format!("{}.before-restore-{}", db_path, chrono::Utc::now().format("%Y%m%d-%H%M%S"));
+        std::fs::copy(db_path, &kept).map_err(|e| format!("Can't keep a copy of the current database: {}", e))?;
+        // Recent changes may only be in the journals yet, SQLite applies them when the copy is opened
+        for suffix in JOURNAL_SUFFIXES {
+            let journal = format!("{}{}", db_path, suffix);
+            if Path::new(&journal).exists() {
+                std::fs::copy(&journal, format!("{}{}", kept, suffix))
+                    .map_err(|e| format!("Can't keep a copy of {}: {}", journal, e))?;
+            }
+        }
+        info!("Kept the current database as {}", kept);
+    }
+
+    // Copy next to the database first, so the swap itself is a rename that can't leave half a file behind
+    let incoming = format!("{}.restoring", db_path);
+    std::fs::copy(backup_path, &incoming).map_err(|e| format!("Can't copy the backup: {}", e))?;
+    std::fs::rename(&incoming, db_path).map_err(|e| format!("Can't replace the database: {}", e))?;
+    // Journals left over from the old database would be applied to the restored one
+    for suffix in JOURNAL_SUFFIXES {
+        let journal = format!("{}{}", db_path, suffix);
+        if Path::new(&journal).exists() {
+            match std::fs::remove_file(&journal) {
+                Ok(()) => warn!("Removed leftover {}", journal),
+                Err(e) => return Err(format!("Can't remove leftover {}: {}", journal, e)),
+            }
+        }
+    }
+    info!("Restored {} from {}", db_path, backup_path);
+    Ok(())
+}
+
+// Commands ->
+
+/// Back up the database now
+#[poise::command(
+    slash_command,
+    owners_only,
+    hide_in_help,
+    default_member_permissions = "ADMINISTRATOR"
+)]
+pub async fn backup(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+    let path = create(&ctx.data().database, &ctx.data().backups).await?;
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Backed up the database to `{}`.", path.display()))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
@@ -1,13 +1,20 @@
+use crate::{fortunes, metrics, shutdown, tools};
 use crate::{Context, Error};
 use chrono::Local;
 use log::debug;
-use poise::serenity_prelude::{Colour, CreateEmbed, CreateEmbedFooter, Mentionable};
+use poise::serenity_prelude::{ChannelId, Colour, CreateEmbed, CreateEmbedFooter, Mentionable};
 use poise::CreateReply;
 use rand::distr::{Distribution, Uniform};
 use rand::rng;
+
+/// How many fortunes `/fortune history` shows
+const FORTUNE_HISTORY_SIZE: i64 = 10;
+
 // Hooks ->
 
 pub fn pre_command(ctx: Context<'_>) {
+    metrics::command_started(&ctx.command().qualified_name);
+    shutdown::command_started(ctx.id());
     debug!(
         "Executing command \"{}\" ID {} for {}",
         ctx.command().qualified_name,
@@ -17,6 +24,8 @@ pub fn pre_command(ctx: Context<'_>) {
 }
 
 pub fn post_command(ctx: Context<'_>) {
+    metrics::command_completed(&ctx.command().qualified_name);
+    shutdown::command_finished(ctx.id());
     debug!(
         "Executed command \"{}\" ID {}",
         ctx.command().qualified_name,
@@ -24,12 +33,52 @@ pub fn post_command(ctx: Context<'_>) {
     );
 }
 
-pub fn command_check(_: Context<'_>) -> Result<bool, Error> {
+pub async fn command_check(ctx: Context<'_>) -> Result<bool, Error> {
+    if shutdown::is_requested() {
+        ctx.send(CreateReply::default()
+            .content("The bot is restarting, try again in a minute.")
+            .ephemeral(true)
+        ).await?;
+        return Ok(false);
+    }
     // todo implement configurable bot-muted role that disables commands entirely for a user who has it
     // Ok(false) // Command does not get to run
     Ok(true) // Command gets to run
 }
 
+// Helpers ->
+
+/// Check whether the bot can post in a channel of the current guild.
+///
+/// Replies with an ephemeral explanation and returns false if it can't.
+pub async fn check_channel_writable(ctx: Context<'_>, channel: ChannelId) -> Result<bool, Error> {
+    let problem = {
+        let guild = ctx.guild().unwrap().clone();
+        match guild.channels.get(&channel) {
+            None => Some("Channel not found."),
+            Some(c) => {
+                #[allow(deprecated)]
+                let permissions = c.permissions_for_user(ctx.cache(), ctx.cache().current_user().id)?;
+                if permissions.send_messages() {
+                    None
+                } else {
+                    Some("I do not have permission to send messages to that channel.")
+                }
+            }
+        }
+    };
+    if let Some(text) = problem {
+        ctx.send(
+            CreateReply::default()
+                .content(text.to_string())
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
 // Commands ->
 
 /* todo
@@ -77,33 +126,12 @@ pub async fn trackjoinleaves_enable(
     #[description = "Channel to log user join/leave events to"]
     channel: poise::serenity_prelude::ChannelId,
 ) -> Result<(), Error> {
-    let guild = ctx.guild().unwrap().clone();
-    let _channel = guild.channels.get(&channel);
-    if guild.channels.get(&channel).is_none() {
-        ctx.send(
-            CreateReply::default()
-                .content("Channel not found.".to_string())
-                .ephemeral(true),
-        )
-        .await?;
-        return Ok(());
-    } else if _channel
-        .unwrap()
-        .permissions_for_user(ctx.cache(), ctx.cache().current_user().id)?
-        .send_messages()
-        == false
-    {
-        ctx.send(
-            CreateReply::default()
-                .content("I do not have permission to send messages to that channel.".to_string())
-                .ephemeral(true),
-        )
-        .await?;
+    if !check_channel_writable(ctx, channel).await? {
         return Ok(());
     }
     ctx.data()
         .database
-        .set_guild_value(
+        .set_guild_value_typed(
             &ctx.guild_id().unwrap(),
             &"config.track_joinleaves",
             &channel,
@@ -166,33 +194,12 @@ pub async fn trackmessageedits_enable(
     #[description = "Channel to log message edits and deletions to"]
     channel: poise::serenity_prelude::ChannelId,
 ) -> Result<(), Error> {
-    let guild = ctx.guild().unwrap().clone();
-    let _channel = guild.channels.get(&channel);
-    if _channel.is_none() {
-        ctx.send(
-            CreateReply::default()
-                .content("Channel not found.".to_string())
-                .ephemeral(true),
-        )
-        .await?;
-        return Ok(());
-    } else if _channel
-        .unwrap()
-        .permissions_for_user(ctx.cache(), ctx.cache().current_user().id)?
-        .send_messages()
-        == false
-    {
-        ctx.send(
-            CreateReply::default()
-                .content("I do not have permission to send messages to that channel.".to_string())
-                .ephemeral(true),
-        )
-        .await?;
+    if !check_channel_writable(ctx, channel).await? {
         return Ok(());
     }
     ctx.data()
         .database
-        .set_guild_value(
+        .set_guild_value_typed(
             &ctx.guild_id().unwrap(),
             &"config.track_msg_edits",
             &channel,
@@ -240,14 +247,13 @@ pub async fn ping(ctx: Context<'_>) -> Result<(), Error> {
     let latency_gateway = ctx.ping().await.as_millis() / 2; // Ping is both ways
     let latency_e2e = now.timestamp_millis() - ctx.created_at().timestamp_millis();
 
-    let colour;
-    if latency_e2e <= 150 {
-        colour = Colour::DARK_GREEN;
+    let colour = if latency_e2e <= 150 {
+        Colour::DARK_GREEN
     } else if latency_e2e <= 800 {
-        colour = Colour::GOLD;
+        Colour::GOLD
     } else {
-        colour = Colour::DARK_RED;
-    }
+        Colour::DARK_RED
+    };
 
     ctx.send(
         CreateReply::default().embed(
@@ -570,9 +576,20 @@ pub async fn yesno(
     Ok(())
 }
 
+/// Fortune cookies
+#[poise::command(
+    slash_command,
+    default_member_permissions = "SEND_MESSAGES",
+    subcommands("fortune_open", "fortune_history"),
+    subcommand_required
+)]
+pub async fn fortune(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
 /// Receive a fortune cookie
-#[poise::command(slash_command, default_member_permissions = "SEND_MESSAGES")]
-pub async fn fortune(
+#[poise::command(slash_command, default_member_permissions = "SEND_MESSAGES", rename = "open")]
+pub async fn fortune_open(
     ctx: Context<'_>,
     #[description = "Don't show the result to anyone else"] hide: Option<bool>,
 ) -> Result<(), Error> {
@@ -586,26 +603,22 @@ pub async fn fortune(
         ctx.defer().await?;
     }
 
-    // Check if the user is in cooldown
-    let fortune_cooldown = ctx.data().fortune_cooldown;
+    // Check if the user is in cooldown. The fortune of the day doesn't change anyway, so it has none.
+    let daily = fortunes::is_daily_mode(ctx.data(), ctx.guild_id()).await?;
+    let fortune_cooldown = if daily { 0 } else { ctx.data().fortune_cooldown };
     let previous = ctx
         .data()
         .database
         .get_user_value(&ctx.author().id, "fortune_last")
-        .await
-        .unwrap();
-    let previous_time = ctx
+        .await?;
+    let previous_time: Option<i64> = ctx
         .data()
         .database
-        .get_user_value(&ctx.author().id, "fortune_last_time")
-        .await
-        .unwrap();
+        .get_user_value_typed(&ctx.author().id, "fortune_last_time")
+        .await?;
     let current_time = chrono::Utc::now().timestamp();
 
-    if previous_time.is_some() {
-        // Convert previous_time to i64 (unix timestamp)
-        let last_time = previous_time.unwrap().parse::<i64>().unwrap_or(0);
-
+    if let Some(last_time) = previous_time {
         // Check if the user is still in cooldown
         if current_time - last_time < fortune_cooldown {
             // Calculate remaining time
@@ -618,11 +631,11 @@ pub async fn fortune(
                 "You must wait **{}h {}m {}s** before receiving another fortune.",
                 hours, minutes, seconds
             );
-            if previous.is_some() {
+            if let Some(previous) = previous {
                 text = format!(
                     "{}\nYour previous fortune was:\n> {}",
                     text,
-                    previous.unwrap()
+                    previous
                 );
             }
 
@@ -641,44 +654,35 @@ pub async fn fortune(
         }
     }
 
-    // Generate fortune
-    // Original fortune.json source: https://github.com/Supinic/supibot/blob/master/commands/cookie/fortune-cookies.json
-    let fortune = {
-        // Read the fortune.json file
-        let fortune_path = std::path::Path::new("fortune.json");
-        let fortune_file = std::fs::File::open(fortune_path).expect("Failed to open fortune.json");
-        let fortunes: Vec<serde_json::Value> =
-            serde_json::from_reader(fortune_file).expect("Failed to parse fortune.json");
-
-        // Generate a random index
-        let mut generator = rng();
-        let range = Uniform::try_from(0..fortunes.len()).unwrap();
-        let index = range.sample(&mut generator);
-
-        // Extract the fortune text
-        fortunes[index].as_str().unwrap().to_string()
+    // Pick a fortune from the packs enabled in this guild
+    let fortune = match fortunes::draw(ctx.data(), ctx.guild_id(), ctx.author().id).await? {
+        Some(f) => {
+            debug!("Picked fortune {}", f.id);
+            f.text
+        }
+        None => {
+            ctx.send(
+                CreateReply::default()
+                    .content("There are no fortunes to hand out. An admin can add some with `/fortunes`.")
+                    .ephemeral(true),
+            )
+            .await?;
+            return Ok(());
+        }
     };
 
-    // Store current time and fortune in the database
-    ctx.data()
-        .database
-        .set_user_value(&ctx.author().id, "fortune_last", &fortune)
-        .await?;
-    ctx.data()
-        .database
-        .set_user_value(
-            &ctx.author().id,
-            "fortune_last_time",
-            &current_time.to_string(),
-        )
-        .await?;
+    // Store current time and fortune in the database, together so the cooldown never shows another fortune
+    let mut transaction = ctx.data().database.begin().await?;
+    transaction.set_user_value(&ctx.author().id, "fortune_last", &fortune).await?;
+    transaction.set_user_value_typed(&ctx.author().id, "fortune_last_time", &current_time).await?;
+    transaction.commit().await?;
 
     // Send the fortune to the user
     ctx.send(
         CreateReply::default()
             .embed(
                 CreateEmbed::new()
-                    .title("🥠 Your Fortune")
+                    .title(if daily { "🥠 Your Fortune of the Day" } else { "🥠 Your Fortune" })
                     .description(fortune)
                     .color(Colour::GOLD),
             )
@@ -688,6 +692,36 @@ pub async fn fortune(
     Ok(())
 }
 
+/// Show the fortunes you received recently
+#[poise::command(slash_command, default_member_permissions = "SEND_MESSAGES", rename = "history")]
+pub async fn fortune_history(ctx: Context<'_>) -> Result<(), Error> {
+    let history = ctx.data()
+        .database
+        .get_fortune_history(&ctx.author().id, FORTUNE_HISTORY_SIZE)
+        .await?;
+    let text = if history.is_empty() {
+        String::from("You haven't received any fortunes yet. Open one with `/fortune open`.")
+    } else {
+        history.iter()
+            .map(|(text, time)| format!("<t:{}:R>\n> {}", time, tools::truncate(text, 300)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title("🥠 Your recent fortunes")
+                    .description(text)
+                    .color(Colour::GOLD),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
 /// Reset a user's fortune cooldown
 #[poise::command(slash_command, default_member_permissions = "ADMINISTRATOR")]
 pub async fn fortune_reset(
@@ -696,7 +730,7 @@ pub async fn fortune_reset(
 ) -> Result<(), Error> {
     ctx.data()
         .database
-        .set_user_value(&user, &"fortune_last_time", &"0")
+        .set_user_value_typed(&user, &"fortune_last_time", &0)
         .await?;
     ctx.send(
         CreateReply::default()
@@ -1,37 +1,350 @@
 #![allow(unused)] // Although some features are unused, I prefer to keep the module feature-complete.
 
+use std::collections::BTreeMap;
 use std::fmt::Display;
+use serde::{de::DeserializeOwned, Serialize};
 use sqlx::{
-    sqlite::{SqlitePool, SqlitePoolOptions},
-    Error as SqlxError, Row,
+    any::{AnyArguments, AnyExecutor, AnyPoolOptions, AnyRow},
+    query::Query,
+    sqlite::{SqliteConnectOptions, SqliteConnection},
+    Any, AnyPool, AssertSqlSafe, Connection, Error as SqlxError, Row, Transaction,
 };
 use std::sync::Arc;
 use log::{debug, info, warn};
+use crate::{metrics, tools};
 
 /// Database connection pool wrapper for key-value storage
+#[derive(Clone)]
 pub struct Database {
-    pool: Arc<SqlitePool>,
+    pool: Arc<AnyPool>,
+    backend: Backend,
+}
+
+/// Why a typed key-value operation failed
+#[derive(Debug)]
+pub enum KvError {
+    Database(SqlxError),
+    /// The stored value isn't JSON of the expected type
+    Corrupt { key: String, value: String, error: serde_json::Error },
+    /// The value can't be written as JSON
+    Unserializable { key: String, error: serde_json::Error },
+}
+
+impl Display for KvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KvError::Database(e) => write!(f, "{}", e),
+            KvError::Corrupt { key, value, error } => {
+                write!(f, "Stored value of {} is corrupt: {} (value: {:?})", key, error, tools::truncate(value, 100))
+            }
+            KvError::Unserializable { key, error } => write!(f, "Can't store value of {}: {}", key, error),
+        }
+    }
+}
+
+impl std::error::Error for KvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KvError::Database(e) => Some(e),
+            KvError::Corrupt { error, .. } | KvError::Unserializable { error, .. } => Some(error),
+        }
+    }
+}
+
+impl From<SqlxError> for KvError {
+    fn from(e: SqlxError) -> Self {
+        KvError::Database(e)
+    }
+}
+
+/// The kind of database behind the pool. Queries are written to work on both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    /// PostgreSQL is used for `postgres://` URLs, anything else is the path of an SQLite file
+    pub fn of(location: &str) -> Self {
+        if location.starts_with("postgres://") || location.starts_with("postgresql://") {
+            Backend::Postgres
+        } else {
+            Backend::Sqlite
+        }
+    }
+
+    /// Translate a `CREATE TABLE` statement written for SQLite
+    fn ddl(self, sql: &'static str) -> AssertSqlSafe<String> {
+        AssertSqlSafe(match self {
+            Backend::Sqlite => sql.to_string(),
+            Backend::Postgres => sql
+                .replace("INTEGER PRIMARY KEY AUTOINCREMENT", "BIGSERIAL PRIMARY KEY")
+                .replace("INTEGER", "BIGINT")
+                .replace("TIMESTAMP DEFAULT", "TEXT DEFAULT"),
+        })
+    }
+}
+
+/// Copy an SQLite database file into an empty PostgreSQL database. Only run this while the bot is stopped.
+pub async fn migrate_from_sqlite(sqlite_path: &str, url: &str) -> Result<(), String> {
+    if Backend::of(url) != Backend::Postgres {
+        return Err(format!("{} isn't a PostgreSQL URL", redact_location(url)));
+    }
+    if !std::path::Path::new(sqlite_path).is_file() {
+        return Err(format!("SQLite database {} doesn't exist", sqlite_path));
+    }
+    let source = Database::new(sqlite_path)
+        .await
+        .map_err(|e| format!("Can't open SQLite database {}: {}", sqlite_path, e))?;
+    let target = Database::new(url)
+        .await
+        .map_err(|e| format!("Can't open PostgreSQL database: {}", e))?;
+    let copied = target.copy_from(&source).await;
+    source.close().await;
+    target.close().await;
+    let copied = copied.map_err(|e| format!("Copying failed, nothing was copied: {}", e))?;
+    info!(
+        "Copied {} row(s) from {}: {}",
+        copied.iter().map(|(_, rows)| rows).sum::<usize>(),
+        sqlite_path,
+        copied.iter().map(|(table, rows)| format!("{} {}", table, rows)).collect::<Vec<String>>().join(", ")
+    );
+    Ok(())
+}
+
+/// Every table, in the order they are created in
+const TABLES: [&str; 18] = [
+    "guild_kv",
+    "user_kv",
+    "member_kv",
+    "automod_rules",
+    "automod_patterns",
+    "stats_channel_messages",
+    "stats_member_messages",
+    "stats_joins",
+    "member_xp",
+    "level_rewards",
+    "polls",
+    "poll_votes",
+    "giveaways",
+    "giveaway_entries",
+    "starboard",
+    "tags",
+    "custom_fortunes",
+    "fortune_history",
+];
+
+/// Tables with data that belongs to a guild, in the order it's purged in. Poll votes and giveaway entries belong to
+/// their poll or giveaway instead.
+const GUILD_TABLES: [&str; 14] = [
+    "guild_kv",
+    "member_kv",
+    "automod_rules",
+    "automod_patterns",
+    "stats_channel_messages",
+    "stats_member_messages",
+    "stats_joins",
+    "member_xp",
+    "level_rewards",
+    "polls",
+    "giveaways",
+    "starboard",
+    "tags",
+    "custom_fortunes",
+];
+
+/// Columns that number new rows automatically
+const SERIAL_COLUMNS: [(&str, &str); 4] = [
+    ("polls", "poll_id"),
+    ("giveaways", "giveaway_id"),
+    ("custom_fortunes", "fortune_id"),
+    ("fortune_history", "rowid"),
+];
+
+/// Tables with data about a single user, and the column with their ID. A user can export and delete all of it.
+const USER_TABLES: [(&str, &str); 7] = [
+    ("user_kv", "user_id"),
+    ("member_kv", "user_id"),
+    ("stats_member_messages", "user_id"),
+    ("member_xp", "user_id"),
+    ("poll_votes", "user_id"),
+    ("giveaway_entries", "user_id"),
+    ("fortune_history", "user_id"),
+];
+
+/// Tables with things a user created in a guild, and the column with their ID. A user can export them, but they belong
+/// to the guild and are left when the user deletes their data.
+const USER_AUTHORED_TABLES: [(&str, &str); 4] = [
+    ("polls", "author_id"),
+    ("giveaways", "host_id"),
+    ("tags", "author_id"),
+    ("custom_fortunes", "author_id"),
+];
+
+/// The database location with the password of a URL left out, for logs
+pub fn redact_location(location: &str) -> String {
+    let Some((scheme, rest)) = location.split_once("://") else {
+        return location.to_string();
+    };
+    match rest.split_once('@') {
+        Some((credentials, host)) => {
+            let user = credentials.split(':').next().unwrap_or_default();
+            format!("{}://{}:***@{}", scheme, user, host)
+        }
+        None => location.to_string(),
+    }
+}
+
+/// A poll as stored in the database
+#[derive(Debug, Clone)]
+pub struct Poll {
+    pub poll_id: i64,
+    pub guild_id: String,
+    pub channel_id: String,
+    pub message_id: Option<String>,
+    pub author_id: String,
+    pub question: String,
+    pub options: Vec<String>,
+    pub multi_choice: bool,
+    pub anonymous: bool,
+    pub ends_at: Option<i64>,
+    pub closed: bool,
+}
+
+impl Poll {
+    fn from_row(row: &AnyRow) -> Self {
+        let options: String = row.get("options");
+        Self {
+            poll_id: row.get("poll_id"),
+            guild_id: row.get("guild_id"),
+            channel_id: row.get("channel_id"),
+            message_id: row.get("message_id"),
+            author_id: row.get("author_id"),
+            question: row.get("question"),
+            options: serde_json::from_str(&options).unwrap_or_default(),
+            multi_choice: row.get::<i64, _>("multi_choice") != 0,
+            anonymous: row.get::<i64, _>("anonymous") != 0,
+            ends_at: row.get("ends_at"),
+            closed: row.get::<i64, _>("closed") != 0,
+        }
+    }
+}
+
+/// A giveaway as stored in the database
+#[derive(Debug, Clone)]
+pub struct Giveaway {
+    pub giveaway_id: i64,
+    pub guild_id: String,
+    pub channel_id: String,
+    pub message_id: Option<String>,
+    pub host_id: String,
+    pub prize: String,
+    pub winner_count: i64,
+    pub required_role: Option<String>,
+    pub min_member_days: Option<i64>,
+    pub ends_at: i64,
+    pub ended: bool,
+    /// Everyone who won so far, including rerolls
+    pub winners: Vec<String>,
+}
+
+impl Giveaway {
+    fn from_row(row: &AnyRow) -> Self {
+        let winners: String = row.get("winners");
+        Self {
+            giveaway_id: row.get("giveaway_id"),
+            guild_id: row.get("guild_id"),
+            channel_id: row.get("channel_id"),
+            message_id: row.get("message_id"),
+            host_id: row.get("host_id"),
+            prize: row.get("prize"),
+            winner_count: row.get("winner_count"),
+            required_role: row.get("required_role"),
+            min_member_days: row.get("min_member_days"),
+            ends_at: row.get("ends_at"),
+            ended: row.get::<i64, _>("ended") != 0,
+            winners: winners.split(',').filter(|w| !w.is_empty()).map(String::from).collect(),
+        }
+    }
+}
+
+/// A custom tag as stored in the database
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub guild_id: String,
+    pub name: String,
+    pub content: String,
+    pub embed: bool,
+    pub author_id: String,
+    pub created_at: i64,
+    pub uses: i64,
+}
+
+impl Tag {
+    fn from_row(row: &AnyRow) -> Self {
+        Self {
+            guild_id: row.get("guild_id"),
+            name: row.get("name"),
+            content: row.get("content"),
+            embed: row.get::<i64, _>("embed") != 0,
+            author_id: row.get("author_id"),
+            created_at: row.get("created_at"),
+            uses: row.get("uses"),
+        }
+    }
 }
 
 impl Database {
-    /// Initialize a new database connection and create necessary tables
+    /// Initialize a new database connection and create necessary tables. The location is either the path of an
+    /// SQLite file or a `postgres://` URL.
     pub async fn new(db_path: &str) -> Result<Self, SqlxError> {
-        // Create empty file if it doesn't exist
-        let db_file_path = std::path::Path::new(db_path);
-        if !db_file_path.exists() {
-            std::fs::File::create(db_file_path)
-                .map_err(|e| SqlxError::Configuration(format!("Failed to create database file: {}", e).into()))?;
-            warn!("Created new database file at {}", db_path);
-        }
+        sqlx::any::install_default_drivers();
+        let backend = Backend::of(db_path);
+        let url = match backend {
+            Backend::Postgres if cfg!(not(feature = "postgres")) => {
+                return Err(SqlxError::Configuration(
+                    "This build doesn't support PostgreSQL, build it with `--features postgres`".into()
+                ));
+            }
+            Backend::Postgres => db_path.to_string(),
+            Backend::Sqlite => {
+                // Create empty file if it doesn't exist
+                let db_file_path = std::path::Path::new(db_path);
+                if !db_file_path.exists() {
+                    std::fs::File::create(db_file_path)
+                        .map_err(|e| SqlxError::Configuration(format!("Failed to create database file: {}", e).into()))?;
+                    warn!("Created new database file at {}", db_path);
+                }
+                format!("sqlite:{}", db_path)
+            }
+        };
 
-        info!("Connecting to database at {}", db_path);
-        let pool = SqlitePoolOptions::new()
+        info!("Connecting to database at {}", redact_location(db_path));
+        let pool = AnyPoolOptions::new()
             .max_connections(5)
-            .connect(&format!("sqlite:{}", db_path))
+            .connect(&url)
             .await?;
+        Self::init(pool, backend).await
+    }
 
-        // Create tables if they don't exist
-        sqlx::query(
+    /// An empty SQLite database that only lives in memory, for tests
+    #[cfg(test)]
+    async fn in_memory() -> Result<Self, SqlxError> {
+        sqlx::any::install_default_drivers();
+        // Every connection would get its own database, so there must only ever be one
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await?;
+        Self::init(pool, Backend::Sqlite).await
+    }
+
+    /// Create the tables that don't exist yet
+    async fn init(pool: AnyPool, backend: Backend) -> Result<Self, SqlxError> {
+        sqlx::query(backend.ddl(
             "CREATE TABLE IF NOT EXISTS guild_kv (
                 guild_id TEXT NOT NULL,
                 key TEXT NOT NULL,
@@ -39,11 +352,11 @@ impl Database {
                 updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
                 PRIMARY KEY (guild_id, key)
             )"
-        )
+        ))
             .execute(&pool)
             .await?;
 
-        sqlx::query(
+        sqlx::query(backend.ddl(
             "CREATE TABLE IF NOT EXISTS user_kv (
                 user_id TEXT NOT NULL,
                 key TEXT NOT NULL,
@@ -51,50 +364,411 @@ impl Database {
                 updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
                 PRIMARY KEY (user_id, key)
             )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS member_kv (
+                guild_id TEXT NOT NULL,
+                user_id TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (guild_id, user_id, key)
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS automod_rules (
+                guild_id TEXT NOT NULL,
+                rule TEXT NOT NULL,
+                action TEXT NOT NULL,
+                threshold INTEGER,
+                PRIMARY KEY (guild_id, rule)
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS automod_patterns (
+                guild_id TEXT NOT NULL,
+                pattern TEXT NOT NULL,
+                is_regex INTEGER NOT NULL,
+                PRIMARY KEY (guild_id, pattern)
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS stats_channel_messages (
+                guild_id TEXT NOT NULL,
+                channel_id TEXT NOT NULL,
+                day TEXT NOT NULL,
+                messages INTEGER NOT NULL,
+                PRIMARY KEY (guild_id, channel_id, day)
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS stats_member_messages (
+                guild_id TEXT NOT NULL,
+                user_id TEXT NOT NULL,
+                day TEXT NOT NULL,
+                messages INTEGER NOT NULL,
+                PRIMARY KEY (guild_id, user_id, day)
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS stats_joins (
+                guild_id TEXT NOT NULL,
+                day TEXT NOT NULL,
+                joins INTEGER NOT NULL DEFAULT 0,
+                leaves INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (guild_id, day)
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS member_xp (
+                guild_id TEXT NOT NULL,
+                user_id TEXT NOT NULL,
+                xp INTEGER NOT NULL DEFAULT 0,
+                last_award INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (guild_id, user_id)
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS level_rewards (
+                guild_id TEXT NOT NULL,
+                level INTEGER NOT NULL,
+                role_id TEXT NOT NULL,
+                PRIMARY KEY (guild_id, role_id)
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS polls (
+                poll_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id TEXT NOT NULL,
+                channel_id TEXT NOT NULL,
+                message_id TEXT,
+                author_id TEXT NOT NULL,
+                question TEXT NOT NULL,
+                options TEXT NOT NULL,
+                multi_choice INTEGER NOT NULL,
+                anonymous INTEGER NOT NULL,
+                ends_at INTEGER,
+                closed INTEGER NOT NULL DEFAULT 0
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS poll_votes (
+                poll_id INTEGER NOT NULL,
+                user_id TEXT NOT NULL,
+                option INTEGER NOT NULL,
+                PRIMARY KEY (poll_id, user_id, option)
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS giveaways (
+                giveaway_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id TEXT NOT NULL,
+                channel_id TEXT NOT NULL,
+                message_id TEXT,
+                host_id TEXT NOT NULL,
+                prize TEXT NOT NULL,
+                winner_count INTEGER NOT NULL,
+                required_role TEXT,
+                min_member_days INTEGER,
+                ends_at INTEGER NOT NULL,
+                ended INTEGER NOT NULL DEFAULT 0,
+                winners TEXT NOT NULL DEFAULT ''
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS giveaway_entries (
+                giveaway_id INTEGER NOT NULL,
+                user_id TEXT NOT NULL,
+                PRIMARY KEY (giveaway_id, user_id)
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS starboard (
+                message_id TEXT PRIMARY KEY,
+                guild_id TEXT NOT NULL,
+                starboard_channel_id TEXT NOT NULL,
+                starboard_message_id TEXT NOT NULL
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS tags (
+                guild_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                content TEXT NOT NULL,
+                embed INTEGER NOT NULL DEFAULT 0,
+                author_id TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                uses INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (guild_id, name)
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS custom_fortunes (
+                fortune_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id TEXT NOT NULL,
+                text TEXT NOT NULL,
+                author_id TEXT NOT NULL
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(backend.ddl(
+            "CREATE TABLE IF NOT EXISTS fortune_history (
+                rowid INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id TEXT NOT NULL,
+                fortune_id TEXT NOT NULL,
+                text TEXT NOT NULL,
+                received_at INTEGER NOT NULL
+            )"
+        ))
+            .execute(&pool)
+            .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS fortune_history_user ON fortune_history (user_id, received_at)"
         )
             .execute(&pool)
             .await?;
 
         info!("Database initialized successfully");
-        Ok(Self { pool: Arc::new(pool) })
+        Ok(Self { pool: Arc::new(pool), backend })
     }
 
     /// Get the database connection pool
-    pub fn pool(&self) -> Arc<SqlitePool> {
+    pub fn pool(&self) -> Arc<AnyPool> {
         self.pool.clone()
     }
 
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Close every connection, waiting for running queries to finish
+    pub async fn close(&self) {
+        self.pool.close().await;
+        info!("Database closed");
+    }
+
+    /// Write a consistent copy of the database to a new file while it's in use. Only SQLite databases can be backed up
+    /// this way, PostgreSQL has `pg_dump` for that.
+    pub async fn backup_to(&self, path: &str) -> Result<(), SqlxError> {
+        if self.backend != Backend::Sqlite {
+            return Err(SqlxError::Configuration("Only SQLite databases can be backed up, use pg_dump for PostgreSQL".into()));
+        }
+        let _timer = metrics::time_query("backup_to");
+        debug!("Backing up database to {}", path);
+        sqlx::query("VACUUM INTO $1")
+            .bind(path)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Check a database file without changing it. Returns the problems found, which is empty for a usable database.
+    pub async fn check_file(path: &str) -> Result<Vec<String>, SqlxError> {
+        let options = SqliteConnectOptions::new().filename(path).read_only(true);
+        let mut connection = SqliteConnection::connect_with(&options).await?;
+
+        let mut problems = sqlx::query("PRAGMA integrity_check")
+            .fetch_all(&mut connection)
+            .await?
+            .iter()
+            .map(|row| row.get::<String, _>(0))
+            .filter(|r| r != "ok")
+            .collect::<Vec<String>>();
+        let has_tables = sqlx::query("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'guild_kv'")
+            .fetch_optional(&mut connection)
+            .await?
+            .is_some();
+        if !has_tables {
+            problems.push(String::from("It's not a database of this bot, the guild_kv table is missing."));
+        }
+        connection.close().await?;
+        Ok(problems)
+    }
+
+    /// Check that the database answers a trivial query
+    pub async fn ping(&self) -> Result<(), SqlxError> {
+        let _timer = metrics::time_query("ping");
+        sqlx::query("SELECT 1").execute(&*self.pool).await?;
+        Ok(())
+    }
+
+    /// Copy every table of an SQLite database into this PostgreSQL database, in one transaction. Refuses to copy into
+    /// tables that already have rows. Returns the number of rows copied per table.
+    pub async fn copy_from(&self, source: &Database) -> Result<Vec<(&'static str, usize)>, SqlxError> {
+        if self.backend != Backend::Postgres || source.backend != Backend::Sqlite {
+            return Err(SqlxError::Configuration("Can only copy from an SQLite database into a PostgreSQL database".into()));
+        }
+        for table in TABLES {
+            let rows: i64 = sqlx::query(AssertSqlSafe(format!("SELECT COUNT(*) AS count FROM {}", table)))
+                .fetch_one(&*self.pool)
+                .await?
+                .get("count");
+            if rows > 0 {
+                return Err(SqlxError::Configuration(
+                    format!("The {} table already has {} row(s), only an empty database can be copied into", table, rows).into()
+                ));
+            }
+        }
+
+        let mut copied = vec![];
+        let mut transaction = self.pool.begin().await?;
+        for table in TABLES {
+            // The target decides the types, SQLite would return whatever was stored
+            let columns = sqlx::query(
+                "SELECT CAST(column_name AS TEXT) AS column_name, CAST(data_type AS TEXT) AS data_type
+                 FROM information_schema.columns
+                 WHERE table_schema = current_schema() AND table_name = $1
+                 ORDER BY ordinal_position"
+            )
+                .bind(table)
+                .fetch_all(&mut *transaction)
+                .await?
+                .iter()
+                .map(|row| (row.get::<String, _>("column_name"), row.get::<String, _>("data_type") == "bigint"))
+                .collect::<Vec<(String, bool)>>();
+            let names = columns.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>().join(", ");
+            let casts = columns.iter()
+                .map(|(name, is_integer)| format!("CAST({0} AS {1}) AS {0}", name, if *is_integer { "INTEGER" } else { "TEXT" }))
+                .collect::<Vec<String>>()
+                .join(", ");
+            // Rowids of the fortune history are its order
+            let order = if table == "fortune_history" { " ORDER BY rowid" } else { "" };
+            let rows = sqlx::query(AssertSqlSafe(format!("SELECT {} FROM {}{}", casts, table, order)))
+                .fetch_all(&*source.pool)
+                .await?;
+
+            let placeholders = (1..=columns.len()).map(|i| format!("${}", i)).collect::<Vec<String>>().join(", ");
+            let insert = format!("INSERT INTO {} ({}) VALUES ({})", table, names, placeholders);
+            for row in &rows {
+                let mut query = sqlx::query(AssertSqlSafe(insert.as_str()));
+                for (i, (_, is_integer)) in columns.iter().enumerate() {
+                    query = if *is_integer {
+                        query.bind(row.try_get::<Option<i64>, _>(i)?)
+                    } else {
+                        query.bind(row.try_get::<Option<String>, _>(i)?)
+                    };
+                }
+                query.execute(&mut *transaction).await?;
+            }
+            debug!("Copied {} row(s) of {}", rows.len(), table);
+            copied.push((table, rows.len()));
+        }
+
+        // New rows must continue after the copied IDs
+        for (table, column) in SERIAL_COLUMNS {
+            sqlx::query(AssertSqlSafe(format!(
+                "SELECT setval(pg_get_serial_sequence('{0}', '{1}'), COALESCE(MAX({1}), 0) + 1, false) FROM {0}",
+                table, column
+            )))
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(copied)
+    }
+
     // Guild-specific key-value methods
 
-    /// Set a key-value pair for a specific guild
+    /// Set a key-value pair for a specific guild. Unchanged values aren't written.
     pub async fn set_guild_value<G, K, V>(&self, guild_id: &G, key: &K, value: &V) -> Result<(), SqlxError>
     where
         G: Display + Send + Sync + ?Sized,
         K: Display + Send + Sync + ?Sized,
         V: Display + Send + Sync + ?Sized,
     {
+        let _timer = metrics::time_query("set_guild_value");
+        kv_set(&*self.pool, KvScope::Guild, &[guild_id.to_string()], key, value).await?;
+        Ok(())
+    }
 
-        // First, check if the key exists and get its current value
-        let existing_value = self.get_guild_value(guild_id, key).await?;
-
-        // Only update if the value has changed or the key didn't exist
-        if existing_value.as_deref() != Some(value.to_string().as_str()) {
-            debug!("Setting guild value: guild_id={}, key={}", guild_id, key);
-            sqlx::query(
-                "INSERT OR REPLACE INTO guild_kv (guild_id, key, value, updated_at)
-             VALUES (?, ?, ?, CURRENT_TIMESTAMP)"
-            )
-                .bind(guild_id.to_string())
-                .bind(key.to_string())
-                .bind(value.to_string())
-                .execute(&*self.pool)
-                .await?;
-        } else {
-            // Key exists with same value - no update needed
-            debug!("Value unchanged for guild_id={}, key={}, skipping update", guild_id, key);
+    /// Set several key-value pairs for a specific guild at once
+    pub async fn set_guild_values<G, K, V>(&self, guild_id: &G, values: &[(K, V)]) -> Result<(), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync,
+        V: Display + Send + Sync,
+    {
+        let _timer = metrics::time_query("set_guild_values");
+        let mut transaction = self.pool.begin().await?;
+        for (key, value) in values {
+            kv_set(&mut *transaction, KvScope::Guild, &[guild_id.to_string()], key, value).await?;
         }
+        transaction.commit().await
+    }
 
-        Ok(())
+    /// Set a value for a specific guild only if it's currently `expected`, where None means the key must not exist.
+    ///
+    /// Returns true if the value was set.
+    pub async fn compare_and_set_guild_value<G, K, V>(&self, guild_id: &G, key: &K, expected: Option<&str>, value: &V)
+        -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+        V: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("compare_and_set_guild_value");
+        kv_compare_and_set(&*self.pool, KvScope::Guild, &[guild_id.to_string()], key, expected, value).await
+    }
+
+    /// Delete a key-value pair for a specific guild only if its value is currently `expected`.
+    ///
+    /// Returns true if it was deleted.
+    pub async fn compare_and_delete_guild_value<G, K>(&self, guild_id: &G, key: &K, expected: &str)
+        -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("compare_and_delete_guild_value");
+        kv_compare_and_delete(&*self.pool, KvScope::Guild, &[guild_id.to_string()], key, expected).await
     }
 
     /// Get a value for a specific guild and key
@@ -103,18 +777,30 @@ impl Database {
         G: Display + Send + Sync + ?Sized,
         K: Display + Send + Sync + ?Sized,
     {
-        debug!("Getting guild value: guild_id={}, key={}", guild_id, key);
+        let _timer = metrics::time_query("get_guild_value");
+        kv_get(&*self.pool, KvScope::Guild, &[guild_id.to_string()], key).await
+    }
 
-        let result = sqlx::query(
-            "SELECT value FROM guild_kv
-             WHERE guild_id = ? AND key = ?"
-        )
-            .bind(guild_id.to_string())
-            .bind(key.to_string())
-            .fetch_optional(&*self.pool)
-            .await?;
+    /// Get a value stored as JSON for a specific guild and key
+    pub async fn get_guild_value_typed<T, G, K>(&self, guild_id: &G, key: &K) -> Result<Option<T>, KvError>
+    where
+        T: DeserializeOwned,
+        G: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let value = self.get_guild_value(guild_id, key).await?;
+        from_json(key, value)
+    }
 
-        Ok(result.map(|row| row.get("value")))
+    /// Store a value as JSON for a specific guild and key
+    pub async fn set_guild_value_typed<T, G, K>(&self, guild_id: &G, key: &K, value: &T) -> Result<(), KvError>
+    where
+        T: Serialize + ?Sized,
+        G: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let value = to_json(key, value)?;
+        Ok(self.set_guild_value(guild_id, key, &value).await?)
     }
 
     /// Delete a key-value pair for a specific guild
@@ -123,19 +809,8 @@ impl Database {
         G: Display + Send + Sync + ?Sized,
         K: Display + Send + Sync + ?Sized,
     {
-        debug!("Deleting guild value: guild_id={}, key={}", guild_id, key);
-
-        let result = sqlx::query(
-            "DELETE FROM guild_kv
-             WHERE guild_id = ? AND key = ?"
-        )
-            .bind(guild_id.to_string())
-            .bind(key.to_string())
-            .execute(&*self.pool)
-            .await?;
-
-        // Return true if a row was deleted
-        Ok(result.rows_affected() > 0)
+        let _timer = metrics::time_query("delete_guild_value");
+        kv_delete(&*self.pool, KvScope::Guild, &[guild_id.to_string()], key).await
     }
 
     /// Get all key-value pairs for a specific guild
@@ -143,118 +818,2271 @@ impl Database {
     where
         G: Display + Send + Sync + ?Sized,
     {
-        debug!("Getting all guild values: guild_id={}", guild_id);
-
-        let rows = sqlx::query(
-            "SELECT key, value FROM guild_kv
-             WHERE guild_id = ?"
-        )
-            .bind(guild_id.to_string())
-            .fetch_all(&*self.pool)
-            .await?;
+        let _timer = metrics::time_query("get_all_guild_values");
+        kv_get_prefix(&*self.pool, KvScope::Guild, &[guild_id.to_string()], "").await
+    }
 
-        let mut values = Vec::with_capacity(rows.len());
-        for row in rows {
-            values.push((row.get("key"), row.get("value")));
-        }
+    /// Get the key-value pairs of a specific guild whose key starts with a prefix, e.g. `config.`
+    pub async fn get_guild_values_with_prefix<G>(&self, guild_id: &G, prefix: &str)
+        -> Result<Vec<(String, String)>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_guild_values_with_prefix");
+        kv_get_prefix(&*self.pool, KvScope::Guild, &[guild_id.to_string()], prefix).await
+    }
 
-        Ok(values)
+    /// Delete the key-value pairs of a specific guild whose key starts with a prefix. Returns how many were deleted.
+    pub async fn delete_guild_values_with_prefix<G>(&self, guild_id: &G, prefix: &str) -> Result<u64, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_guild_values_with_prefix");
+        kv_delete_prefix(&*self.pool, KvScope::Guild, &[guild_id.to_string()], prefix).await
     }
 
     // User-specific key-value methods
 
-    /// Set a key-value pair for a specific user
+    /// Set a key-value pair for a specific user. Unchanged values aren't written.
     pub async fn set_user_value<U, K, V>(&self, user_id: &U, key: &K, value: &V) -> Result<(), SqlxError>
     where
         U: Display + Send + Sync + ?Sized,
         K: Display + Send + Sync + ?Sized,
         V: Display + Send + Sync + ?Sized,
     {
-        // First, check if the key exists and get its current value
-        let existing_value = self.get_user_value(user_id, key).await?;
-
-        // Only update if the value has changed or the key didn't exist
-        if existing_value.as_deref() != Some(value.to_string().as_str()) {
-            debug!("Setting user value: user_id={}, key={}", user_id, key);
-            sqlx::query(
-                "INSERT OR REPLACE INTO user_kv (user_id, key, value, updated_at)
-             VALUES (?, ?, ?, CURRENT_TIMESTAMP)"
-            )
-                .bind(user_id.to_string())
-                .bind(key.to_string())
-                .bind(value.to_string())
-                .execute(&*self.pool)
-                .await?;
-        } else {
-            // Key exists with the same value - no update needed
-            debug!("Value unchanged for user_id={}, key={}, skipping update", user_id, key);
-        }
-
+        let _timer = metrics::time_query("set_user_value");
+        kv_set(&*self.pool, KvScope::User, &[user_id.to_string()], key, value).await?;
         Ok(())
     }
 
-    /// Get a value for a specific user and key
-    pub async fn get_user_value<U, K>(&self, user_id: &U, key: &K) -> Result<Option<String>, SqlxError>
+    /// Set several key-value pairs for a specific user at once
+    pub async fn set_user_values<U, K, V>(&self, user_id: &U, values: &[(K, V)]) -> Result<(), SqlxError>
     where
         U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync,
+        V: Display + Send + Sync,
+    {
+        let _timer = metrics::time_query("set_user_values");
+        let mut transaction = self.pool.begin().await?;
+        for (key, value) in values {
+            kv_set(&mut *transaction, KvScope::User, &[user_id.to_string()], key, value).await?;
+        }
+        transaction.commit().await
+    }
+
+    /// Set a value for a specific user only if it's currently `expected`, where None means the key must not exist.
+    ///
+    /// Returns true if the value was set.
+    pub async fn compare_and_set_user_value<U, K, V>(&self, user_id: &U, key: &K, expected: Option<&str>, value: &V)
+        -> Result<bool, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+        V: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("compare_and_set_user_value");
+        kv_compare_and_set(&*self.pool, KvScope::User, &[user_id.to_string()], key, expected, value).await
+    }
+
+    /// Get a value for a specific user and key
+    pub async fn get_user_value<U, K>(&self, user_id: &U, key: &K) -> Result<Option<String>, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_user_value");
+        kv_get(&*self.pool, KvScope::User, &[user_id.to_string()], key).await
+    }
+
+    /// Get a value stored as JSON for a specific user and key
+    pub async fn get_user_value_typed<T, U, K>(&self, user_id: &U, key: &K) -> Result<Option<T>, KvError>
+    where
+        T: DeserializeOwned,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let value = self.get_user_value(user_id, key).await?;
+        from_json(key, value)
+    }
+
+    /// Store a value as JSON for a specific user and key
+    pub async fn set_user_value_typed<T, U, K>(&self, user_id: &U, key: &K, value: &T) -> Result<(), KvError>
+    where
+        T: Serialize + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let value = to_json(key, value)?;
+        Ok(self.set_user_value(user_id, key, &value).await?)
+    }
+
+    /// Delete a key-value pair for a specific user
+    pub async fn delete_user_value<U, K>(&self, user_id: &U, key: &K) -> Result<bool, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_user_value");
+        kv_delete(&*self.pool, KvScope::User, &[user_id.to_string()], key).await
+    }
+
+    /// Get all key-value pairs for a specific user
+    pub async fn get_all_user_values<U>(&self, user_id: &U) -> Result<Vec<(String, String)>, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_all_user_values");
+        kv_get_prefix(&*self.pool, KvScope::User, &[user_id.to_string()], "").await
+    }
+
+    /// Get the key-value pairs of a specific user whose key starts with a prefix
+    pub async fn get_user_values_with_prefix<U>(&self, user_id: &U, prefix: &str)
+        -> Result<Vec<(String, String)>, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_user_values_with_prefix");
+        kv_get_prefix(&*self.pool, KvScope::User, &[user_id.to_string()], prefix).await
+    }
+
+    /// Delete the key-value pairs of a specific user whose key starts with a prefix. Returns how many were deleted.
+    pub async fn delete_user_values_with_prefix<U>(&self, user_id: &U, prefix: &str) -> Result<u64, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_user_values_with_prefix");
+        kv_delete_prefix(&*self.pool, KvScope::User, &[user_id.to_string()], prefix).await
+    }
+
+    /// Start a transaction for key-value changes that must happen together. Nothing is written unless it's committed.
+    pub async fn begin(&self) -> Result<KvTransaction, SqlxError> {
+        Ok(KvTransaction { transaction: self.pool.begin().await? })
+    }
+
+    // Member-specific key-value methods, for a user within a guild
+
+    /// Set a key-value pair for a specific member. Unchanged values aren't written.
+    pub async fn set_member_value<G, U, K, V>(&self, guild_id: &G, user_id: &U, key: &K, value: &V)
+        -> Result<(), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+        V: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("set_member_value");
+        kv_set(&*self.pool, KvScope::Member, &[guild_id.to_string(), user_id.to_string()], key, value).await?;
+        Ok(())
+    }
+
+    /// Get a value for a specific member and key
+    pub async fn get_member_value<G, U, K>(&self, guild_id: &G, user_id: &U, key: &K)
+        -> Result<Option<String>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_member_value");
+        kv_get(&*self.pool, KvScope::Member, &[guild_id.to_string(), user_id.to_string()], key).await
+    }
+
+    /// Get a value stored as JSON for a specific member and key
+    pub async fn get_member_value_typed<T, G, U, K>(&self, guild_id: &G, user_id: &U, key: &K)
+        -> Result<Option<T>, KvError>
+    where
+        T: DeserializeOwned,
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let value = self.get_member_value(guild_id, user_id, key).await?;
+        from_json(key, value)
+    }
+
+    /// Store a value as JSON for a specific member and key
+    pub async fn set_member_value_typed<T, G, U, K>(&self, guild_id: &G, user_id: &U, key: &K, value: &T)
+        -> Result<(), KvError>
+    where
+        T: Serialize + ?Sized,
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let value = to_json(key, value)?;
+        Ok(self.set_member_value(guild_id, user_id, key, &value).await?)
+    }
+
+    /// Delete a key-value pair for a specific member
+    pub async fn delete_member_value<G, U, K>(&self, guild_id: &G, user_id: &U, key: &K) -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_member_value");
+        kv_delete(&*self.pool, KvScope::Member, &[guild_id.to_string(), user_id.to_string()], key).await
+    }
+
+    /// Get all key-value pairs for a specific member
+    pub async fn get_all_member_values<G, U>(&self, guild_id: &G, user_id: &U)
+        -> Result<Vec<(String, String)>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_all_member_values");
+        kv_get_prefix(&*self.pool, KvScope::Member, &[guild_id.to_string(), user_id.to_string()], "").await
+    }
+
+    /// Delete the key-value pairs of every member of a guild. Returns how many were deleted.
+    pub async fn delete_guild_member_values<G>(&self, guild_id: &G) -> Result<u64, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_guild_member_values");
+        debug!("Deleting member values of guild_id={}", guild_id);
+
+        let result = sqlx::query("DELETE FROM member_kv WHERE guild_id = $1")
+            .bind(guild_id.to_string())
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Get every guild that has a value for a key, with that value
+    pub async fn get_guilds_with_value<K>(&self, key: &K) -> Result<Vec<(String, String)>, SqlxError>
+    where
+        K: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_guilds_with_value");
+        debug!("Getting guilds with value: key={}", key);
+
+        let rows = sqlx::query("SELECT guild_id, value FROM guild_kv WHERE key = $1")
+            .bind(key.to_string())
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| (row.get("guild_id"), row.get("value"))).collect())
+    }
+
+    /// Get every member that has a value for a key, as (guild ID, user ID, value)
+    pub async fn get_members_with_value<K>(&self, key: &K) -> Result<Vec<(String, String, String)>, SqlxError>
+    where
         K: Display + Send + Sync + ?Sized,
     {
+        let _timer = metrics::time_query("get_members_with_value");
+        debug!("Getting members with value: key={}", key);
+
+        let rows = sqlx::query("SELECT guild_id, user_id, value FROM member_kv WHERE key = $1")
+            .bind(key.to_string())
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| (row.get("guild_id"), row.get("user_id"), row.get("value"))).collect())
+    }
+
+    // Guild data retention methods
+
+    /// Count the rows every guild has in each table, for the guilds that have any
+    pub async fn get_guild_data_counts(&self) -> Result<BTreeMap<String, Vec<(&'static str, i64)>>, SqlxError> {
+        let _timer = metrics::time_query("get_guild_data_counts");
+        let mut counts: BTreeMap<String, Vec<(&'static str, i64)>> = BTreeMap::new();
+        for table in GUILD_TABLES {
+            let rows = sqlx::query(AssertSqlSafe(format!(
+                "SELECT guild_id, COUNT(*) AS count FROM {} GROUP BY guild_id",
+                table
+            )))
+                .fetch_all(&*self.pool)
+                .await?;
+            for row in rows {
+                counts.entry(row.get("guild_id")).or_default().push((table, row.get("count")));
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Delete everything stored for a guild, in one transaction. Returns how many rows were deleted.
+    pub async fn purge_guild<G>(&self, guild_id: &G) -> Result<u64, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("purge_guild");
+        debug!("Purging guild_id={}", guild_id);
+
+        let mut transaction = self.pool.begin().await?;
+        let mut deleted = 0;
+        // Votes and entries first, while their polls and giveaways can still be found
+        for statement in [
+            "DELETE FROM poll_votes WHERE poll_id IN (SELECT poll_id FROM polls WHERE guild_id = $1)",
+            "DELETE FROM giveaway_entries WHERE giveaway_id IN (SELECT giveaway_id FROM giveaways WHERE guild_id = $1)",
+        ] {
+            deleted += sqlx::query(statement)
+                .bind(guild_id.to_string())
+                .execute(&mut *transaction)
+                .await?
+                .rows_affected();
+        }
+        for table in GUILD_TABLES {
+            deleted += sqlx::query(AssertSqlSafe(format!("DELETE FROM {} WHERE guild_id = $1", table)))
+                .bind(guild_id.to_string())
+                .execute(&mut *transaction)
+                .await?
+                .rows_affected();
+        }
+        transaction.commit().await?;
+
+        Ok(deleted)
+    }
+
+    // User data methods
+
+    /// Get the names of a table's columns, and whether each holds integers
+    async fn get_columns(&self, table: &str) -> Result<Vec<(String, bool)>, SqlxError> {
+        let query = match self.backend {
+            Backend::Sqlite => "SELECT name AS column_name, type AS data_type FROM pragma_table_info($1) ORDER BY cid",
+            Backend::Postgres => {
+                "SELECT CAST(column_name AS TEXT) AS column_name, CAST(data_type AS TEXT) AS data_type
+                 FROM information_schema.columns
+                 WHERE table_schema = current_schema() AND table_name = $1
+                 ORDER BY ordinal_position"
+            }
+        };
+        let rows = sqlx::query(query)
+            .bind(table)
+            .fetch_all(&*self.pool)
+            .await?;
+        Ok(rows.iter()
+            .map(|row| {
+                let data_type = row.get::<String, _>("data_type").to_uppercase();
+                (row.get("column_name"), data_type.contains("INT"))
+            })
+            .collect())
+    }
+
+    /// Get every row stored about a user and every row they created, as JSON objects grouped by table
+    pub async fn export_user_data<U>(&self, user_id: &U)
+        -> Result<BTreeMap<&'static str, Vec<serde_json::Map<String, serde_json::Value>>>, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("export_user_data");
+        debug!("Exporting user data: user_id={}", user_id);
+
+        let mut tables = BTreeMap::new();
+        for (table, column) in USER_TABLES.iter().chain(USER_AUTHORED_TABLES.iter()) {
+            let columns = self.get_columns(table).await?;
+            // Cast every column, the Any driver can't read some SQLite types like timestamps
+            let casts = columns.iter()
+                .map(|(name, is_integer)| format!("CAST({0} AS {1}) AS {0}", name, if *is_integer { "BIGINT" } else { "TEXT" }))
+                .collect::<Vec<String>>()
+                .join(", ");
+            let rows = sqlx::query(AssertSqlSafe(format!("SELECT {} FROM {} WHERE {} = $1", casts, table, column)))
+                .bind(user_id.to_string())
+                .fetch_all(&*self.pool)
+                .await?;
+            if rows.is_empty() {
+                continue;
+            }
+
+            let mut objects = vec![];
+            for row in &rows {
+                let mut object = serde_json::Map::new();
+                for (i, (name, is_integer)) in columns.iter().enumerate() {
+                    let value = if *is_integer {
+                        row.try_get::<Option<i64>, _>(i)?.map(serde_json::Value::from)
+                    } else {
+                        row.try_get::<Option<String>, _>(i)?.map(serde_json::Value::from)
+                    };
+                    object.insert(name.clone(), value.unwrap_or_default());
+                }
+                objects.push(object);
+            }
+            tables.insert(*table, objects);
+        }
+        Ok(tables)
+    }
+
+    /// Delete everything stored about a user, in one transaction. What they created in guilds is kept, and so are the
+    /// user values of `kept_keys`. Returns how many rows were deleted.
+    pub async fn delete_user_data<U>(&self, user_id: &U, kept_keys: &[&str]) -> Result<u64, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_user_data");
+        debug!("Deleting user data: user_id={}, kept_keys={:?}", user_id, kept_keys);
+
+        let mut transaction = self.pool.begin().await?;
+        let mut deleted = 0;
+        for (table, column) in USER_TABLES {
+            let mut sql = format!("DELETE FROM {} WHERE {} = $1", table, column);
+            let kept = if table == "user_kv" { kept_keys } else { &[] };
+            if !kept.is_empty() {
+                let params = (2..kept.len() + 2).map(|i| format!("${}", i)).collect::<Vec<String>>().join(", ");
+                sql += &format!(" AND key NOT IN ({})", params);
+            }
+            let mut query = sqlx::query(AssertSqlSafe(sql)).bind(user_id.to_string());
+            for key in kept {
+                query = query.bind(key.to_string());
+            }
+            deleted += query.execute(&mut *transaction).await?.rows_affected();
+        }
+        transaction.commit().await?;
+
+        Ok(deleted)
+    }
+
+    // Automod methods
+
+    /// Enable an automod rule for a guild, or change its action and threshold
+    pub async fn set_automod_rule<G>(&self, guild_id: &G, rule: &str, action: &str, threshold: Option<i64>)
+        -> Result<(), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("set_automod_rule");
+        debug!("Setting automod rule: guild_id={}, rule={}", guild_id, rule);
+
+        sqlx::query(
+            "INSERT INTO automod_rules (guild_id, rule, action, threshold)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (guild_id, rule) DO UPDATE SET action = excluded.action, threshold = excluded.threshold"
+        )
+            .bind(guild_id.to_string())
+            .bind(rule)
+            .bind(action)
+            .bind(threshold)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Disable an automod rule for a guild
+    pub async fn delete_automod_rule<G>(&self, guild_id: &G, rule: &str) -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_automod_rule");
+        debug!("Deleting automod rule: guild_id={}, rule={}", guild_id, rule);
+
+        let result = sqlx::query(
+            "DELETE FROM automod_rules
+             WHERE guild_id = $1 AND rule = $2"
+        )
+            .bind(guild_id.to_string())
+            .bind(rule)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Get all enabled automod rules for a guild as (rule, action, threshold)
+    pub async fn get_automod_rules<G>(&self, guild_id: &G) -> Result<Vec<(String, String, Option<i64>)>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_automod_rules");
+        debug!("Getting automod rules: guild_id={}", guild_id);
+
+        let rows = sqlx::query(
+            "SELECT rule, action, threshold FROM automod_rules
+             WHERE guild_id = $1"
+        )
+            .bind(guild_id.to_string())
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| (row.get("rule"), row.get("action"), row.get("threshold"))).collect())
+    }
+
+    /// Add a banned word or regex for a guild. Returns false if it already existed.
+    pub async fn add_automod_pattern<G>(&self, guild_id: &G, pattern: &str, is_regex: bool) -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("add_automod_pattern");
+        debug!("Adding automod pattern: guild_id={}", guild_id);
+
+        let result = sqlx::query(
+            "INSERT INTO automod_patterns (guild_id, pattern, is_regex)
+             VALUES ($1, $2, $3)
+             ON CONFLICT DO NOTHING"
+        )
+            .bind(guild_id.to_string())
+            .bind(pattern)
+            .bind(is_regex as i64)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Remove a banned word or regex from a guild
+    pub async fn delete_automod_pattern<G>(&self, guild_id: &G, pattern: &str) -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_automod_pattern");
+        debug!("Deleting automod pattern: guild_id={}", guild_id);
+
+        let result = sqlx::query(
+            "DELETE FROM automod_patterns
+             WHERE guild_id = $1 AND pattern = $2"
+        )
+            .bind(guild_id.to_string())
+            .bind(pattern)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Get all banned words and regexes for a guild as (pattern, is_regex)
+    pub async fn get_automod_patterns<G>(&self, guild_id: &G) -> Result<Vec<(String, bool)>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_automod_patterns");
+        debug!("Getting automod patterns: guild_id={}", guild_id);
+
+        let rows = sqlx::query(
+            "SELECT pattern, is_regex FROM automod_patterns
+             WHERE guild_id = $1"
+        )
+            .bind(guild_id.to_string())
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| (row.get("pattern"), row.get::<i64, _>("is_regex") != 0)).collect())
+    }
+
+    // Statistics methods
+
+    /// Count a message towards the daily channel statistics, and the member statistics if a user is given
+    pub async fn record_message_stat<G, C, U>(&self, guild_id: &G, channel_id: &C, user_id: Option<&U>, day: &str)
+        -> Result<(), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        C: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("record_message_stat");
+        debug!("Recording message stat: guild_id={}, channel_id={}", guild_id, channel_id);
+
+        sqlx::query(
+            "INSERT INTO stats_channel_messages (guild_id, channel_id, day, messages)
+             VALUES ($1, $2, $3, 1)
+             ON CONFLICT (guild_id, channel_id, day) DO UPDATE SET messages = stats_channel_messages.messages + 1"
+        )
+            .bind(guild_id.to_string())
+            .bind(channel_id.to_string())
+            .bind(day)
+            .execute(&*self.pool)
+            .await?;
+
+        if let Some(user_id) = user_id {
+            sqlx::query(
+                "INSERT INTO stats_member_messages (guild_id, user_id, day, messages)
+                 VALUES ($1, $2, $3, 1)
+                 ON CONFLICT (guild_id, user_id, day) DO UPDATE SET messages = stats_member_messages.messages + 1"
+            )
+                .bind(guild_id.to_string())
+                .bind(user_id.to_string())
+                .bind(day)
+                .execute(&*self.pool)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Count a member joining or leaving towards the daily statistics
+    pub async fn record_join_stat<G>(&self, guild_id: &G, day: &str, joined: bool) -> Result<(), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("record_join_stat");
+        debug!("Recording join stat: guild_id={}, joined={}", guild_id, joined);
+
+        let query = if joined {
+            "INSERT INTO stats_joins (guild_id, day, joins) VALUES ($1, $2, 1)
+             ON CONFLICT (guild_id, day) DO UPDATE SET joins = stats_joins.joins + 1"
+        } else {
+            "INSERT INTO stats_joins (guild_id, day, leaves) VALUES ($1, $2, 1)
+             ON CONFLICT (guild_id, day) DO UPDATE SET leaves = stats_joins.leaves + 1"
+        };
+        sqlx::query(query)
+            .bind(guild_id.to_string())
+            .bind(day)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get message counts per channel since the given day, busiest channels first
+    pub async fn get_channel_message_stats<G>(&self, guild_id: &G, since: &str) -> Result<Vec<(String, i64)>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_channel_message_stats");
+        debug!("Getting channel message stats: guild_id={}", guild_id);
+
+        let rows = sqlx::query(
+            "SELECT channel_id, CAST(SUM(messages) AS BIGINT) AS total FROM stats_channel_messages
+             WHERE guild_id = $1 AND day >= $2
+             GROUP BY channel_id
+             ORDER BY total DESC"
+        )
+            .bind(guild_id.to_string())
+            .bind(since)
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| (row.get("channel_id"), row.get("total"))).collect())
+    }
+
+    /// Get message counts per member since the given day, most active members first
+    pub async fn get_member_message_stats<G>(&self, guild_id: &G, since: &str, limit: i64)
+        -> Result<Vec<(String, i64)>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_member_message_stats");
+        debug!("Getting member message stats: guild_id={}", guild_id);
+
+        let rows = sqlx::query(
+            "SELECT user_id, CAST(SUM(messages) AS BIGINT) AS total FROM stats_member_messages
+             WHERE guild_id = $1 AND day >= $2
+             GROUP BY user_id
+             ORDER BY total DESC
+             LIMIT $3"
+        )
+            .bind(guild_id.to_string())
+            .bind(since)
+            .bind(limit)
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| (row.get("user_id"), row.get("total"))).collect())
+    }
+
+    /// Get the number of members that sent at least one message since the given day
+    pub async fn get_active_member_count<G>(&self, guild_id: &G, since: &str) -> Result<i64, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_active_member_count");
+        debug!("Getting active member count: guild_id={}", guild_id);
+
+        let row = sqlx::query(
+            "SELECT COUNT(DISTINCT user_id) AS active FROM stats_member_messages
+             WHERE guild_id = $1 AND day >= $2"
+        )
+            .bind(guild_id.to_string())
+            .bind(since)
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok(row.get("active"))
+    }
+
+    /// Get the number of (joins, leaves) since the given day
+    pub async fn get_join_stats<G>(&self, guild_id: &G, since: &str) -> Result<(i64, i64), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_join_stats");
+        debug!("Getting join stats: guild_id={}", guild_id);
+
+        let row = sqlx::query(
+            "SELECT CAST(COALESCE(SUM(joins), 0) AS BIGINT) AS joins, CAST(COALESCE(SUM(leaves), 0) AS BIGINT) AS leaves
+             FROM stats_joins
+             WHERE guild_id = $1 AND day >= $2"
+        )
+            .bind(guild_id.to_string())
+            .bind(since)
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok((row.get("joins"), row.get("leaves")))
+    }
+
+    /// Delete a user's member statistics in all guilds
+    pub async fn delete_member_stats<U>(&self, user_id: &U) -> Result<u64, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_member_stats");
+        debug!("Deleting member stats: user_id={}", user_id);
+
+        let result = sqlx::query(
+            "DELETE FROM stats_member_messages
+             WHERE user_id = $1"
+        )
+            .bind(user_id.to_string())
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // Leveling methods
+
+    /// Give a member XP for a message, unless they already got some less than `cooldown` seconds ago.
+    ///
+    /// Returns the member's new XP total, or None if they're still in cooldown.
+    pub async fn award_member_xp<G, U>(&self, guild_id: &G, user_id: &U, amount: i64, now: i64, cooldown: i64)
+        -> Result<Option<i64>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("award_member_xp");
+        debug!("Awarding member XP: guild_id={}, user_id={}", guild_id, user_id);
+
+        let row = sqlx::query(
+            "INSERT INTO member_xp (guild_id, user_id, xp, last_award)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (guild_id, user_id) DO UPDATE
+             SET xp = member_xp.xp + excluded.xp, last_award = excluded.last_award
+             WHERE excluded.last_award - member_xp.last_award >= $5
+             RETURNING xp"
+        )
+            .bind(guild_id.to_string())
+            .bind(user_id.to_string())
+            .bind(amount)
+            .bind(now)
+            .bind(cooldown)
+            .fetch_optional(&*self.pool)
+            .await?;
+
+        Ok(row.map(|row| row.get("xp")))
+    }
+
+    /// Add (or with a negative amount, remove) XP without touching the cooldown. Returns the new XP total.
+    pub async fn add_member_xp<G, U>(&self, guild_id: &G, user_id: &U, amount: i64) -> Result<i64, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("add_member_xp");
+        debug!("Adding member XP: guild_id={}, user_id={}, amount={}", guild_id, user_id, amount);
+
+        let row = sqlx::query(
+            "INSERT INTO member_xp (guild_id, user_id, xp)
+             VALUES ($1, $2, CASE WHEN $3 < 0 THEN 0 ELSE $3 END)
+             ON CONFLICT (guild_id, user_id) DO UPDATE
+             SET xp = CASE WHEN member_xp.xp + $3 < 0 THEN 0 ELSE member_xp.xp + $3 END
+             RETURNING xp"
+        )
+            .bind(guild_id.to_string())
+            .bind(user_id.to_string())
+            .bind(amount)
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok(row.get("xp"))
+    }
+
+    /// Set a member's XP total
+    pub async fn set_member_xp<G, U>(&self, guild_id: &G, user_id: &U, xp: i64) -> Result<(), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("set_member_xp");
+        debug!("Setting member XP: guild_id={}, user_id={}, xp={}", guild_id, user_id, xp);
+
+        sqlx::query(
+            "INSERT INTO member_xp (guild_id, user_id, xp)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (guild_id, user_id) DO UPDATE SET xp = excluded.xp"
+        )
+            .bind(guild_id.to_string())
+            .bind(user_id.to_string())
+            .bind(xp)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get a member's XP total and their rank in the guild, starting at 1
+    pub async fn get_member_xp<G, U>(&self, guild_id: &G, user_id: &U) -> Result<Option<(i64, i64)>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_member_xp");
+        debug!("Getting member XP: guild_id={}, user_id={}", guild_id, user_id);
+
+        let row = sqlx::query(
+            "SELECT xp, (SELECT COUNT(*) FROM member_xp AS other
+                         WHERE other.guild_id = member_xp.guild_id AND other.xp > member_xp.xp) + 1 AS rank
+             FROM member_xp
+             WHERE guild_id = $1 AND user_id = $2"
+        )
+            .bind(guild_id.to_string())
+            .bind(user_id.to_string())
+            .fetch_optional(&*self.pool)
+            .await?;
+
+        Ok(row.map(|row| (row.get("xp"), row.get("rank"))))
+    }
+
+    /// Delete a member's XP, or everyone's in the guild if no user is given. Returns the number of members reset.
+    pub async fn delete_member_xp<G, U>(&self, guild_id: &G, user_id: Option<&U>) -> Result<u64, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_member_xp");
+        debug!("Deleting member XP: guild_id={}", guild_id);
+
+        let result = match user_id {
+            Some(user_id) => sqlx::query(
+                "DELETE FROM member_xp
+                 WHERE guild_id = $1 AND user_id = $2"
+            )
+                .bind(guild_id.to_string())
+                .bind(user_id.to_string())
+                .execute(&*self.pool)
+                .await?,
+            None => sqlx::query(
+                "DELETE FROM member_xp
+                 WHERE guild_id = $1"
+            )
+                .bind(guild_id.to_string())
+                .execute(&*self.pool)
+                .await?,
+        };
+
+        Ok(result.rows_affected())
+    }
+
+    /// Get the members with the most XP in a guild as (user_id, xp)
+    pub async fn get_xp_leaderboard<G>(&self, guild_id: &G, limit: i64) -> Result<Vec<(String, i64)>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_xp_leaderboard");
+        debug!("Getting XP leaderboard: guild_id={}", guild_id);
+
+        let rows = sqlx::query(
+            "SELECT user_id, xp FROM member_xp
+             WHERE guild_id = $1 AND xp > 0
+             ORDER BY xp DESC
+             LIMIT $2"
+        )
+            .bind(guild_id.to_string())
+            .bind(limit)
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| (row.get("user_id"), row.get("xp"))).collect())
+    }
+
+    /// Give a role to members who reach a level. A role can only be the reward for one level.
+    pub async fn set_level_reward<G, R>(&self, guild_id: &G, level: i64, role_id: &R) -> Result<(), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        R: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("set_level_reward");
+        debug!("Setting level reward: guild_id={}, level={}, role_id={}", guild_id, level, role_id);
+
+        sqlx::query(
+            "INSERT INTO level_rewards (guild_id, level, role_id)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (guild_id, role_id) DO UPDATE SET level = excluded.level"
+        )
+            .bind(guild_id.to_string())
+            .bind(level)
+            .bind(role_id.to_string())
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Stop giving a role as level reward
+    pub async fn delete_level_reward<G, R>(&self, guild_id: &G, role_id: &R) -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        R: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_level_reward");
+        debug!("Deleting level reward: guild_id={}, role_id={}", guild_id, role_id);
+
+        let result = sqlx::query(
+            "DELETE FROM level_rewards
+             WHERE guild_id = $1 AND role_id = $2"
+        )
+            .bind(guild_id.to_string())
+            .bind(role_id.to_string())
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Get all level rewards of a guild as (level, role_id), lowest level first
+    pub async fn get_level_rewards<G>(&self, guild_id: &G) -> Result<Vec<(i64, String)>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_level_rewards");
+        debug!("Getting level rewards: guild_id={}", guild_id);
+
+        let rows = sqlx::query(
+            "SELECT level, role_id FROM level_rewards
+             WHERE guild_id = $1
+             ORDER BY level"
+        )
+            .bind(guild_id.to_string())
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| (row.get("level"), row.get("role_id"))).collect())
+    }
+
+    // Poll methods
+
+    /// Store a new poll and return its ID. The message ID is set separately once the poll has been posted.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_poll<G, C, U>(
+        &self,
+        guild_id: &G,
+        channel_id: &C,
+        author_id: &U,
+        question: &str,
+        options: &[String],
+        multi_choice: bool,
+        anonymous: bool,
+        ends_at: Option<i64>,
+    ) -> Result<i64, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        C: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("create_poll");
+        debug!("Creating poll: guild_id={}, channel_id={}", guild_id, channel_id);
+
+        let row = sqlx::query(
+            "INSERT INTO polls (guild_id, channel_id, author_id, question, options, multi_choice, anonymous, ends_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             RETURNING poll_id"
+        )
+            .bind(guild_id.to_string())
+            .bind(channel_id.to_string())
+            .bind(author_id.to_string())
+            .bind(question)
+            .bind(serde_json::to_string(options).unwrap_or_default())
+            .bind(multi_choice as i64)
+            .bind(anonymous as i64)
+            .bind(ends_at)
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok(row.get("poll_id"))
+    }
+
+    /// Set the ID of the message a poll was posted in
+    pub async fn set_poll_message<M>(&self, poll_id: i64, message_id: &M) -> Result<(), SqlxError>
+    where
+        M: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("set_poll_message");
+        debug!("Setting poll message: poll_id={}, message_id={}", poll_id, message_id);
+
+        sqlx::query(
+            "UPDATE polls SET message_id = $1
+             WHERE poll_id = $2"
+        )
+            .bind(message_id.to_string())
+            .bind(poll_id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get a poll by its ID
+    pub async fn get_poll(&self, poll_id: i64) -> Result<Option<Poll>, SqlxError> {
+        let _timer = metrics::time_query("get_poll");
+        debug!("Getting poll: poll_id={}", poll_id);
+
+        let row = sqlx::query(
+            "SELECT * FROM polls
+             WHERE poll_id = $1"
+        )
+            .bind(poll_id)
+            .fetch_optional(&*self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(Poll::from_row))
+    }
+
+    /// Get all open polls whose end time has passed
+    pub async fn get_due_polls(&self, now: i64) -> Result<Vec<Poll>, SqlxError> {
+        let _timer = metrics::time_query("get_due_polls");
+        let rows = sqlx::query(
+            "SELECT * FROM polls
+             WHERE closed = 0 AND ends_at IS NOT NULL AND ends_at <= $1"
+        )
+            .bind(now)
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(Poll::from_row).collect())
+    }
+
+    /// Mark a poll as closed. Returns false if it already was.
+    pub async fn close_poll(&self, poll_id: i64) -> Result<bool, SqlxError> {
+        let _timer = metrics::time_query("close_poll");
+        debug!("Closing poll: poll_id={}", poll_id);
+
+        let result = sqlx::query(
+            "UPDATE polls SET closed = 1
+             WHERE poll_id = $1 AND closed = 0"
+        )
+            .bind(poll_id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete a poll and its votes, in one transaction
+    pub async fn delete_poll(&self, poll_id: i64) -> Result<(), SqlxError> {
+        let _timer = metrics::time_query("delete_poll");
+        debug!("Deleting poll: poll_id={}", poll_id);
+
+        let mut transaction = self.pool.begin().await?;
+        for statement in ["DELETE FROM poll_votes WHERE poll_id = $1", "DELETE FROM polls WHERE poll_id = $1"] {
+            sqlx::query(statement)
+                .bind(poll_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Get all votes of a poll as (user_id, option index)
+    pub async fn get_poll_votes(&self, poll_id: i64) -> Result<Vec<(String, i64)>, SqlxError> {
+        let _timer = metrics::time_query("get_poll_votes");
+        debug!("Getting poll votes: poll_id={}", poll_id);
+
+        let rows = sqlx::query(
+            "SELECT user_id, option FROM poll_votes
+             WHERE poll_id = $1"
+        )
+            .bind(poll_id)
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| (row.get("user_id"), row.get("option"))).collect())
+    }
+
+    /// Replace a user's votes on a poll. An empty list removes their vote.
+    pub async fn set_poll_votes<U>(&self, poll_id: i64, user_id: &U, options: &[i64]) -> Result<(), SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("set_poll_votes");
+        debug!("Setting poll votes: poll_id={}, user_id={}", poll_id, user_id);
+
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM poll_votes
+             WHERE poll_id = $1 AND user_id = $2"
+        )
+            .bind(poll_id)
+            .bind(user_id.to_string())
+            .execute(&mut *transaction)
+            .await?;
+        for option in options {
+            sqlx::query(
+                "INSERT INTO poll_votes (poll_id, user_id, option)
+                 VALUES ($1, $2, $3)
+                 ON CONFLICT DO NOTHING"
+            )
+                .bind(poll_id)
+                .bind(user_id.to_string())
+                .bind(option)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    // Giveaway methods
+
+    /// Store a new giveaway and return its ID. The message ID is set separately once it has been posted.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_giveaway<G, C, U>(
+        &self,
+        guild_id: &G,
+        channel_id: &C,
+        host_id: &U,
+        prize: &str,
+        winner_count: i64,
+        required_role: Option<String>,
+        min_member_days: Option<i64>,
+        ends_at: i64,
+    ) -> Result<i64, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        C: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("create_giveaway");
+        debug!("Creating giveaway: guild_id={}, channel_id={}", guild_id, channel_id);
+
+        let row = sqlx::query(
+            "INSERT INTO giveaways (guild_id, channel_id, host_id, prize, winner_count, required_role, min_member_days, ends_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             RETURNING giveaway_id"
+        )
+            .bind(guild_id.to_string())
+            .bind(channel_id.to_string())
+            .bind(host_id.to_string())
+            .bind(prize)
+            .bind(winner_count)
+            .bind(required_role)
+            .bind(min_member_days)
+            .bind(ends_at)
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok(row.get("giveaway_id"))
+    }
+
+    /// Set the ID of the message a giveaway was posted in
+    pub async fn set_giveaway_message<M>(&self, giveaway_id: i64, message_id: &M) -> Result<(), SqlxError>
+    where
+        M: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("set_giveaway_message");
+        debug!("Setting giveaway message: giveaway_id={}, message_id={}", giveaway_id, message_id);
+
+        sqlx::query(
+            "UPDATE giveaways SET message_id = $1
+             WHERE giveaway_id = $2"
+        )
+            .bind(message_id.to_string())
+            .bind(giveaway_id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get a giveaway by its ID
+    pub async fn get_giveaway(&self, giveaway_id: i64) -> Result<Option<Giveaway>, SqlxError> {
+        let _timer = metrics::time_query("get_giveaway");
+        debug!("Getting giveaway: giveaway_id={}", giveaway_id);
+
+        let row = sqlx::query(
+            "SELECT * FROM giveaways
+             WHERE giveaway_id = $1"
+        )
+            .bind(giveaway_id)
+            .fetch_optional(&*self.pool)
+            .await?;
+
+        Ok(row.as_ref().map(Giveaway::from_row))
+    }
+
+    /// Get all running giveaways whose end time has passed
+    pub async fn get_due_giveaways(&self, now: i64) -> Result<Vec<Giveaway>, SqlxError> {
+        let _timer = metrics::time_query("get_due_giveaways");
+        let rows = sqlx::query(
+            "SELECT * FROM giveaways
+             WHERE ended = 0 AND ends_at <= $1"
+        )
+            .bind(now)
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(Giveaway::from_row).collect())
+    }
+
+    /// Mark a giveaway as ended. Returns false if it already was.
+    pub async fn end_giveaway(&self, giveaway_id: i64) -> Result<bool, SqlxError> {
+        let _timer = metrics::time_query("end_giveaway");
+        debug!("Ending giveaway: giveaway_id={}", giveaway_id);
+
+        let result = sqlx::query(
+            "UPDATE giveaways SET ended = 1
+             WHERE giveaway_id = $1 AND ended = 0"
+        )
+            .bind(giveaway_id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Add winners to a giveaway's list of winners
+    pub async fn add_giveaway_winners(&self, giveaway_id: i64, winners: &[String]) -> Result<(), SqlxError> {
+        let _timer = metrics::time_query("add_giveaway_winners");
+        debug!("Adding giveaway winners: giveaway_id={}", giveaway_id);
+
+        sqlx::query(
+            "UPDATE giveaways SET winners = CASE WHEN winners = '' THEN $1 ELSE winners || ',' || $1 END
+             WHERE giveaway_id = $2"
+        )
+            .bind(winners.join(","))
+            .bind(giveaway_id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Enter a user into a giveaway, or withdraw them if they already entered. Returns true if they're now entered.
+    pub async fn toggle_giveaway_entry<U>(&self, giveaway_id: i64, user_id: &U) -> Result<bool, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("toggle_giveaway_entry");
+        debug!("Toggling giveaway entry: giveaway_id={}, user_id={}", giveaway_id, user_id);
+
+        let result = sqlx::query(
+            "DELETE FROM giveaway_entries
+             WHERE giveaway_id = $1 AND user_id = $2"
+        )
+            .bind(giveaway_id)
+            .bind(user_id.to_string())
+            .execute(&*self.pool)
+            .await?;
+        if result.rows_affected() > 0 {
+            return Ok(false);
+        }
+
+        sqlx::query(
+            "INSERT INTO giveaway_entries (giveaway_id, user_id)
+             VALUES ($1, $2)
+             ON CONFLICT DO NOTHING"
+        )
+            .bind(giveaway_id)
+            .bind(user_id.to_string())
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Get the IDs of everyone who entered a giveaway
+    pub async fn get_giveaway_entries(&self, giveaway_id: i64) -> Result<Vec<String>, SqlxError> {
+        let _timer = metrics::time_query("get_giveaway_entries");
+        debug!("Getting giveaway entries: giveaway_id={}", giveaway_id);
+
+        let rows = sqlx::query(
+            "SELECT user_id FROM giveaway_entries
+             WHERE giveaway_id = $1"
+        )
+            .bind(giveaway_id)
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| row.get("user_id")).collect())
+    }
+
+    /// Remember which starboard post belongs to a message
+    pub async fn set_starboard_entry<G, M, C, S>(&self, guild_id: &G, message_id: &M, starboard_channel_id: &C, starboard_message_id: &S)
+        -> Result<(), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        M: Display + Send + Sync + ?Sized,
+        C: Display + Send + Sync + ?Sized,
+        S: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("set_starboard_entry");
+        debug!("Setting starboard entry: message_id={}, starboard_message_id={}", message_id, starboard_message_id);
+
+        sqlx::query(
+            "INSERT INTO starboard (message_id, guild_id, starboard_channel_id, starboard_message_id)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT(message_id) DO UPDATE SET
+                starboard_channel_id = excluded.starboard_channel_id,
+                starboard_message_id = excluded.starboard_message_id"
+        )
+            .bind(message_id.to_string())
+            .bind(guild_id.to_string())
+            .bind(starboard_channel_id.to_string())
+            .bind(starboard_message_id.to_string())
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the channel and message ID of the starboard post of a message, if it has one
+    pub async fn get_starboard_entry<M>(&self, message_id: &M) -> Result<Option<(String, String)>, SqlxError>
+    where
+        M: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_starboard_entry");
+        debug!("Getting starboard entry: message_id={}", message_id);
+
+        let row = sqlx::query(
+            "SELECT starboard_channel_id, starboard_message_id FROM starboard
+             WHERE message_id = $1"
+        )
+            .bind(message_id.to_string())
+            .fetch_optional(&*self.pool)
+            .await?;
+
+        Ok(row.map(|r| (r.get("starboard_channel_id"), r.get("starboard_message_id"))))
+    }
+
+    /// Forget the starboard post of a message
+    pub async fn delete_starboard_entry<M>(&self, message_id: &M) -> Result<(), SqlxError>
+    where
+        M: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_starboard_entry");
+        debug!("Deleting starboard entry: message_id={}", message_id);
+
+        sqlx::query(
+            "DELETE FROM starboard
+             WHERE message_id = $1"
+        )
+            .bind(message_id.to_string())
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Create a tag. Returns false if a tag with that name already exists.
+    pub async fn create_tag<G, U>(&self, guild_id: &G, name: &str, content: &str, embed: bool, author_id: &U)
+        -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("create_tag");
+        debug!("Creating tag: guild_id={}, name={}", guild_id, name);
+
+        let result = sqlx::query(
+            "INSERT INTO tags (guild_id, name, content, embed, author_id, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT DO NOTHING"
+        )
+            .bind(guild_id.to_string())
+            .bind(name)
+            .bind(content)
+            .bind(embed as i64)
+            .bind(author_id.to_string())
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Change the content of a tag. Returns false if it doesn't exist.
+    pub async fn edit_tag<G>(&self, guild_id: &G, name: &str, content: &str, embed: bool) -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("edit_tag");
+        debug!("Editing tag: guild_id={}, name={}", guild_id, name);
+
+        let result = sqlx::query(
+            "UPDATE tags SET content = $1, embed = $2
+             WHERE guild_id = $3 AND name = $4"
+        )
+            .bind(content)
+            .bind(embed as i64)
+            .bind(guild_id.to_string())
+            .bind(name)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete a tag. Returns false if it doesn't exist.
+    pub async fn delete_tag<G>(&self, guild_id: &G, name: &str) -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_tag");
+        debug!("Deleting tag: guild_id={}, name={}", guild_id, name);
+
+        let result = sqlx::query(
+            "DELETE FROM tags
+             WHERE guild_id = $1 AND name = $2"
+        )
+            .bind(guild_id.to_string())
+            .bind(name)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Get a tag by name
+    pub async fn get_tag<G>(&self, guild_id: &G, name: &str) -> Result<Option<Tag>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_tag");
+        debug!("Getting tag: guild_id={}, name={}", guild_id, name);
+
+        let row = sqlx::query(
+            "SELECT * FROM tags
+             WHERE guild_id = $1 AND name = $2"
+        )
+            .bind(guild_id.to_string())
+            .bind(name)
+            .fetch_optional(&*self.pool)
+            .await?;
+
+        Ok(row.map(|r| Tag::from_row(&r)))
+    }
+
+    /// Get the names of a guild's tags that start with `prefix`, in alphabetical order
+    pub async fn get_tag_names<G>(&self, guild_id: &G, prefix: &str, limit: i64) -> Result<Vec<String>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_tag_names");
+        debug!("Getting tag names: guild_id={}, prefix={}", guild_id, prefix);
+
+        let rows = sqlx::query(
+            "SELECT name FROM tags
+             WHERE guild_id = $1 AND substr(name, 1, length($2)) = $2
+             ORDER BY name
+             LIMIT $3"
+        )
+            .bind(guild_id.to_string())
+            .bind(prefix)
+            .bind(limit)
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| row.get("name")).collect())
+    }
+
+    /// Count a use of a tag
+    pub async fn increment_tag_uses<G>(&self, guild_id: &G, name: &str) -> Result<(), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("increment_tag_uses");
+        debug!("Incrementing tag uses: guild_id={}, name={}", guild_id, name);
+
+        sqlx::query(
+            "UPDATE tags SET uses = uses + 1
+             WHERE guild_id = $1 AND name = $2"
+        )
+            .bind(guild_id.to_string())
+            .bind(name)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Add a custom fortune to a guild and return its ID
+    pub async fn add_custom_fortune<G, U>(&self, guild_id: &G, text: &str, author_id: &U) -> Result<i64, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("add_custom_fortune");
+        debug!("Adding custom fortune: guild_id={}", guild_id);
+
+        let row = sqlx::query(
+            "INSERT INTO custom_fortunes (guild_id, text, author_id)
+             VALUES ($1, $2, $3)
+             RETURNING fortune_id"
+        )
+            .bind(guild_id.to_string())
+            .bind(text)
+            .bind(author_id.to_string())
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok(row.get("fortune_id"))
+    }
 
-        debug!("Getting user value: user_id={}, key={}", user_id, key);
+    /// Delete a custom fortune of a guild. Returns false if it doesn't exist.
+    pub async fn delete_custom_fortune<G>(&self, guild_id: &G, fortune_id: i64) -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("delete_custom_fortune");
+        debug!("Deleting custom fortune: guild_id={}, fortune_id={}", guild_id, fortune_id);
 
         let result = sqlx::query(
-            "SELECT value FROM user_kv
-             WHERE user_id = ? AND key = ?"
+            "DELETE FROM custom_fortunes
+             WHERE guild_id = $1 AND fortune_id = $2"
         )
-            .bind(user_id.to_string())
-            .bind(key.to_string())
-            .fetch_optional(&*self.pool)
+            .bind(guild_id.to_string())
+            .bind(fortune_id)
+            .execute(&*self.pool)
             .await?;
 
-        Ok(result.map(|row| row.get("value")))
+        Ok(result.rows_affected() > 0)
     }
 
-    /// Delete a key-value pair for a specific user
-    pub async fn delete_user_value<U, K>(&self, user_id: &U, key: &K) -> Result<bool, SqlxError>
+    /// Get the ID and text of all custom fortunes of a guild
+    pub async fn get_custom_fortunes<G>(&self, guild_id: &G) -> Result<Vec<(i64, String)>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_custom_fortunes");
+        debug!("Getting custom fortunes: guild_id={}", guild_id);
+
+        let rows = sqlx::query(
+            "SELECT fortune_id, text FROM custom_fortunes
+             WHERE guild_id = $1
+             ORDER BY fortune_id"
+        )
+            .bind(guild_id.to_string())
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| (row.get("fortune_id"), row.get("text"))).collect())
+    }
+
+    /// Remember that a user received a fortune
+    pub async fn add_fortune_history<U>(&self, user_id: &U, fortune_id: &str, text: &str, received_at: i64)
+        -> Result<(), SqlxError>
     where
         U: Display + Send + Sync + ?Sized,
-        K: Display + Send + Sync + ?Sized,
     {
-        debug!("Deleting user value: user_id={}, key={}", user_id, key);
+        let _timer = metrics::time_query("add_fortune_history");
+        debug!("Adding fortune history: user_id={}, fortune_id={}", user_id, fortune_id);
 
-        let result = sqlx::query(
-            "DELETE FROM user_kv
-             WHERE user_id = ? AND key = ?"
+        sqlx::query(
+            "INSERT INTO fortune_history (user_id, fortune_id, text, received_at)
+             VALUES ($1, $2, $3, $4)"
         )
             .bind(user_id.to_string())
-            .bind(key.to_string())
+            .bind(fortune_id)
+            .bind(text)
+            .bind(received_at)
             .execute(&*self.pool)
             .await?;
 
-        // Return true if a row was deleted
-        Ok(result.rows_affected() > 0)
+        Ok(())
     }
 
-    /// Get all key-value pairs for a specific user
-    pub async fn get_all_user_values<U>(&self, user_id: &U) -> Result<Vec<(String, String)>, SqlxError>
+    /// Get the text and time of the fortunes a user received most recently, newest first
+    pub async fn get_fortune_history<U>(&self, user_id: &U, limit: i64) -> Result<Vec<(String, i64)>, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+    {
+        let _timer = metrics::time_query("get_fortune_history");
+        debug!("Getting fortune history: user_id={}", user_id);
+
+        let rows = sqlx::query(
+            "SELECT text, received_at FROM fortune_history
+             WHERE user_id = $1
+             ORDER BY received_at DESC, rowid DESC
+             LIMIT $2"
+        )
+            .bind(user_id.to_string())
+            .bind(limit)
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(rows.iter().map(|row| (row.get("text"), row.get("received_at"))).collect())
+    }
+
+    /// Get the IDs of the fortunes a user received since a point in time
+    pub async fn get_seen_fortunes<U>(&self, user_id: &U, since: i64) -> Result<Vec<String>, SqlxError>
     where
         U: Display + Send + Sync + ?Sized,
     {
-        debug!("Getting all user values: user_id={}", user_id);
+        let _timer = metrics::time_query("get_seen_fortunes");
+        debug!("Getting seen fortunes: user_id={}, since={}", user_id, since);
 
         let rows = sqlx::query(
-            "SELECT key, value FROM user_kv
-             WHERE user_id = ?"
+            "SELECT DISTINCT fortune_id FROM fortune_history
+             WHERE user_id = $1 AND received_at >= $2"
         )
             .bind(user_id.to_string())
+            .bind(since)
             .fetch_all(&*self.pool)
             .await?;
 
-        let mut values = Vec::with_capacity(rows.len());
-        for row in rows {
-            values.push((row.get("key"), row.get("value")));
+        Ok(rows.iter().map(|row| row.get("fortune_id")).collect())
+    }
+}
+
+/// Key-value changes that are applied all at once when committed, or not at all when dropped
+pub struct KvTransaction {
+    transaction: Transaction<'static, Any>,
+}
+
+impl KvTransaction {
+    pub async fn get_guild_value<G, K>(&mut self, guild_id: &G, key: &K) -> Result<Option<String>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        kv_get(&mut *self.transaction, KvScope::Guild, &[guild_id.to_string()], key).await
+    }
+
+    pub async fn set_guild_value<G, K, V>(&mut self, guild_id: &G, key: &K, value: &V) -> Result<(), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+        V: Display + Send + Sync + ?Sized,
+    {
+        kv_set(&mut *self.transaction, KvScope::Guild, &[guild_id.to_string()], key, value).await?;
+        Ok(())
+    }
+
+    pub async fn delete_guild_value<G, K>(&mut self, guild_id: &G, key: &K) -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        kv_delete(&mut *self.transaction, KvScope::Guild, &[guild_id.to_string()], key).await
+    }
+
+    pub async fn get_user_value<U, K>(&mut self, user_id: &U, key: &K) -> Result<Option<String>, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        kv_get(&mut *self.transaction, KvScope::User, &[user_id.to_string()], key).await
+    }
+
+    pub async fn set_user_value<U, K, V>(&mut self, user_id: &U, key: &K, value: &V) -> Result<(), SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+        V: Display + Send + Sync + ?Sized,
+    {
+        kv_set(&mut *self.transaction, KvScope::User, &[user_id.to_string()], key, value).await?;
+        Ok(())
+    }
+
+    pub async fn delete_user_value<U, K>(&mut self, user_id: &U, key: &K) -> Result<bool, SqlxError>
+    where
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        kv_delete(&mut *self.transaction, KvScope::User, &[user_id.to_string()], key).await
+    }
+
+    pub async fn get_guild_value_typed<T, G, K>(&mut self, guild_id: &G, key: &K) -> Result<Option<T>, KvError>
+    where
+        T: DeserializeOwned,
+        G: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let value = self.get_guild_value(guild_id, key).await?;
+        from_json(key, value)
+    }
+
+    pub async fn set_guild_value_typed<T, G, K>(&mut self, guild_id: &G, key: &K, value: &T) -> Result<(), KvError>
+    where
+        T: Serialize + ?Sized,
+        G: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let value = to_json(key, value)?;
+        Ok(self.set_guild_value(guild_id, key, &value).await?)
+    }
+
+    pub async fn get_user_value_typed<T, U, K>(&mut self, user_id: &U, key: &K) -> Result<Option<T>, KvError>
+    where
+        T: DeserializeOwned,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let value = self.get_user_value(user_id, key).await?;
+        from_json(key, value)
+    }
+
+    pub async fn set_user_value_typed<T, U, K>(&mut self, user_id: &U, key: &K, value: &T) -> Result<(), KvError>
+    where
+        T: Serialize + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        let value = to_json(key, value)?;
+        Ok(self.set_user_value(user_id, key, &value).await?)
+    }
+
+    pub async fn get_member_value<G, U, K>(&mut self, guild_id: &G, user_id: &U, key: &K)
+        -> Result<Option<String>, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        kv_get(&mut *self.transaction, KvScope::Member, &[guild_id.to_string(), user_id.to_string()], key).await
+    }
+
+    pub async fn set_member_value<G, U, K, V>(&mut self, guild_id: &G, user_id: &U, key: &K, value: &V)
+        -> Result<(), SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+        V: Display + Send + Sync + ?Sized,
+    {
+        kv_set(&mut *self.transaction, KvScope::Member, &[guild_id.to_string(), user_id.to_string()], key, value).await?;
+        Ok(())
+    }
+
+    pub async fn delete_member_value<G, U, K>(&mut self, guild_id: &G, user_id: &U, key: &K) -> Result<bool, SqlxError>
+    where
+        G: Display + Send + Sync + ?Sized,
+        U: Display + Send + Sync + ?Sized,
+        K: Display + Send + Sync + ?Sized,
+    {
+        kv_delete(&mut *self.transaction, KvScope::Member, &[guild_id.to_string(), user_id.to_string()], key).await
+    }
+
+    /// Apply every change made in this transaction
+    pub async fn commit(self) -> Result<(), SqlxError> {
+        let _timer = metrics::time_query("kv_transaction_commit");
+        self.transaction.commit().await
+    }
+}
+
+/// Parse a stored value as JSON of the expected type. Plain numbers stored before typed values existed are valid JSON.
+fn from_json<T, K>(key: &K, value: Option<String>) -> Result<Option<T>, KvError>
+where
+    T: DeserializeOwned,
+    K: Display + ?Sized,
+{
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    match serde_json::from_str(&value) {
+        Ok(v) => Ok(Some(v)),
+        Err(error) => Err(KvError::Corrupt { key: key.to_string(), value, error }),
+    }
+}
+
+fn to_json<T, K>(key: &K, value: &T) -> Result<String, KvError>
+where
+    T: Serialize + ?Sized,
+    K: Display + ?Sized,
+{
+    serde_json::to_string(value).map_err(|error| KvError::Unserializable { key: key.to_string(), error })
+}
+
+/// The key-value tables, which only differ in what the values belong to
+#[derive(Debug, Clone, Copy)]
+enum KvScope {
+    Guild,
+    User,
+    /// A user within a guild
+    Member,
+}
+
+impl KvScope {
+    fn name(self) -> &'static str {
+        match self {
+            KvScope::Guild => "guild",
+            KvScope::User => "user",
+            KvScope::Member => "member",
+        }
+    }
+
+    fn table(self) -> &'static str {
+        match self {
+            KvScope::Guild => "guild_kv",
+            KvScope::User => "user_kv",
+            KvScope::Member => "member_kv",
+        }
+    }
+
+    fn id_columns(self) -> &'static [&'static str] {
+        match self {
+            KvScope::Guild => &["guild_id"],
+            KvScope::User => &["user_id"],
+            KvScope::Member => &["guild_id", "user_id"],
+        }
+    }
+
+    /// Fill in a query template. `{ids}` is the ID columns, `{id_params}` their parameters and `{match}` compares
+    /// them to their parameters. `{1}`, `{2}` and so on are the parameters after the IDs. Everything filled in comes
+    /// from this enum, never from input.
+    fn sql(self, template: &str) -> AssertSqlSafe<String> {
+        let columns = self.id_columns();
+        let mut sql = template
+            .replace("{table}", self.table())
+            .replace("{ids}", &columns.join(", "))
+            .replace("{id_params}", &(1..=columns.len()).map(|i| format!("${}", i)).collect::<Vec<String>>().join(", "))
+            .replace(
+                "{match}",
+                &columns.iter().enumerate().map(|(i, c)| format!("{} = ${}", c, i + 1)).collect::<Vec<String>>()
+                    .join(" AND "),
+            );
+        for i in 1..=3 {
+            sql = sql.replace(&format!("{{{}}}", i), &format!("${}", columns.len() + i));
+        }
+        AssertSqlSafe(sql)
+    }
+
+    /// Start a query with the IDs bound
+    fn query(self, template: &str, ids: &[String]) -> Query<'static, Any, AnyArguments> {
+        ids.iter().fold(sqlx::query(self.sql(template)), |query, id| query.bind(id.clone()))
+    }
+}
+
+async fn kv_get<'e, E, K>(executor: E, scope: KvScope, ids: &[String], key: &K) -> Result<Option<String>, SqlxError>
+where
+    E: AnyExecutor<'e>,
+    K: Display + Send + Sync + ?Sized,
+{
+    debug!("Getting {} value: id={}, key={}", scope.name(), ids.join("/"), key);
+    let result = scope.query("SELECT value FROM {table} WHERE {match} AND key = {1}", ids)
+        .bind(key.to_string())
+        .fetch_optional(executor)
+        .await?;
+
+    Ok(result.map(|row| row.get("value")))
+}
+
+/// Insert or update a value in one statement. Returns false if it already had this value, which skips the write.
+async fn kv_set<'e, E, K, V>(executor: E, scope: KvScope, ids: &[String], key: &K, value: &V) -> Result<bool, SqlxError>
+where
+    E: AnyExecutor<'e>,
+    K: Display + Send + Sync + ?Sized,
+    V: Display + Send + Sync + ?Sized,
+{
+    debug!("Setting {} value: id={}, key={}", scope.name(), ids.join("/"), key);
+    let result = scope.query(
+        "INSERT INTO {table} ({ids}, key, value, updated_at)
+         VALUES ({id_params}, {1}, {2}, CURRENT_TIMESTAMP)
+         ON CONFLICT ({ids}, key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+         WHERE {table}.value <> excluded.value",
+        ids,
+    )
+        .bind(key.to_string())
+        .bind(value.to_string())
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn kv_compare_and_set<'e, E, K, V>(
+    executor: E,
+    scope: KvScope,
+    ids: &[String],
+    key: &K,
+    expected: Option<&str>,
+    value: &V,
+) -> Result<bool, SqlxError>
+where
+    E: AnyExecutor<'e>,
+    K: Display + Send + Sync + ?Sized,
+    V: Display + Send + Sync + ?Sized,
+{
+    debug!("Compare-and-set {} value: id={}, key={}", scope.name(), ids.join("/"), key);
+    let query = match expected {
+        Some(expected) => scope.query(
+            "UPDATE {table} SET value = {2}, updated_at = CURRENT_TIMESTAMP
+             WHERE {match} AND key = {1} AND value = {3}",
+            ids,
+        )
+            .bind(key.to_string())
+            .bind(value.to_string())
+            .bind(expected.to_string()),
+        None => scope.query(
+            "INSERT INTO {table} ({ids}, key, value, updated_at)
+             VALUES ({id_params}, {1}, {2}, CURRENT_TIMESTAMP)
+             ON CONFLICT DO NOTHING",
+            ids,
+        )
+            .bind(key.to_string())
+            .bind(value.to_string()),
+    };
+    let result = query.execute(executor).await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn kv_compare_and_delete<'e, E, K>(executor: E, scope: KvScope, ids: &[String], key: &K, expected: &str)
+    -> Result<bool, SqlxError>
+where
+    E: AnyExecutor<'e>,
+    K: Display + Send + Sync + ?Sized,
+{
+    debug!("Compare-and-delete {} value: id={}, key={}", scope.name(), ids.join("/"), key);
+    let result = scope.query("DELETE FROM {table} WHERE {match} AND key = {1} AND value = {2}", ids)
+        .bind(key.to_string())
+        .bind(expected.to_string())
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn kv_delete<'e, E, K>(executor: E, scope: KvScope, ids: &[String], key: &K) -> Result<bool, SqlxError>
+where
+    E: AnyExecutor<'e>,
+    K: Display + Send + Sync + ?Sized,
+{
+    debug!("Deleting {} value: id={}, key={}", scope.name(), ids.join("/"), key);
+    let result = scope.query("DELETE FROM {table} WHERE {match} AND key = {1}", ids)
+        .bind(key.to_string())
+        .execute(executor)
+        .await?;
+
+    // Return true if a row was deleted
+    Ok(result.rows_affected() > 0)
+}
+
+/// Every key-value pair whose key starts with the prefix, which may be empty to get them all
+async fn kv_get_prefix<'e, E>(executor: E, scope: KvScope, ids: &[String], prefix: &str)
+    -> Result<Vec<(String, String)>, SqlxError>
+where
+    E: AnyExecutor<'e>,
+{
+    debug!("Getting {} values: id={}, prefix={}", scope.name(), ids.join("/"), prefix);
+    let rows = scope.query(
+        "SELECT key, value FROM {table}
+         WHERE {match} AND substr(key, 1, length({1})) = {1}
+         ORDER BY key",
+        ids,
+    )
+        .bind(prefix.to_string())
+        .fetch_all(executor)
+        .await?;
+
+    Ok(rows.iter().map(|row| (row.get("key"), row.get("value"))).collect())
+}
+
+async fn kv_delete_prefix<'e, E>(executor: E, scope: KvScope, ids: &[String], prefix: &str) -> Result<u64, SqlxError>
+where
+    E: AnyExecutor<'e>,
+{
+    debug!("Deleting {} values: id={}, prefix={}", scope.name(), ids.join("/"), prefix);
+    let result = scope.query("DELETE FROM {table} WHERE {match} AND substr(key, 1, length({1})) = {1}", ids)
+        .bind(prefix.to_string())
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// PostgreSQL tests run against this database when it's set, and are skipped otherwise
+    const POSTGRES_URL_VAR: &str = "TEST_POSTGRES_URL";
+
+    /// Tests creating the tables at the same time can fail on PostgreSQL, so they take turns
+    #[cfg(feature = "postgres")]
+    static POSTGRES_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    /// An ID no other test uses, so tests don't see each other's rows in a shared PostgreSQL database
+    fn unique_id() -> String {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        format!("{}{}", chrono::Utc::now().timestamp_micros(), NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Run a test against an in-memory SQLite database, and against PostgreSQL if it's configured
+    macro_rules! database_test {
+        ($name:ident) => {
+            mod $name {
+                #[tokio::test]
+                async fn sqlite() {
+                    super::$name(&super::Database::in_memory().await.unwrap()).await;
+                }
+
+                #[cfg(feature = "postgres")]
+                #[tokio::test]
+                async fn postgres() {
+                    let Ok(url) = std::env::var(super::POSTGRES_URL_VAR) else {
+                        return;
+                    };
+                    let _guard = super::POSTGRES_LOCK.lock().await;
+                    let database = super::Database::new(&url).await.unwrap();
+                    super::$name(&database).await;
+                    database.close().await;
+                }
+            }
+        };
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn ddl_translation() {
+        let sql = "CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, n INTEGER, t TIMESTAMP DEFAULT CURRENT_TIMESTAMP)";
+        assert_eq!(Backend::Sqlite.ddl(sql).0, sql);
+        assert_eq!(
+            Backend::Postgres.ddl(sql).0,
+            "CREATE TABLE t (id BIGSERIAL PRIMARY KEY, n BIGINT, t TEXT DEFAULT CURRENT_TIMESTAMP)"
+        );
+        assert_eq!(Backend::of("postgres://u@h/db"), Backend::Postgres);
+        assert_eq!(Backend::of("postgresql://u@h/db"), Backend::Postgres);
+        assert_eq!(Backend::of("bot.sqlite"), Backend::Sqlite);
+        assert_eq!(redact_location("postgres://u:secret@h/db"), "postgres://u:***@h/db");
+    }
+
+    async fn guild_kv(database: &Database) {
+        let (guild, other) = (unique_id(), unique_id());
+        assert_eq!(database.get_guild_value(&guild, "a").await.unwrap(), None);
+        database.set_guild_value(&guild, "b", "1").await.unwrap();
+        database.set_guild_value(&guild, "a", "1").await.unwrap();
+        database.set_guild_value(&guild, "a", "2").await.unwrap();
+        database.set_guild_values(&guild, &[("c", "3"), ("d", "4")]).await.unwrap();
+        database.set_guild_value(&other, "a", "other").await.unwrap();
+
+        assert_eq!(database.get_guild_value(&guild, "a").await.unwrap().as_deref(), Some("2"));
+        assert_eq!(
+            database.get_all_guild_values(&guild).await.unwrap(),
+            pairs(&[("a", "2"), ("b", "1"), ("c", "3"), ("d", "4")])
+        );
+        assert!(database.delete_guild_value(&guild, "a").await.unwrap());
+        assert!(!database.delete_guild_value(&guild, "a").await.unwrap());
+        assert_eq!(database.get_guild_value(&guild, "a").await.unwrap(), None);
+        assert_eq!(database.get_guild_value(&other, "a").await.unwrap().as_deref(), Some("other"));
+    }
+    database_test!(guild_kv);
+
+    async fn user_kv(database: &Database) {
+        let (user, other) = (unique_id(), unique_id());
+        assert_eq!(database.get_user_value(&user, "a").await.unwrap(), None);
+        database.set_user_value(&user, "a", "1").await.unwrap();
+        database.set_user_value(&user, "a", "2").await.unwrap();
+        database.set_user_values(&user, &[("b", "3")]).await.unwrap();
+        database.set_user_value(&other, "a", "other").await.unwrap();
+
+        assert_eq!(database.get_user_value(&user, "a").await.unwrap().as_deref(), Some("2"));
+        assert_eq!(database.get_all_user_values(&user).await.unwrap(), pairs(&[("a", "2"), ("b", "3")]));
+        assert!(database.delete_user_value(&user, "a").await.unwrap());
+        assert!(!database.delete_user_value(&user, "a").await.unwrap());
+        assert_eq!(database.get_all_user_values(&user).await.unwrap(), pairs(&[("b", "3")]));
+        assert_eq!(database.get_user_value(&other, "a").await.unwrap().as_deref(), Some("other"));
+    }
+    database_test!(user_kv);
+
+    async fn member_kv(database: &Database) {
+        let (guild, other_guild, user, other_user) = (unique_id(), unique_id(), unique_id(), unique_id());
+        database.set_member_value(&guild, &user, "a", "1").await.unwrap();
+        database.set_member_value(&guild, &user, "b", "2").await.unwrap();
+        database.set_member_value(&guild, &other_user, "a", "3").await.unwrap();
+        database.set_member_value(&other_guild, &user, "a", "4").await.unwrap();
+
+        assert_eq!(database.get_member_value(&guild, &user, "a").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(database.get_all_member_values(&guild, &user).await.unwrap(), pairs(&[("a", "1"), ("b", "2")]));
+        assert!(database.delete_member_value(&guild, &user, "a").await.unwrap());
+        assert!(!database.delete_member_value(&guild, &user, "a").await.unwrap());
+        assert_eq!(database.get_member_value(&guild, &user, "a").await.unwrap(), None);
+        assert_eq!(database.get_member_value(&guild, &other_user, "a").await.unwrap().as_deref(), Some("3"));
+
+        let key = format!("due.{}", unique_id());
+        database.set_member_value(&other_guild, &other_user, &key, "5").await.unwrap();
+        assert_eq!(
+            database.get_members_with_value(&key).await.unwrap(),
+            vec![(other_guild.clone(), other_user.clone(), "5".to_string())]
+        );
+        assert!(database.delete_member_value(&other_guild, &other_user, &key).await.unwrap());
+
+        assert_eq!(database.delete_guild_member_values(&guild).await.unwrap(), 2);
+        assert_eq!(database.get_all_member_values(&guild, &other_user).await.unwrap(), vec![]);
+        assert_eq!(database.get_member_value(&other_guild, &user, "a").await.unwrap().as_deref(), Some("4"));
+    }
+    database_test!(member_kv);
+
+    async fn compare_and_set(database: &Database) {
+        let (guild, user) = (unique_id(), unique_id());
+        assert!(database.compare_and_set_guild_value(&guild, "k", None, "1").await.unwrap());
+        assert!(!database.compare_and_set_guild_value(&guild, "k", None, "2").await.unwrap());
+        assert!(!database.compare_and_set_guild_value(&guild, "k", Some("2"), "3").await.unwrap());
+        assert!(database.compare_and_set_guild_value(&guild, "k", Some("1"), "3").await.unwrap());
+        assert_eq!(database.get_guild_value(&guild, "k").await.unwrap().as_deref(), Some("3"));
+        assert!(!database.compare_and_delete_guild_value(&guild, "k", "1").await.unwrap());
+        assert!(database.compare_and_delete_guild_value(&guild, "k", "3").await.unwrap());
+        assert_eq!(database.get_guild_value(&guild, "k").await.unwrap(), None);
+
+        assert!(!database.compare_and_set_user_value(&user, "k", Some("1"), "1").await.unwrap());
+        assert_eq!(database.get_user_value(&user, "k").await.unwrap(), None);
+        assert!(database.compare_and_set_user_value(&user, "k", None, "1").await.unwrap());
+        assert!(database.compare_and_set_user_value(&user, "k", Some("1"), "2").await.unwrap());
+        assert_eq!(database.get_user_value(&user, "k").await.unwrap().as_deref(), Some("2"));
+    }
+    database_test!(compare_and_set);
+
+    async fn prefixes(database: &Database) {
+        let (guild, user) = (unique_id(), unique_id());
+        database.set_guild_values(&guild, &[("config.a", "1"), ("config.b", "2"), ("configx", "3"), ("stats.a", "4")])
+            .await
+            .unwrap();
+        assert_eq!(
+            database.get_guild_values_with_prefix(&guild, "config.").await.unwrap(),
+            pairs(&[("config.a", "1"), ("config.b", "2")])
+        );
+        // Wildcards of LIKE mean nothing in a prefix
+        assert_eq!(database.get_guild_values_with_prefix(&guild, "config_").await.unwrap(), vec![]);
+        assert_eq!(database.get_guild_values_with_prefix(&guild, "%").await.unwrap(), vec![]);
+        assert_eq!(database.delete_guild_values_with_prefix(&guild, "config.").await.unwrap(), 2);
+        assert_eq!(database.get_all_guild_values(&guild).await.unwrap(), pairs(&[("configx", "3"), ("stats.a", "4")]));
+
+        database.set_user_values(&user, &[("fortune.a", "1"), ("fortune.b", "2"), ("other", "3")]).await.unwrap();
+        assert_eq!(database.get_user_values_with_prefix(&user, "fortune.").await.unwrap().len(), 2);
+        assert_eq!(database.delete_user_values_with_prefix(&user, "fortune.").await.unwrap(), 2);
+        assert_eq!(database.get_all_user_values(&user).await.unwrap(), pairs(&[("other", "3")]));
+    }
+    database_test!(prefixes);
+
+    async fn transactions(database: &Database) {
+        let (guild, user) = (unique_id(), unique_id());
+        database.set_guild_value(&guild, "kept", "1").await.unwrap();
+
+        let mut transaction = database.begin().await.unwrap();
+        transaction.set_guild_value(&guild, "a", "1").await.unwrap();
+        transaction.set_user_value(&user, "a", "1").await.unwrap();
+        transaction.set_member_value(&guild, &user, "a", "1").await.unwrap();
+        transaction.delete_guild_value(&guild, "kept").await.unwrap();
+        assert_eq!(transaction.get_guild_value(&guild, "a").await.unwrap().as_deref(), Some("1"));
+        drop(transaction);
+
+        assert_eq!(database.get_all_guild_values(&guild).await.unwrap(), pairs(&[("kept", "1")]));
+        assert_eq!(database.get_user_value(&user, "a").await.unwrap(), None);
+        assert_eq!(database.get_member_value(&guild, &user, "a").await.unwrap(), None);
+
+        let mut transaction = database.begin().await.unwrap();
+        transaction.set_guild_value(&guild, "a", "1").await.unwrap();
+        transaction.set_user_value_typed(&user, "n", &5).await.unwrap();
+        transaction.set_member_value(&guild, &user, "a", "1").await.unwrap();
+        transaction.delete_guild_value(&guild, "kept").await.unwrap();
+        transaction.commit().await.unwrap();
+
+        assert_eq!(database.get_all_guild_values(&guild).await.unwrap(), pairs(&[("a", "1")]));
+        assert_eq!(database.get_user_value_typed::<i64, _, _>(&user, "n").await.unwrap(), Some(5));
+        assert_eq!(database.get_member_value(&guild, &user, "a").await.unwrap().as_deref(), Some("1"));
+    }
+    database_test!(transactions);
+
+    async fn typed_values(database: &Database) {
+        let (guild, user) = (unique_id(), unique_id());
+        let list = vec![String::from("a"), String::from("b,c")];
+        database.set_guild_value_typed(&guild, "list", &list).await.unwrap();
+        assert_eq!(database.get_guild_value_typed::<Vec<String>, _, _>(&guild, "list").await.unwrap(), Some(list));
+        assert_eq!(database.get_guild_value_typed::<i64, _, _>(&guild, "missing").await.unwrap(), None);
+        // Numbers stored as plain text before values were JSON are still read
+        database.set_user_value(&user, "time", "1700000000").await.unwrap();
+        assert_eq!(database.get_user_value_typed::<i64, _, _>(&user, "time").await.unwrap(), Some(1700000000));
+
+        database.set_user_value(&user, "bad", "not a number").await.unwrap();
+        let error = database.get_user_value_typed::<i64, _, _>(&user, "bad").await.unwrap_err();
+        assert!(matches!(&error, KvError::Corrupt { key, value, .. } if key == "bad" && value == "not a number"));
+        database.set_guild_value(&guild, "list", "[1, 2]").await.unwrap();
+        let error = database.get_guild_value_typed::<Vec<String>, _, _>(&guild, "list").await.unwrap_err();
+        assert!(matches!(error, KvError::Corrupt { .. }));
+
+        database.set_member_value_typed(&guild, &user, "flag", &true).await.unwrap();
+        assert_eq!(database.get_member_value_typed::<bool, _, _, _>(&guild, &user, "flag").await.unwrap(), Some(true));
+        database.set_member_value(&guild, &user, "flag", "yes").await.unwrap();
+        let error = database.get_member_value_typed::<bool, _, _, _>(&guild, &user, "flag").await.unwrap_err();
+        assert!(matches!(error, KvError::Corrupt { .. }));
+    }
+    database_test!(typed_values);
+
+    /// Store something for a guild in every table that belongs to guilds, and vote and enter with `user`
+    async fn fill_guild(database: &Database, guild: &str, user: &str) {
+        database.set_guild_value(guild, "a", "1").await.unwrap();
+        database.set_member_value(guild, user, "a", "1").await.unwrap();
+        database.set_automod_rule(guild, "caps", "delete", None).await.unwrap();
+        database.add_automod_pattern(guild, "darn", false).await.unwrap();
+        database.record_message_stat(guild, "1", Some(user), "2026-01-01").await.unwrap();
+        database.record_join_stat(guild, "2026-01-01", true).await.unwrap();
+        database.award_member_xp(guild, user, 10, 0, 60).await.unwrap();
+        database.set_level_reward(guild, 5, "1").await.unwrap();
+        let options = [String::from("yes")];
+        let poll = database.create_poll(guild, "1", user, "?", &options, false, false, None).await.unwrap();
+        database.set_poll_votes(poll, user, &[0]).await.unwrap();
+        let giveaway = database.create_giveaway(guild, "1", user, "prize", 1, None, None, 0).await.unwrap();
+        database.toggle_giveaway_entry(giveaway, user).await.unwrap();
+        database.set_starboard_entry(guild, &unique_id(), "1", "2").await.unwrap();
+        database.create_tag(guild, &unique_id(), "text", false, user).await.unwrap();
+        database.add_custom_fortune(guild, "fortune", user).await.unwrap();
+    }
+
+    async fn guild_purge(database: &Database) {
+        let (guild, other, user) = (unique_id(), unique_id(), unique_id());
+        fill_guild(database, &guild, &user).await;
+        fill_guild(database, &other, &user).await;
+        let counts = database.get_guild_data_counts().await.unwrap();
+        let tables = counts[&guild].iter().map(|(table, _)| *table).collect::<Vec<&str>>();
+        assert_eq!(tables, GUILD_TABLES, "every guild table must be covered");
+
+        // A row in each guild table, plus a vote and an entry
+        assert_eq!(database.purge_guild(&guild).await.unwrap(), GUILD_TABLES.len() as u64 + 2);
+        let counts = database.get_guild_data_counts().await.unwrap();
+        assert!(!counts.contains_key(&guild));
+        assert_eq!(counts[&other].len(), GUILD_TABLES.len());
+        assert_eq!(database.purge_guild(&guild).await.unwrap(), 0);
+
+        // The votes and entries of the other guild are left
+        let exported = database.export_user_data(&user).await.unwrap();
+        assert_eq!(exported["poll_votes"].len(), 1);
+        assert_eq!(exported["giveaway_entries"].len(), 1);
+    }
+    database_test!(guild_purge);
+
+    async fn user_data(database: &Database) {
+        let (guild, user, other) = (unique_id(), unique_id(), unique_id());
+        for id in [&user, &other] {
+            fill_guild(database, &guild, id).await;
+            let values = [("fortune_last_time", "1"), ("stats_optout", ""), ("other", "2")];
+            database.set_user_values(id, &values).await.unwrap();
+            database.add_fortune_history(id, "pack:1", "text", 1).await.unwrap();
         }
 
-        Ok(values)
+        let exported = database.export_user_data(&user).await.unwrap();
+        let tables = exported.keys().copied().collect::<Vec<&str>>();
+        let mut expected = USER_TABLES.iter().chain(USER_AUTHORED_TABLES.iter())
+            .map(|(t, _)| *t)
+            .collect::<Vec<&str>>();
+        expected.sort();
+        assert_eq!(tables, expected, "every user table must be covered");
+        let xp = &exported["member_xp"][0];
+        assert_eq!(xp["user_id"], user.as_str());
+        assert_eq!(xp["xp"], 10);
+        assert!(exported["user_kv"][0]["updated_at"].is_string());
+
+        // A row in each user table, the kept user value aside
+        let deleted = database.delete_user_data(&user, &crate::mydata::KEPT_KEYS).await.unwrap();
+        assert_eq!(deleted, USER_TABLES.len() as u64);
+        let exported = database.export_user_data(&user).await.unwrap();
+        let tables = exported.keys().copied().collect::<Vec<&str>>();
+        let mut expected = USER_AUTHORED_TABLES.iter().map(|(t, _)| *t).chain(["user_kv"]).collect::<Vec<&str>>();
+        expected.sort();
+        assert_eq!(tables, expected);
+        assert_eq!(
+            database.get_all_user_values(&user).await.unwrap(),
+            pairs(&[("fortune_last_time", "1"), ("stats_optout", "")])
+        );
+
+        let untouched = database.export_user_data(&other).await.unwrap();
+        assert_eq!(untouched.len(), USER_TABLES.len() + USER_AUTHORED_TABLES.len());
+        assert_eq!(untouched["user_kv"].len(), 3);
     }
+    database_test!(user_data);
 }
@@ -1,8 +1,10 @@
 use log::{debug, info, warn};
 use poise::serenity_prelude::{Colour, CreateEmbed, CreateEmbedFooter, Mentionable};
 use poise::serenity_prelude::model::Timestamp;
-use poise::serenity_prelude::CreateMessage;
-use crate::{serenity, tools};
+use crate::{
+    automod, autorole, giveaways, health, leveling, log_queue, metrics, mydata, polls, raid, retention, serenity,
+    shutdown, starboard, stats, tools, welcome,
+};
 use crate::{Data, Error};
 
 pub async fn event_dispatcher(
@@ -11,21 +13,28 @@ pub async fn event_dispatcher(
     framework: poise::FrameworkContext<'_, Data, Error>,
     data: &Data,
 ) -> Result<(), Error> {
+    metrics::event_received(event.snake_case_name());
+    health::event_received();
+    if shutdown::is_requested() {
+        return Ok(());
+    }
+    let _in_flight = shutdown::event_started();
     match event {
         serenity::FullEvent::Ready { data_about_bot, .. } => {
             info!("Authenticated as {} ID {}", data_about_bot.user.name, data_about_bot.user.id);
         }
         serenity::FullEvent::GuildCreate { guild, is_new: _is_new, } => {
             poise::builtins::register_in_guild(ctx, &framework.options().commands, guild.id).await?;
-            let seen_before = data.database.get_guild_value(&guild.id, &"stats.first_join").await?;
-            if seen_before.is_none(){
-                data.database.set_guild_value(&guild.id, &"stats.first_join", &Timestamp::now()).await?;
+            let now = Timestamp::now().to_string();
+            // Only the first join stores its time, which also tells whether this guild is new
+            let first_join = data.database.compare_and_set_guild_value(&guild.id, &"stats.first_join", None, &now).await?;
+            if first_join {
                 info!("Joined guild \"{}\" ID {}", guild.name, guild.id);
             }else{
                 info!("Reconnected to guild \"{}\" ID {}", guild.name, guild.id);
             }
-            data.database.set_guild_value(&guild.id, &"stats.last_join", &Timestamp::now()).await?;
-            data.database.set_guild_value(&guild.id, &"stats.name", &guild.name).await?;
+            data.database.set_guild_values(&guild.id, &[("stats.last_join", now), ("stats.name", guild.name.clone())]).await?;
+            retention::on_guild_joined(&data.database, guild.id).await?;
         }
         serenity::FullEvent::GuildDelete { incomplete, full } => {
             let name = match full { // Get last known guild name from db if we haven't cached it
@@ -36,8 +45,9 @@ pub async fn event_dispatcher(
             match incomplete.unavailable {
                 true => info!("Guild unavailable: \"{}\" ID {}", name, incomplete.id),
                 false => {
-                    data.database.set_guild_value(&incomplete.id, &"stats.kicked_from", &"").await?;
-                    info!("Removed from guild: \"{}\" ID {}", name, incomplete.id)
+                    retention::on_guild_removed(&data.database, incomplete.id).await?;
+                    // The guild's data, member values included, is purged when the retention period is over
+                    info!("Removed from guild: \"{}\" ID {}", name, incomplete.id);
                 }
             }
         }
@@ -52,11 +62,9 @@ pub async fn event_dispatcher(
             let guild_id = guild_id.unwrap();
 
             // Exit if we don't log these // todo de-duplicate, we're doing this many times.
-            let _lci = match data.database.get_guild_value(&guild_id, &"config.track_msg_edits").await {
-                Ok(lci) => match lci {
-                    Some(l) => l,
-                    None => return Ok(())},
-                Err(_) => return Ok(())
+            let log_channel = data.database.get_guild_value_typed(&guild_id, &"config.track_msg_edits").await?;
+            let Some(log_channel_id) = log_channel else {
+                return Ok(());
             };
 
             let (author, link, content, is_available) = {
@@ -78,26 +86,23 @@ pub async fn event_dispatcher(
             };
 
             // Log the message
-            let log_channel_id = tools::to_channel(_lci.as_str()).unwrap();
-            log_channel_id.send_message(&ctx.http,
-                 CreateMessage::new().embed(
-                    CreateEmbed::new()
-                        .title("💬🗑️ Message deleted")
-                        .description(if is_available {
-                            format!("**Text:** {}", content)
-                        } else {
-                            "**Old content unavailable.**".to_string()
-                        })
-                        .field("Author:", author, true)
-                        .field(
-                            if link.is_empty() {"Channel:"} else {"Message:"},
-                            if link.is_empty() {channel_id.mention().to_string()} else {link},
-                            true)
-                        // todo include attachments / embeds
-                        .color(Colour::DARK_RED)
-                        .footer(CreateEmbedFooter::new(format!("Message ID: {}", deleted_message_id)))
-                    )
-                ).await?;
+            log_queue::send(&ctx.http, log_channel_id,
+                CreateEmbed::new()
+                    .title("💬🗑️ Message deleted")
+                    .description(if is_available {
+                        format!("**Text:** {}", content)
+                    } else {
+                        "**Old content unavailable.**".to_string()
+                    })
+                    .field("Author:", author, true)
+                    .field(
+                        if link.is_empty() {"Channel:"} else {"Message:"},
+                        if link.is_empty() {channel_id.mention().to_string()} else {link},
+                        true)
+                    // todo include attachments / embeds
+                    .color(Colour::DARK_RED)
+                    .footer(CreateEmbedFooter::new(format!("Message ID: {}", deleted_message_id)))
+            );
         }
         serenity::FullEvent::MessageUpdate { old_if_available, new, event } => {
             // Exit if it's not a guild message
@@ -107,11 +112,9 @@ pub async fn event_dispatcher(
             let guild_id = event.guild_id.unwrap(); // We already exited if this was None
 
             // Exit if we don't log these
-            let _lci = match data.database.get_guild_value(&guild_id, &"config.track_msg_edits").await {
-                Ok(lci) => match lci {
-                    Some(l) => l,
-                    None => return Ok(())},
-                Err(_) => return Ok(())
+            let log_channel = data.database.get_guild_value_typed(&guild_id, &"config.track_msg_edits").await?;
+            let Some(log_channel_id) = log_channel else {
+                return Ok(());
             };
 
             // If the message isn't cached, try to fetch it
@@ -151,26 +154,29 @@ pub async fn event_dispatcher(
             }
 
             // Log the event
-            let log_channel_id = tools::to_channel(_lci.as_str()).unwrap();
-            log_channel_id.send_message(&ctx.http,
-                CreateMessage::new().embed(
-                    CreateEmbed::new()
-                        .title("💬✏️ Message edited")
-                        .description(if is_available {
-                            format!("**Old:** {}\n\n**New:** {}\n", old_content, new_content)
-                        } else {
-                            format!("**Old content unavailable.** \n**New:** {}", new_content)
-                        })
-                        .field("Author:", new_message.author.to_string(), true)
-                        .field("Message:", new_message.link().to_string(), true)
-                        // todo include attachments / embeds
-                        .color(Colour::DARK_TEAL)
-                        .footer(CreateEmbedFooter::new(format!("Message ID: {}", new_message.id)))
-                )
-            ).await?;
+            log_queue::send(&ctx.http, log_channel_id,
+                CreateEmbed::new()
+                    .title("💬✏️ Message edited")
+                    .description(if is_available {
+                        format!("**Old:** {}\n\n**New:** {}\n", old_content, new_content)
+                    } else {
+                        format!("**Old content unavailable.** \n**New:** {}", new_content)
+                    })
+                    .field("Author:", new_message.author.to_string(), true)
+                    .field("Message:", new_message.link().to_string(), true)
+                    // todo include attachments / embeds
+                    .color(Colour::DARK_TEAL)
+                    .footer(CreateEmbedFooter::new(format!("Message ID: {}", new_message.id)))
+            );
         }
-        serenity::FullEvent::Message { new_message: _new_message } => {
-            // TODO
+        serenity::FullEvent::Message { new_message } => {
+            if let Err(e) = automod::on_message(ctx, data, new_message).await {
+                warn!("Automod failed on message ID {}: {}", new_message.id, e);
+            }
+            if let Err(e) = leveling::on_message(ctx, data, new_message).await {
+                warn!("Failed to award XP for message ID {}: {}", new_message.id, e);
+            }
+            stats::on_message(data, new_message).await?;
         }
         serenity::FullEvent::GuildBanAddition { guild_id: _guild_id, banned_user: _banned_user } => {
             // TODO
@@ -181,12 +187,29 @@ pub async fn event_dispatcher(
         serenity::FullEvent::GuildMemberAddition { new_member } => {
             let guild_id = new_member.guild_id;
 
+            if let Err(e) = stats::on_member_join(data, new_member).await {
+                warn!("Failed to record join statistics: {}", e);
+            }
+            let kicked = match raid::on_member_join(ctx, data, new_member).await {
+                Ok(k) => k,
+                Err(e) => {
+                    warn!("Failed to check user ID {} for raids: {}", new_member.user.id, e);
+                    false
+                }
+            };
+            if !kicked {
+                if let Err(e) = welcome::on_member_join(ctx, data, new_member).await {
+                    warn!("Failed to welcome user ID {}: {}", new_member.user.id, e);
+                }
+                if let Err(e) = autorole::on_member_join(ctx, data, new_member).await {
+                    warn!("Failed to assign auto-roles to user ID {}: {}", new_member.user.id, e);
+                }
+            }
+
             // Exit if we don't log these
-            let _lci = match data.database.get_guild_value(&guild_id, &"config.track_joinleaves").await {
-                Ok(lci) => match lci {
-                    Some(l) => l,
-                    None => return Ok(())},
-                Err(_) => return Ok(())
+            let log_channel = data.database.get_guild_value_typed(&guild_id, &"config.track_joinleaves").await?;
+            let Some(log_channel_id) = log_channel else {
+                return Ok(());
             };
 
             let discriminator = match new_member.user.discriminator {
@@ -195,29 +218,31 @@ pub async fn event_dispatcher(
             };
 
             // Log the message
-            let log_channel_id = tools::to_channel(_lci.as_str()).unwrap();
-            log_channel_id.send_message(&ctx.http,
-                CreateMessage::new().embed(
-                    CreateEmbed::new()
-                        .title("👋 User joined")
-                        .field("User:", new_member.user.mention().to_string(), false)
-                        .field("User name:", new_member.user.name.to_string(), true)
-                        .field("Discriminator:", discriminator, true)
-                        .field("Global nickname:", new_member.clone().user.global_name.unwrap_or(String::from("")), true)
-                        .field("Account age:", tools::user_account_age(new_member.user.id), false)
-                        .thumbnail(new_member.user.avatar_url().unwrap_or(String::from("")))
-                        .color(Colour::DARK_GREEN)
-                        .footer(CreateEmbedFooter::new(format!("User ID: {}", new_member.user.id)))
-                )
-            ).await?;
+            log_queue::send(&ctx.http, log_channel_id,
+                CreateEmbed::new()
+                    .title("👋 User joined")
+                    .field("User:", new_member.user.mention().to_string(), false)
+                    .field("User name:", new_member.user.name.to_string(), true)
+                    .field("Discriminator:", discriminator, true)
+                    .field("Global nickname:", new_member.clone().user.global_name.unwrap_or(String::from("")), true)
+                    .field("Account age:", tools::user_account_age(new_member.user.id), false)
+                    .thumbnail(new_member.user.avatar_url().unwrap_or(String::from("")))
+                    .color(Colour::DARK_GREEN)
+                    .footer(CreateEmbedFooter::new(format!("User ID: {}", new_member.user.id)))
+            );
         }
         serenity::FullEvent::GuildMemberRemoval { guild_id, user, member_data_if_available } => {
+            if let Err(e) = stats::on_member_leave(data, *guild_id).await {
+                warn!("Failed to record leave statistics: {}", e);
+            }
+            if let Err(e) = welcome::on_member_leave(ctx, data, *guild_id, user).await {
+                warn!("Failed to say goodbye to user ID {}: {}", user.id, e);
+            }
+
             // Exit if we don't log these
-            let _lci = match data.database.get_guild_value(&guild_id, &"config.track_joinleaves").await {
-                Ok(lci) => match lci {
-                    Some(l) => l,
-                    None => return Ok(())},
-                Err(_) => return Ok(())
+            let log_channel = data.database.get_guild_value_typed(&guild_id, &"config.track_joinleaves").await?;
+            let Some(log_channel_id) = log_channel else {
+                return Ok(());
             };
 
             let (server_nickname, member_age) = match member_data_if_available {
@@ -246,32 +271,57 @@ pub async fn event_dispatcher(
             };
 
             // Log the message
-            let log_channel_id = tools::to_channel(_lci.as_str()).unwrap();
-            log_channel_id.send_message(&ctx.http,
-                CreateMessage::new().embed(
-                    CreateEmbed::new()
-                        .title("🚪 User left")
-                        .field("User:", user.mention().to_string(), false)
-                        .field("User name:", user.name.to_string(), true)
-                        .field("Discriminator:", discriminator, true)
-                        .field("Global nickname:", user.clone().global_name.unwrap_or(String::from("")), true)
-                        .field("Server nickname:", server_nickname, true)
-                        .field("Server membership age:", member_age, false)
-                        .field("Account age:", tools::user_account_age(user.id), false)
-                        .thumbnail(user.avatar_url().unwrap_or(String::from("")))
-                        .color(Colour::DARK_RED)
-                        .footer(CreateEmbedFooter::new(format!("User ID: {}", user.id)))
-                )
-            ).await?;
+            log_queue::send(&ctx.http, log_channel_id,
+                CreateEmbed::new()
+                    .title("🚪 User left")
+                    .field("User:", user.mention().to_string(), false)
+                    .field("User name:", user.name.to_string(), true)
+                    .field("Discriminator:", discriminator, true)
+                    .field("Global nickname:", user.clone().global_name.unwrap_or(String::from("")), true)
+                    .field("Server nickname:", server_nickname, true)
+                    .field("Server membership age:", member_age, false)
+                    .field("Account age:", tools::user_account_age(user.id), false)
+                    .thumbnail(user.avatar_url().unwrap_or(String::from("")))
+                    .color(Colour::DARK_RED)
+                    .footer(CreateEmbedFooter::new(format!("User ID: {}", user.id)))
+            );
         }
-        serenity::FullEvent::GuildMemberUpdate { old_if_available: _old_if_available, new: _new, event: _event } => {
-            // todo
+        serenity::FullEvent::GuildMemberUpdate { old_if_available, new: _new, event } => {
+            autorole::on_member_update(ctx, data, old_if_available, event).await?;
         }
         serenity::FullEvent::GuildUpdate { old_data_if_available: _old_data_if_available, new_data } => {
             data.database.set_guild_value(&new_data.id, &"stats.name", &new_data.name).await?;
         }
         serenity::FullEvent::GuildAuditLogEntryCreate { guild_id: _guild_id, entry: _entry } => {
 
+        }
+        serenity::FullEvent::InteractionCreate { interaction } => {
+            // Commands are handled by poise, we only care about our own buttons here
+            if let Some(component) = interaction.as_message_component() {
+                match component.data.custom_id.as_str() {
+                    id if id.starts_with(raid::KICK_BUTTON_ID) => raid::on_kick_button(ctx, data, component).await?,
+                    id if id.starts_with(polls::VOTE_PREFIX) || id.starts_with(polls::CLOSE_PREFIX) => {
+                        polls::on_component(ctx, data, component).await?
+                    }
+                    id if id.starts_with(giveaways::ENTER_PREFIX) => giveaways::on_component(ctx, data, component).await?,
+                    id if id.starts_with(mydata::DELETE_PREFIX) || id == mydata::CANCEL_BUTTON_ID => {
+                        mydata::on_component(ctx, data, component).await?
+                    }
+                    _ => {}
+                }
+            }
+        }
+        serenity::FullEvent::ReactionAdd { add_reaction } => {
+            starboard::on_reaction(ctx, data, add_reaction).await?;
+        }
+        serenity::FullEvent::ReactionRemove { removed_reaction } => {
+            starboard::on_reaction(ctx, data, removed_reaction).await?;
+        }
+        serenity::FullEvent::ReactionRemoveEmoji { removed_reactions } => {
+            starboard::on_reaction(ctx, data, removed_reactions).await?;
+        }
+        serenity::FullEvent::ReactionRemoveAll { channel_id: _channel_id, removed_from_message_id } => {
+            starboard::on_reactions_cleared(ctx, data, *removed_from_message_id).await?;
         }
         serenity::FullEvent::Resume { event: _event } => {
             info!("Reconnected to gateway");
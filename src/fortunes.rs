@@ -0,0 +1,384 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use log::{info, warn};
+use poise::serenity_prelude::{Colour, CreateEmbed, GuildId, UserId};
+use poise::CreateReply;
+use rand::distr::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::{rng, SeedableRng};
+use crate::tools;
+use crate::{Context, Data, Error};
+
+/// Name of the pack holding a guild's own fortunes
+pub const CUSTOM_PACK: &str = "custom";
+const SECONDS_PER_DAY: i64 = 60 * 60 * 24;
+
+/// A fortune that can be handed out
+#[derive(Debug, Clone)]
+pub struct Fortune {
+    /// Stable identifier, `pack:index` for pack fortunes and `custom:id` for custom ones
+    pub id: String,
+    pub text: String,
+}
+
+/// All fortune packs, loaded once at startup
+#[derive(Default)]
+pub struct FortunePacks {
+    packs: BTreeMap<String, Vec<String>>,
+}
+
+impl FortunePacks {
+    /// Load fortune packs from a JSON file, or from every JSON file in a directory.
+    /// Each file is a pack named after the file, holding an array of strings.
+    ///
+    /// Problems are logged rather than fatal; the bot just has fewer fortunes to give out.
+    pub fn load(path: &str) -> Self {
+        let path = Path::new(path);
+        let files = if path.is_dir() {
+            match std::fs::read_dir(path) {
+                Ok(entries) => {
+                    let mut files = entries
+                        .filter_map(|e| e.ok().map(|e| e.path()))
+                        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+                        .collect::<Vec<_>>();
+                    files.sort();
+                    files
+                }
+                Err(e) => {
+                    warn!("Failed to read fortune directory {}: {}", path.display(), e);
+                    vec![]
+                }
+            }
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        let mut packs = BTreeMap::new();
+        for file in files {
+            let name = match file.file_stem().and_then(|s| s.to_str()) {
+                Some(n) if n != CUSTOM_PACK => n.to_lowercase(),
+                _ => {
+                    warn!("Skipping fortune pack with unusable name: {}", file.display());
+                    continue;
+                }
+            };
+            match Self::load_pack(&file) {
+                Ok(fortunes) if !fortunes.is_empty() => {
+                    info!("Loaded {} fortunes from pack \"{}\"", fortunes.len(), name);
+                    packs.insert(name, fortunes);
+                }
+                Ok(_) => warn!("Fortune pack {} is empty", file.display()),
+                Err(e) => warn!("Failed to load fortune pack {}: {}", file.display(), e),
+            }
+        }
+        if packs.is_empty() {
+            warn!("No fortunes loaded from {}, only custom fortunes will be available", path.display());
+        }
+        Self { packs }
+    }
+
+    fn load_pack(file: &Path) -> Result<Vec<String>, Error> {
+        let contents = std::fs::read_to_string(file)?;
+        let values: Vec<serde_json::Value> = serde_json::from_str(&contents)?;
+        Ok(values.into_iter().filter_map(|v| v.as_str().map(String::from)).collect())
+    }
+
+    /// Names of all loaded packs, in alphabetical order
+    pub fn names(&self) -> Vec<&String> {
+        self.packs.keys().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Vec<String>> {
+        self.packs.get(name)
+    }
+}
+
+/// The packs a guild has enabled. All packs are enabled until an admin changes that.
+pub async fn enabled_packs(data: &Data, guild_id: GuildId) -> Result<Vec<String>, Error> {
+    Ok(match data.database.get_guild_value(&guild_id, &"config.fortune_packs").await? {
+        Some(v) => v.split(',').filter(|p| !p.is_empty()).map(String::from).collect(),
+        None => data.fortunes.names().into_iter().cloned().collect(),
+    })
+}
+
+/// Every fortune available in a guild, or in every pack outside of guilds
+pub async fn candidates(data: &Data, guild_id: Option<GuildId>) -> Result<Vec<Fortune>, Error> {
+    let (packs, custom) = match guild_id {
+        Some(g) => (enabled_packs(data, g).await?, data.database.get_custom_fortunes(&g).await?),
+        None => (data.fortunes.names().into_iter().cloned().collect(), vec![]),
+    };
+
+    let mut fortunes = vec![];
+    for pack in packs {
+        if let Some(texts) = data.fortunes.get(&pack) {
+            fortunes.extend(texts.iter().enumerate().map(|(i, t)| Fortune {
+                id: format!("{}:{}", pack, i),
+                text: t.clone(),
+            }));
+        }
+    }
+    fortunes.extend(custom.into_iter().map(|(id, text)| Fortune {
+        id: format!("{}:{}", CUSTOM_PACK, id),
+        text,
+    }));
+    Ok(fortunes)
+}
+
+/// Pick a random fortune
+pub fn pick(fortunes: &[Fortune]) -> Option<Fortune> {
+    let range = Uniform::try_from(0..fortunes.len()).ok()?;
+    Some(fortunes[range.sample(&mut rng())].clone())
+}
+
+/// Pick a random fortune that isn't in `seen`
+pub fn pick_unseen(fortunes: &[Fortune], seen: &HashSet<String>) -> Option<Fortune> {
+    let unseen = fortunes.iter()
+        .filter(|f| !seen.contains(&f.id))
+        .cloned()
+        .collect::<Vec<Fortune>>();
+    pick(&unseen)
+}
+
+/// Pick the fortune of the day of a user. A user gets the same fortune all day, as long as the available fortunes
+/// don't change.
+pub fn pick_daily(fortunes: &[Fortune], user_id: UserId, day: i64) -> Option<Fortune> {
+    let seed = user_id.get() ^ (day as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let range = Uniform::try_from(0..fortunes.len()).ok()?;
+    Some(fortunes[range.sample(&mut StdRng::seed_from_u64(seed))].clone())
+}
+
+/// Whether a guild hands out one fortune per user per day instead of random ones
+pub async fn is_daily_mode(data: &Data, guild_id: Option<GuildId>) -> Result<bool, Error> {
+    Ok(match guild_id {
+        Some(g) => data.database.get_guild_value(&g, &"config.fortune_daily").await?.is_some(),
+        None => false,
+    })
+}
+
+/// Draw a fortune for a user and add it to their history.
+///
+/// Random fortunes don't repeat until the user has seen every available one. In daily mode the fortune is derived from
+/// the user and the date instead, and only goes into the history once.
+pub async fn draw(data: &Data, guild_id: Option<GuildId>, user_id: UserId) -> Result<Option<Fortune>, Error> {
+    let candidates = candidates(data, guild_id).await?;
+    let now = chrono::Utc::now().timestamp();
+    let database = &data.database;
+
+    if is_daily_mode(data, guild_id).await? {
+        let day = now.div_euclid(SECONDS_PER_DAY);
+        let fortune = match pick_daily(&candidates, user_id, day) {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        if !database.get_seen_fortunes(&user_id, day * SECONDS_PER_DAY).await?.contains(&fortune.id) {
+            database.add_fortune_history(&user_id, &fortune.id, &fortune.text, now).await?;
+        }
+        return Ok(Some(fortune));
+    }
+
+    let cycle_start: i64 = database.get_user_value_typed(&user_id, &"fortune_cycle_start").await?.unwrap_or(0);
+    let seen = database.get_seen_fortunes(&user_id, cycle_start).await?.into_iter().collect::<HashSet<String>>();
+    let fortune = match pick_unseen(&candidates, &seen) {
+        Some(f) => f,
+        None => {
+            // Seen them all, start over
+            database.set_user_value_typed(&user_id, &"fortune_cycle_start", &now).await?;
+            match pick(&candidates) {
+                Some(f) => f,
+                None => return Ok(None),
+            }
+        }
+    };
+    database.add_fortune_history(&user_id, &fortune.id, &fortune.text, now).await?;
+    Ok(Some(fortune))
+}
+
+async fn autocomplete_pack(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    ctx.data()
+        .fortunes
+        .names()
+        .into_iter()
+        .filter(|n| n.starts_with(&partial.to_lowercase()))
+        .cloned()
+        .collect()
+}
+
+async fn reply_ephemeral(ctx: Context<'_>, text: impl Into<String>) -> Result<(), Error> {
+    ctx.send(CreateReply::default().content(text).ephemeral(true)).await?;
+    Ok(())
+}
+
+async fn set_enabled_packs(ctx: Context<'_>, packs: &[String]) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let all = ctx.data().fortunes.names();
+    if all.iter().all(|p| packs.contains(p)) {
+        ctx.data().database.delete_guild_value(&guild_id, &"config.fortune_packs").await?;
+    } else {
+        ctx.data().database.set_guild_value(&guild_id, &"config.fortune_packs", &packs.join(",")).await?;
+    }
+    Ok(())
+}
+
+// Commands ->
+
+/// Manage the fortunes handed out in this server
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    subcommands(
+        "fortunes_packs",
+        "fortunes_enable",
+        "fortunes_disable",
+        "fortunes_add",
+        "fortunes_remove",
+        "fortunes_custom",
+        "fortunes_daily"
+    ),
+    subcommand_required
+)]
+pub async fn fortunes(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// List the fortune packs and whether they're enabled
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "packs")]
+pub async fn fortunes_packs(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let enabled = enabled_packs(ctx.data(), guild_id).await?;
+    let custom = ctx.data().database.get_custom_fortunes(&guild_id).await?.len();
+
+    let mut lines = ctx.data()
+        .fortunes
+        .names()
+        .into_iter()
+        .map(|name| format!(
+            "{} **{}** - {} fortunes",
+            if enabled.contains(name) { "✅" } else { "❌" },
+            name,
+            ctx.data().fortunes.get(name).map(|f| f.len()).unwrap_or(0)
+        ))
+        .collect::<Vec<String>>();
+    lines.push(format!("✅ **{}** - {} fortunes", CUSTOM_PACK, custom));
+
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title("🥠 Fortune packs")
+                    .description(lines.join("\n"))
+                    .color(Colour::GOLD),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Hand out fortunes from a pack
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "enable")]
+pub async fn fortunes_enable(
+    ctx: Context<'_>,
+    #[description = "Name of the pack"]
+    #[autocomplete = "autocomplete_pack"]
+    pack: String,
+) -> Result<(), Error> {
+    let pack = pack.to_lowercase();
+    if ctx.data().fortunes.get(&pack).is_none() {
+        return reply_ephemeral(ctx, format!("There's no fortune pack called `{}`.", pack)).await;
+    }
+    let mut packs = enabled_packs(ctx.data(), ctx.guild_id().unwrap()).await?;
+    if !packs.contains(&pack) {
+        packs.push(pack.clone());
+        set_enabled_packs(ctx, &packs).await?;
+    }
+    reply_ephemeral(ctx, format!("Fortunes from `{}` will be handed out.", pack)).await
+}
+
+/// Stop handing out fortunes from a pack
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "disable")]
+pub async fn fortunes_disable(
+    ctx: Context<'_>,
+    #[description = "Name of the pack"]
+    #[autocomplete = "autocomplete_pack"]
+    pack: String,
+) -> Result<(), Error> {
+    let pack = pack.to_lowercase();
+    let mut packs = enabled_packs(ctx.data(), ctx.guild_id().unwrap()).await?;
+    packs.retain(|p| *p != pack);
+    set_enabled_packs(ctx, &packs).await?;
+    reply_ephemeral(ctx, format!("Fortunes from `{}` will no longer be handed out.", pack)).await
+}
+
+/// Add a fortune of your own to this server
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "add")]
+pub async fn fortunes_add(
+    ctx: Context<'_>,
+    #[description = "The fortune"]
+    #[max_length = 1000]
+    text: String,
+) -> Result<(), Error> {
+    let fortune_id = ctx.data()
+        .database
+        .add_custom_fortune(&ctx.guild_id().unwrap(), &text, &ctx.author().id)
+        .await?;
+    info!("{} added custom fortune ID {}", ctx.author().name, fortune_id);
+    reply_ephemeral(ctx, format!("Added fortune #{}.", fortune_id)).await
+}
+
+/// Remove a fortune you added to this server
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "remove")]
+pub async fn fortunes_remove(
+    ctx: Context<'_>,
+    #[description = "Number of the fortune, see /fortunes custom"] fortune_id: i64,
+) -> Result<(), Error> {
+    if ctx.data().database.delete_custom_fortune(&ctx.guild_id().unwrap(), fortune_id).await? {
+        info!("{} removed custom fortune ID {}", ctx.author().name, fortune_id);
+        reply_ephemeral(ctx, format!("Removed fortune #{}.", fortune_id)).await
+    } else {
+        reply_ephemeral(ctx, format!("There's no custom fortune #{}.", fortune_id)).await
+    }
+}
+
+/// List the fortunes added to this server
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "custom")]
+pub async fn fortunes_custom(ctx: Context<'_>) -> Result<(), Error> {
+    let custom = ctx.data().database.get_custom_fortunes(&ctx.guild_id().unwrap()).await?;
+    let text = if custom.is_empty() {
+        String::from("This server has no custom fortunes yet. Add one with `/fortunes add`.")
+    } else {
+        let lines = custom.iter()
+            .map(|(id, text)| format!("**#{}** {}", id, tools::truncate(text, 100)))
+            .collect::<Vec<String>>()
+            .join("\n");
+        tools::truncate(&lines, 4000)
+    };
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title("🥠 Custom fortunes")
+                    .description(text)
+                    .color(Colour::GOLD),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Give every member one fortune per day instead of random ones
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "daily")]
+pub async fn fortunes_daily(
+    ctx: Context<'_>,
+    #[description = "Whether fortune of the day mode is on"] enabled: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    if enabled {
+        ctx.data().database.set_guild_value(&guild_id, &"config.fortune_daily", &"").await?;
+        reply_ephemeral(ctx, "Everyone now gets one fortune of the day.").await
+    } else {
+        ctx.data().database.delete_guild_value(&guild_id, &"config.fortune_daily").await?;
+        reply_ephemeral(ctx, "Fortunes are random again.").await
+    }
+}
@@ -0,0 +1,364 @@
+use std::time::Duration;
+use log::{info, warn};
+use poise::serenity_prelude::{
+    ButtonStyle, Colour, ComponentInteraction, CreateActionRow, CreateButton, CreateEmbed, CreateEmbedFooter,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage, EditMessage, GuildId, Http, Member,
+    MessageReference, Role, UserId,
+};
+use poise::CreateReply;
+use rand::distr::{Distribution, Uniform};
+use rand::rng;
+use crate::database::{Database, Giveaway};
+use crate::{serenity, shutdown, tools};
+use crate::{Context, Data, Error};
+
+/// Custom ID prefix of the entry button, followed by the giveaway ID
+pub const ENTER_PREFIX: &str = "giveaway:";
+/// How often to check for giveaways that reached their end time
+const END_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Randomly pick up to `count` different winners
+pub fn draw_winners(entries: &[String], count: usize) -> Vec<String> {
+    let mut pool = entries.to_vec();
+    let mut winners = vec![];
+    let mut generator = rng();
+    while winners.len() < count && !pool.is_empty() {
+        let range = match Uniform::try_from(0..pool.len()) {
+            Ok(r) => r,
+            Err(_) => break,
+        };
+        winners.push(pool.swap_remove(range.sample(&mut generator)));
+    }
+    winners
+}
+
+fn mention_list(users: &[String]) -> String {
+    users.iter().map(|u| format!("<@{}>", u)).collect::<Vec<String>>().join(", ")
+}
+
+fn giveaway_embed(giveaway: &Giveaway, entries: usize) -> CreateEmbed {
+    let mut requirements = vec![];
+    if let Some(role) = &giveaway.required_role {
+        requirements.push(format!("Role <@&{}>", role));
+    }
+    if let Some(days) = giveaway.min_member_days {
+        requirements.push(format!("Member for at least {} days", days));
+    }
+
+    let mut embed = CreateEmbed::new()
+        .title(format!("🎁 {}", giveaway.prize))
+        .field("Hosted by:", format!("<@{}>", giveaway.host_id), true)
+        .field("Winners:", giveaway.winner_count.to_string(), true)
+        .field("Entries:", entries.to_string(), true)
+        .footer(CreateEmbedFooter::new(format!("Giveaway ID: {}", giveaway.giveaway_id)));
+    if !requirements.is_empty() {
+        embed = embed.field("Requirements:", requirements.join("\n"), false);
+    }
+    if giveaway.ended {
+        embed
+            .description(format!("Ended <t:{}:R>", giveaway.ends_at))
+            .field("Won by:", if giveaway.winners.is_empty() {
+                String::from("Nobody entered")
+            } else {
+                mention_list(&giveaway.winners)
+            }, false)
+            .color(Colour::DARK_GREY)
+    } else {
+        embed
+            .description(format!("Click the button to enter!\nEnds <t:{}:R>", giveaway.ends_at))
+            .color(Colour::GOLD)
+    }
+}
+
+/// Why a member can't enter a giveaway, if they can't
+fn unmet_requirement(giveaway: &Giveaway, member: Option<&Member>) -> Option<String> {
+    let has_role = match giveaway.required_role.as_deref().and_then(tools::to_role) {
+        Some(role) => member.is_some_and(|m| m.roles.contains(&role)),
+        None => true,
+    };
+    let old_enough = match giveaway.min_member_days {
+        Some(days) => member
+            .and_then(|m| m.joined_at)
+            .is_some_and(|j| tools::time_since(j).num_days() >= days),
+        None => true,
+    };
+
+    if !has_role {
+        Some(String::from("You don't have the role required to enter this giveaway."))
+    } else if !old_enough {
+        Some(format!(
+            "You must be a member of this server for at least {} days to enter.",
+            giveaway.min_member_days.unwrap_or(0)
+        ))
+    } else {
+        None
+    }
+}
+
+/// Whether an entrant still meets the requirements of a giveaway. Members can lose the role or leave after entering.
+async fn is_eligible(http: &Http, giveaway: &Giveaway, user_id: &str) -> bool {
+    if giveaway.required_role.is_none() && giveaway.min_member_days.is_none() {
+        return true;
+    }
+    let (Some(guild_id), Some(user_id)) = (tools::to_snowflake(&giveaway.guild_id), tools::to_snowflake(user_id)) else {
+        return false;
+    };
+    match GuildId::from(guild_id).member(http, UserId::from(user_id)).await {
+        Ok(member) => unmet_requirement(giveaway, Some(&member)).is_none(),
+        Err(e) => {
+            warn!("Can't check giveaway entrant user ID {}, skipping them: {}", user_id, e); // Probably left
+            false
+        }
+    }
+}
+
+/// Draw new winners that haven't won this giveaway before and still meet its requirements, and announce them
+async fn announce_winners(http: &Http, database: &Database, giveaway: &Giveaway, count: usize)
+    -> Result<Vec<String>, Error> {
+    let mut candidates = database.get_giveaway_entries(giveaway.giveaway_id).await?.into_iter()
+        .filter(|e| !giveaway.winners.contains(e))
+        .collect::<Vec<String>>();
+    let mut winners = vec![];
+    while winners.len() < count && !candidates.is_empty() {
+        for drawn in draw_winners(&candidates, count - winners.len()) {
+            candidates.retain(|c| *c != drawn);
+            if is_eligible(http, giveaway, &drawn).await {
+                winners.push(drawn);
+            }
+        }
+    }
+    if !winners.is_empty() {
+        database.add_giveaway_winners(giveaway.giveaway_id, &winners).await?;
+    }
+
+    if let Some(channel_id) = tools::to_channel(&giveaway.channel_id) {
+        let text = if winners.is_empty() {
+            format!("Nobody was eligible to win **{}**.", giveaway.prize)
+        } else {
+            format!("🎉 Congratulations {}! You won **{}**!", mention_list(&winners), giveaway.prize)
+        };
+        let mut message = CreateMessage::new().content(text);
+        if let Some(message_id) = giveaway.message_id.as_deref().and_then(tools::to_snowflake) {
+            message = message.reference_message(MessageReference::from((channel_id, message_id.into())));
+        }
+        channel_id.send_message(http, message).await?;
+    }
+    Ok(winners)
+}
+
+/// End a giveaway, draw its winners and update its message
+pub async fn end_giveaway(http: &Http, database: &Database, giveaway: &Giveaway) -> Result<(), Error> {
+    if !database.end_giveaway(giveaway.giveaway_id).await? {
+        return Ok(()); // Someone beat us to it
+    }
+    let winners = announce_winners(http, database, giveaway, giveaway.winner_count as usize).await?;
+    let giveaway = Giveaway { ended: true, winners, ..giveaway.clone() };
+    update_ended_message(http, database, &giveaway).await?;
+    info!("Ended giveaway ID {}", giveaway.giveaway_id);
+    Ok(())
+}
+
+/// Show the winners of an ended giveaway in its message, and remove the entry button
+async fn update_ended_message(http: &Http, database: &Database, giveaway: &Giveaway) -> Result<(), Error> {
+    let entries = database.get_giveaway_entries(giveaway.giveaway_id).await?.len();
+    if let (Some(channel_id), Some(message_id)) = (
+        tools::to_channel(&giveaway.channel_id),
+        giveaway.message_id.as_deref().and_then(tools::to_snowflake),
+    ) {
+        channel_id.edit_message(http, message_id,
+            EditMessage::new().embed(giveaway_embed(giveaway, entries)).components(vec![])
+        ).await?;
+    }
+    Ok(())
+}
+
+/// End giveaways that reached their end time, forever. Running giveaways survive restarts since they're in the database.
+pub async fn end_expired_giveaways(ctx: serenity::Context, database: Database) {
+    let mut interval = tokio::time::interval(END_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown::requested() => break,
+        }
+        let giveaways = match database.get_due_giveaways(chrono::Utc::now().timestamp()).await {
+            Ok(g) => g,
+            Err(e) => {
+                warn!("Failed to get expired giveaways: {}", e);
+                continue;
+            }
+        };
+        for giveaway in giveaways {
+            if let Err(e) = end_giveaway(&ctx.http, &database, &giveaway).await {
+                warn!("Failed to end giveaway ID {}: {}", giveaway.giveaway_id, e);
+            }
+        }
+    }
+}
+
+// Event handlers ->
+
+/// Handle a click on the entry button
+pub async fn on_component(ctx: &serenity::Context, data: &Data, component: &ComponentInteraction) -> Result<(), Error> {
+    let giveaway = match component.data.custom_id.strip_prefix(ENTER_PREFIX).and_then(|id| id.parse::<i64>().ok()) {
+        Some(id) => data.database.get_giveaway(id).await?,
+        None => None,
+    };
+
+    let mut entries_changed = None;
+    let text = match giveaway {
+        Some(g) if !g.ended => {
+            if let Some(reason) = unmet_requirement(&g, component.member.as_ref()) {
+                reason
+            } else if data.database.toggle_giveaway_entry(g.giveaway_id, &component.user.id).await? {
+                let text = format!("You entered the giveaway for **{}**. Good luck!", g.prize);
+                entries_changed = Some(g);
+                text
+            } else {
+                entries_changed = Some(g);
+                String::from("You withdrew from the giveaway.")
+            }
+        }
+        _ => String::from("This giveaway has ended."),
+    };
+
+    component.create_response(&ctx.http, CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new().content(text).ephemeral(true)
+    )).await?;
+
+    // Update the entry count
+    if let Some(g) = entries_changed {
+        let entries = data.database.get_giveaway_entries(g.giveaway_id).await?.len();
+        component.channel_id.edit_message(&ctx.http, component.message.id,
+            EditMessage::new().embed(giveaway_embed(&g, entries))
+        ).await?;
+    }
+    Ok(())
+}
+
+// Commands ->
+
+/// Host giveaways
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD",
+    subcommands("giveaway_start", "giveaway_end", "giveaway_reroll"),
+    subcommand_required
+)]
+pub async fn giveaway(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Start a giveaway in this channel
+#[poise::command(slash_command, guild_only, default_member_permissions = "MANAGE_GUILD", rename = "start")]
+pub async fn giveaway_start(
+    ctx: Context<'_>,
+    #[description = "What can be won"]
+    #[max_length = 250]
+    prize: String,
+    #[description = "How long the giveaway lasts, in minutes"]
+    #[min = 1]
+    #[max = 43200]
+    duration: u32,
+    #[description = "How many winners to draw. Defaults to 1"]
+    #[min = 1]
+    #[max = 50]
+    winners: Option<u32>,
+    #[description = "Only members with this role can enter"] required_role: Option<Role>,
+    #[description = "Only members who joined at least this many days ago can enter"] min_member_days: Option<u32>,
+) -> Result<(), Error> {
+    let ends_at = chrono::Utc::now().timestamp() + duration as i64 * 60;
+    let giveaway_id = ctx.data()
+        .database
+        .create_giveaway(
+            &ctx.guild_id().unwrap(),
+            &ctx.channel_id(),
+            &ctx.author().id,
+            &prize,
+            winners.unwrap_or(1) as i64,
+            required_role.map(|r| r.id.to_string()),
+            min_member_days.map(i64::from),
+            ends_at,
+        )
+        .await?;
+    let giveaway = ctx.data()
+        .database
+        .get_giveaway(giveaway_id)
+        .await?
+        .ok_or("Giveaway disappeared after creating it")?;
+
+    let reply = ctx.send(
+        CreateReply::default()
+            .embed(giveaway_embed(&giveaway, 0))
+            .components(vec![CreateActionRow::Buttons(vec![
+                CreateButton::new(format!("{}{}", ENTER_PREFIX, giveaway_id))
+                    .label("Enter")
+                    .emoji('🎉')
+                    .style(ButtonStyle::Success)
+            ])]),
+    )
+    .await?;
+    let message = reply.message().await?;
+    ctx.data().database.set_giveaway_message(giveaway_id, &message.id).await?;
+    info!("{} started giveaway ID {}", ctx.author().name, giveaway_id);
+    Ok(())
+}
+
+/// End a giveaway early and draw its winners
+#[poise::command(slash_command, guild_only, default_member_permissions = "MANAGE_GUILD", rename = "end")]
+pub async fn giveaway_end(
+    ctx: Context<'_>,
+    #[description = "ID of the giveaway, shown at the bottom of it"] giveaway_id: i64,
+) -> Result<(), Error> {
+    // Drawing checks every winner with Discord, which can take a while
+    ctx.defer_ephemeral().await?;
+    let giveaway = ctx.data().database.get_giveaway(giveaway_id).await?
+        .filter(|g| g.guild_id == ctx.guild_id().unwrap().to_string());
+    let text = match giveaway {
+        None => String::from("Giveaway not found."),
+        Some(g) if g.ended => String::from("That giveaway has already ended."),
+        Some(g) => {
+            end_giveaway(&ctx.serenity_context().http, &ctx.data().database, &g).await?;
+            String::from("Giveaway ended.")
+        }
+    };
+    ctx.send(CreateReply::default().content(text).ephemeral(true)).await?;
+    Ok(())
+}
+
+/// Draw new winners for an ended giveaway
+///
+/// Members who already won this giveaway can't win again.
+#[poise::command(slash_command, guild_only, default_member_permissions = "MANAGE_GUILD", rename = "reroll")]
+pub async fn giveaway_reroll(
+    ctx: Context<'_>,
+    #[description = "ID of the giveaway, shown at the bottom of it"] giveaway_id: i64,
+    #[description = "How many new winners to draw. Defaults to 1"]
+    #[min = 1]
+    #[max = 50]
+    winners: Option<u32>,
+) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+    let giveaway = ctx.data().database.get_giveaway(giveaway_id).await?
+        .filter(|g| g.guild_id == ctx.guild_id().unwrap().to_string());
+    let text = match giveaway {
+        None => String::from("Giveaway not found."),
+        Some(g) if !g.ended => String::from("That giveaway hasn't ended yet."),
+        Some(g) => {
+            let http = &ctx.serenity_context().http;
+            let winners = announce_winners(http, &ctx.data().database, &g, winners.unwrap_or(1) as usize).await?;
+            // The stored winners now include the new ones
+            if let Some(g) = ctx.data().database.get_giveaway(giveaway_id).await?.filter(|_| !winners.is_empty()) {
+                update_ended_message(http, &ctx.data().database, &g).await?;
+            }
+            info!("{} rerolled giveaway ID {}", ctx.author().name, giveaway_id);
+            if winners.is_empty() {
+                String::from("There's nobody left to draw.")
+            } else {
+                format!("Drew {}.", mention_list(&winners))
+            }
+        }
+    };
+    ctx.send(CreateReply::default().content(text).ephemeral(true)).await?;
+    Ok(())
+}
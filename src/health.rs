@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use poise::serenity_prelude::{ConnectionStage, ShardId, ShardManager};
+use serde_json::{json, Value};
+use crate::database::Database;
+use crate::http::{self, Response};
+
+/// When the last gateway event arrived. Starts out as the time the bot started, which gives it a grace period to
+/// connect.
+static LAST_EVENT: LazyLock<Mutex<Instant>> = LazyLock::new(|| Mutex::new(Instant::now()));
+
+pub fn event_received() {
+    if let Ok(mut last) = LAST_EVENT.lock() {
+        *last = Instant::now();
+    }
+}
+
+fn since_last_event() -> Duration {
+    LAST_EVENT.lock().map(|l| l.elapsed()).unwrap_or_default()
+}
+
+/// How often the shards are looked at for new heartbeat acknowledgements
+const HEARTBEAT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The last heartbeat latency seen of a shard, and when it was seen
+struct Heartbeat {
+    latency: Option<Duration>,
+    acked_at: Instant,
+}
+
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+static HEARTBEATS: LazyLock<Mutex<HashMap<ShardId, Heartbeat>>> = LazyLock::new(Mutex::default);
+
+/// Keep track of when each shard's heartbeat was last acknowledged, forever. Serenity only tells the latency, which it
+/// measures again on every acknowledgement, so a new latency means a new acknowledgement.
+async fn watch_heartbeats(shard_manager: Arc<ShardManager>) {
+    let mut interval = tokio::time::interval(HEARTBEAT_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let runners = shard_manager.runners.lock().await;
+        let Ok(mut heartbeats) = HEARTBEATS.lock() else {
+            return;
+        };
+        for (shard_id, runner) in runners.iter() {
+            let heartbeat = heartbeats.entry(*shard_id)
+                .or_insert(Heartbeat { latency: runner.latency, acked_at: Instant::now() });
+            let acknowledged = runner.latency.is_some() && runner.latency != heartbeat.latency;
+            if runner.stage == ConnectionStage::Connected && acknowledged {
+                heartbeat.latency = runner.latency;
+                heartbeat.acked_at = Instant::now();
+            }
+        }
+    }
+}
+
+/// Time since a shard's heartbeat was last acknowledged. Shards that were never looked at yet count as just started.
+fn since_heartbeat(shard_id: ShardId) -> Duration {
+    HEARTBEATS.lock().ok()
+        .and_then(|h| h.get(&shard_id).map(|h| h.acked_at.elapsed()))
+        .unwrap_or_default()
+}
+
+/// When the bot counts as unhealthy or not ready
+#[derive(Clone, Copy)]
+pub struct Thresholds {
+    /// Unhealthy when a shard's heartbeat wasn't acknowledged for this long, or no shard started within it. Zero
+    /// disables the check.
+    pub max_heartbeat_age: Duration,
+    /// Not ready when a shard's heartbeat latency is higher than this. Zero disables the check.
+    pub max_latency: Duration,
+}
+
+/// Liveness and readiness of the bot, with the details that went into them
+async fn check(shard_manager: &ShardManager, database: &Database, thresholds: Thresholds) -> (bool, bool, Value) {
+    let database_ok = database.ping().await.is_ok();
+    let is_recent = |age: Duration| thresholds.max_heartbeat_age.is_zero() || age <= thresholds.max_heartbeat_age;
+
+    let mut shards = vec![];
+    let mut shards_alive = true;
+    let mut shards_ok = true;
+    for (shard_id, runner) in shard_manager.runners.lock().await.iter() {
+        // A shard that stays disconnected gets no acknowledgements either
+        let heartbeat_age = since_heartbeat(*shard_id);
+        shards_alive &= is_recent(heartbeat_age);
+        let latency_ok = thresholds.max_latency.is_zero()
+            || runner.latency.is_none_or(|l| l <= thresholds.max_latency);
+        shards_ok &= runner.stage == ConnectionStage::Connected && latency_ok;
+        shards.push(json!({
+            "id": shard_id.0,
+            "stage": runner.stage.to_string(),
+            "latency_ms": runner.latency.map(|l| l.as_millis() as u64),
+            "seconds_since_heartbeat_ack": heartbeat_age.as_secs(),
+        }));
+    }
+    if shards.is_empty() {
+        shards_alive = is_recent(STARTED.elapsed());
+    }
+
+    let healthy = database_ok && shards_alive;
+    let ready = healthy && shards_ok && !shards.is_empty();
+    let details = json!({
+        "healthy": healthy,
+        "ready": ready,
+        "database": database_ok,
+        "seconds_since_last_event": since_last_event().as_secs(),
+        "shards": shards,
+    });
+    (healthy, ready, details)
+}
+
+/// Serve `/healthz` (the bot works, restart it otherwise) and `/readyz` (every shard is connected), forever.
+/// Both answer 200 or 503 with the details as JSON.
+pub async fn serve(address: SocketAddr, shard_manager: Arc<ShardManager>, database: Database, thresholds: Thresholds) {
+    LazyLock::force(&LAST_EVENT);
+    LazyLock::force(&STARTED);
+    tokio::spawn(watch_heartbeats(shard_manager.clone()));
+    http::serve("Health", address, move |path| {
+        let shard_manager = shard_manager.clone();
+        let database = database.clone();
+        async move {
+            let (healthy, ready, details) = match path.as_str() {
+                "/healthz" | "/readyz" => check(&shard_manager, &database, thresholds).await,
+                _ => return Response::text(404, "Not found\n"),
+            };
+            let ok = if path == "/healthz" { healthy } else { ready };
+            Response::json(if ok { 200 } else { 503 }, &details)
+        }
+    }).await
+}
@@ -0,0 +1,491 @@
+use log::{info, warn};
+use poise::serenity_prelude::{
+    Colour, CreateEmbed, CreateMessage, GuildId, Http, Mentionable, Message, Role, RoleId, User, UserId,
+};
+use poise::CreateReply;
+use rand::distr::{Distribution, Uniform};
+use rand::rng;
+use crate::{serenity, tools};
+use crate::{Context, Data, Error};
+
+/// XP per message is picked randomly from this range
+const MESSAGE_XP: std::ops::RangeInclusive<i64> = 15..=25;
+const DEFAULT_COOLDOWN: i64 = 60;
+const LEADERBOARD_SIZE: i64 = 10;
+
+/// Total XP needed for a level is `base * level ^ exponent`
+#[derive(Debug, Clone, Copy)]
+pub struct LevelCurve {
+    pub base: f64,
+    pub exponent: f64,
+}
+
+impl Default for LevelCurve {
+    fn default() -> Self {
+        Self { base: 100.0, exponent: 2.0 }
+    }
+}
+
+impl LevelCurve {
+    async fn load(data: &Data, guild_id: GuildId) -> Result<Self, Error> {
+        let defaults = Self::default();
+        let base = data.database.get_guild_value(&guild_id, &"config.leveling_base").await?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(defaults.base);
+        let exponent = data.database.get_guild_value(&guild_id, &"config.leveling_exponent").await?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(defaults.exponent);
+        Ok(Self { base, exponent })
+    }
+
+    /// Total XP needed to reach a level
+    pub fn xp_for_level(&self, level: i64) -> i64 {
+        (self.base * (level as f64).powf(self.exponent)).round() as i64
+    }
+
+    /// The level a member with this much XP is at
+    pub fn level_for_xp(&self, xp: i64) -> i64 {
+        let mut level = (xp.max(0) as f64 / self.base).powf(1.0 / self.exponent).floor() as i64;
+        // Correct for floating point rounding at the level boundaries
+        while self.xp_for_level(level + 1) <= xp {
+            level += 1;
+        }
+        while level > 0 && self.xp_for_level(level) > xp {
+            level -= 1;
+        }
+        level
+    }
+}
+
+async fn is_enabled(data: &Data, guild_id: GuildId) -> Result<bool, Error> {
+    Ok(data.database.get_guild_value(&guild_id, &"config.leveling").await?.is_some())
+}
+
+/// Give a member every reward role up to their level that they don't have yet
+async fn grant_rewards(http: &Http, data: &Data, guild_id: GuildId, user_id: UserId, level: i64, current_roles: &[RoleId])
+    -> Result<(), Error> {
+    for (reward_level, role) in data.database.get_level_rewards(&guild_id).await? {
+        let role = match tools::to_role(&role) {
+            Some(r) if reward_level <= level && !current_roles.contains(&r) => r,
+            _ => continue,
+        };
+        if let Err(e) = http.add_member_role(guild_id, user_id, role, Some("Level reward")).await {
+            warn!("Failed to give level reward {} to user ID {} in guild ID {}: {}", role, user_id, guild_id, e);
+        }
+    }
+    Ok(())
+}
+
+// Event handlers ->
+
+/// Give XP for a message and handle level ups
+pub async fn on_message(ctx: &serenity::Context, data: &Data, message: &Message) -> Result<(), Error> {
+    let guild_id = match message.guild_id {
+        Some(g) => g,
+        None => return Ok(()),
+    };
+    if message.author.bot || !is_enabled(data, guild_id).await? {
+        return Ok(());
+    }
+
+    let cooldown = data.database.get_guild_value(&guild_id, &"config.leveling_cooldown").await?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_COOLDOWN);
+    let amount = {
+        let mut generator = rng();
+        Uniform::try_from(MESSAGE_XP)?.sample(&mut generator)
+    };
+    let now = chrono::Utc::now().timestamp();
+    let new_xp = match data.database.award_member_xp(&guild_id, &message.author.id, amount, now, cooldown).await? {
+        Some(xp) => xp,
+        None => return Ok(()), // Still in cooldown
+    };
+
+    let curve = LevelCurve::load(data, guild_id).await?;
+    let old_level = curve.level_for_xp(new_xp - amount);
+    let new_level = curve.level_for_xp(new_xp);
+    if new_level <= old_level {
+        return Ok(());
+    }
+
+    info!("User ID {} reached level {} in guild ID {}", message.author.id, new_level, guild_id);
+    let current_roles = message.member.as_ref().map(|m| m.roles.clone()).unwrap_or_default();
+    grant_rewards(&ctx.http, data, guild_id, message.author.id, new_level, &current_roles).await?;
+
+    if data.database.get_guild_value(&guild_id, &"config.leveling_quiet").await?.is_none() {
+        message.channel_id.send_message(&ctx.http, CreateMessage::new().content(format!(
+            "🎉 {} reached level **{}**!", message.author.mention(), new_level
+        ))).await?;
+    }
+    Ok(())
+}
+
+// Commands ->
+
+/// Show your level and XP, or someone else's
+#[poise::command(slash_command, guild_only, default_member_permissions = "SEND_MESSAGES")]
+pub async fn rank(
+    ctx: Context<'_>,
+    #[description = "Whose rank to show. Defaults to you"] user: Option<User>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let user = user.as_ref().unwrap_or(ctx.author());
+    if !is_enabled(ctx.data(), guild_id).await? {
+        ctx.send(
+            CreateReply::default()
+                .content("Leveling is not enabled on this server.".to_string())
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let (xp, rank) = ctx.data().database.get_member_xp(&guild_id, &user.id).await?.unwrap_or((0, 0));
+    let curve = LevelCurve::load(ctx.data(), guild_id).await?;
+    let level = curve.level_for_xp(xp);
+    let current = curve.xp_for_level(level);
+    let next = curve.xp_for_level(level + 1);
+
+    // Draw a progress bar of 10 blocks
+    let filled = ((xp - current) * 10 / (next - current).max(1)) as usize;
+    let bar = format!("{}{}", "🟩".repeat(filled), "⬛".repeat(10 - filled.min(10)));
+
+    ctx.send(
+        CreateReply::default().embed(
+            CreateEmbed::new()
+                .title(format!("⭐ Rank of {}", user.display_name()))
+                .thumbnail(user.face())
+                .field("Level:", level.to_string(), true)
+                .field("XP:", xp.to_string(), true)
+                .field("Rank:", if rank > 0 { format!("#{}", rank) } else { String::from("Unranked") }, true)
+                .field(format!("Progress to level {}:", level + 1), format!("{}\n{} / {} XP", bar, xp, next), false)
+                .color(Colour::GOLD),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Show the members with the most XP
+#[poise::command(slash_command, guild_only, default_member_permissions = "SEND_MESSAGES")]
+pub async fn leaderboard(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let members = ctx.data().database.get_xp_leaderboard(&guild_id, LEADERBOARD_SIZE).await?;
+    let curve = LevelCurve::load(ctx.data(), guild_id).await?;
+
+    let text = if members.is_empty() {
+        String::from("Nobody has earned any XP yet.")
+    } else {
+        members.iter()
+            .enumerate()
+            .map(|(i, (user, xp))| format!("{}. <@{}> - level {} ({} XP)", i + 1, user, curve.level_for_xp(*xp), xp))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    ctx.send(
+        CreateReply::default().embed(
+            CreateEmbed::new()
+                .title("🏆 Leaderboard")
+                .description(text)
+                .color(Colour::GOLD),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Configure the leveling system
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    subcommands(
+        "leveling_enable",
+        "leveling_disable",
+        "leveling_curve",
+        "leveling_cooldown",
+        "leveling_announce",
+        "leveling_reward",
+        "leveling_xp"
+    ),
+    subcommand_required
+)]
+pub async fn leveling(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Enable leveling on this server
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "enable")]
+pub async fn leveling_enable(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.data().database.set_guild_value(&ctx.guild_id().unwrap(), &"config.leveling", &"").await?;
+    ctx.send(
+        CreateReply::default()
+            .content("Leveling enabled.".to_string())
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Disable leveling on this server
+///
+/// Members keep their XP.
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "disable")]
+pub async fn leveling_disable(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.data().database.delete_guild_value(&ctx.guild_id().unwrap(), &"config.leveling").await?;
+    ctx.send(
+        CreateReply::default()
+            .content("Leveling disabled.".to_string())
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Change how much XP each level needs
+///
+/// The total XP needed for a level is base × level ^ exponent.
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "curve")]
+pub async fn leveling_curve(
+    ctx: Context<'_>,
+    #[description = "XP needed for level 1. Defaults to 100"]
+    #[min = 1]
+    base: Option<u32>,
+    #[description = "How steeply the XP per level increases. Defaults to 2.0"]
+    #[min = 1.0]
+    #[max = 5.0]
+    exponent: Option<f64>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let database = &ctx.data().database;
+    match base {
+        Some(b) => database.set_guild_value(&guild_id, &"config.leveling_base", &b).await?,
+        None => {
+            database.delete_guild_value(&guild_id, &"config.leveling_base").await?;
+        }
+    }
+    match exponent {
+        Some(e) => database.set_guild_value(&guild_id, &"config.leveling_exponent", &e).await?,
+        None => {
+            database.delete_guild_value(&guild_id, &"config.leveling_exponent").await?;
+        }
+    }
+
+    let curve = LevelCurve::load(ctx.data(), guild_id).await?;
+    let examples = [1, 5, 10, 25, 50].iter()
+        .map(|l| format!("Level {}: {} XP", l, curve.xp_for_level(*l)))
+        .collect::<Vec<String>>()
+        .join("\n");
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Level curve updated.\n{}", examples))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Change how often members can earn XP
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "cooldown")]
+pub async fn leveling_cooldown(
+    ctx: Context<'_>,
+    #[description = "Seconds between messages that earn XP. Defaults to 60"]
+    #[max = 86400]
+    seconds: u32,
+) -> Result<(), Error> {
+    ctx.data()
+        .database
+        .set_guild_value(&ctx.guild_id().unwrap(), &"config.leveling_cooldown", &seconds)
+        .await?;
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Members can now earn XP once every {} seconds.", seconds))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Choose whether level ups are announced
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "announce")]
+pub async fn leveling_announce(
+    ctx: Context<'_>,
+    #[description = "Announce level ups in the channel they happened in"]
+    enabled: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    if enabled {
+        ctx.data().database.delete_guild_value(&guild_id, &"config.leveling_quiet").await?;
+    } else {
+        ctx.data().database.set_guild_value(&guild_id, &"config.leveling_quiet", &"").await?;
+    }
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Level up announcements {}.", if enabled { "enabled" } else { "disabled" }))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Manage roles that are given out at certain levels
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "reward",
+    subcommands("leveling_reward_add", "leveling_reward_remove", "leveling_reward_list"),
+    subcommand_required
+)]
+pub async fn leveling_reward(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Give a role to members who reach a level
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "add")]
+pub async fn leveling_reward_add(
+    ctx: Context<'_>,
+    #[description = "Level at which the role is given"]
+    #[min = 1]
+    level: u32,
+    #[description = "Role to give"]
+    role: Role,
+) -> Result<(), Error> {
+    if role.managed {
+        ctx.send(
+            CreateReply::default()
+                .content("That role can't be assigned by a bot.".to_string())
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+    ctx.data().database.set_level_reward(&ctx.guild_id().unwrap(), level as i64, &role.id).await?;
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Members reaching level {} will receive {}", level, role.mention()))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Stop giving a role as level reward
+///
+/// Members who already received the role keep it.
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "remove")]
+pub async fn leveling_reward_remove(
+    ctx: Context<'_>,
+    #[description = "Role to stop giving"]
+    role: Role,
+) -> Result<(), Error> {
+    let removed = ctx.data().database.delete_level_reward(&ctx.guild_id().unwrap(), &role.id).await?;
+    ctx.send(
+        CreateReply::default()
+            .content(if removed {
+                format!("{} is no longer a level reward.", role.mention())
+            } else {
+                format!("{} is not a level reward.", role.mention())
+            })
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Show all level rewards
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "list")]
+pub async fn leveling_reward_list(ctx: Context<'_>) -> Result<(), Error> {
+    let rewards = ctx.data().database.get_level_rewards(&ctx.guild_id().unwrap()).await?;
+    let text = if rewards.is_empty() {
+        String::from("No level rewards configured.")
+    } else {
+        rewards.iter()
+            .map(|(level, role)| format!("Level {}: <@&{}>", level, role))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+    ctx.send(
+        CreateReply::default()
+            .embed(CreateEmbed::new().title("🎁 Level rewards").description(text).color(Colour::BLITZ_BLUE))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Change a member's XP
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "xp",
+    subcommands("leveling_xp_set", "leveling_xp_add", "leveling_xp_reset"),
+    subcommand_required
+)]
+pub async fn leveling_xp(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Reply with a member's new XP total and grant any rewards they reached
+async fn report_xp_change(ctx: Context<'_>, user: &User, xp: i64) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let level = LevelCurve::load(ctx.data(), guild_id).await?.level_for_xp(xp);
+    let current_roles = ctx.guild()
+        .and_then(|g| g.members.get(&user.id).map(|m| m.roles.clone()))
+        .unwrap_or_default();
+    grant_rewards(&ctx.serenity_context().http, ctx.data(), guild_id, user.id, level, &current_roles).await?;
+    ctx.send(
+        CreateReply::default()
+            .content(format!("{} now has {} XP (level {}).", user.mention(), xp, level))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Set a member's XP
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "set")]
+pub async fn leveling_xp_set(
+    ctx: Context<'_>,
+    #[description = "Member whose XP to set"] user: User,
+    #[description = "New XP total"] xp: u32,
+) -> Result<(), Error> {
+    ctx.data().database.set_member_xp(&ctx.guild_id().unwrap(), &user.id, xp as i64).await?;
+    info!("{} set the XP of user ID {} to {}", ctx.author().name, user.id, xp);
+    report_xp_change(ctx, &user, xp as i64).await
+}
+
+/// Give or take XP from a member
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "add")]
+pub async fn leveling_xp_add(
+    ctx: Context<'_>,
+    #[description = "Member to give XP to"] user: User,
+    #[description = "XP to add. Use a negative number to take XP away"] amount: i32,
+) -> Result<(), Error> {
+    let xp = ctx.data().database.add_member_xp(&ctx.guild_id().unwrap(), &user.id, amount as i64).await?;
+    info!("{} added {} XP to user ID {}", ctx.author().name, amount, user.id);
+    report_xp_change(ctx, &user, xp).await
+}
+
+/// Reset the XP of a member, or of everyone
+#[poise::command(slash_command, guild_only, default_member_permissions = "ADMINISTRATOR", rename = "reset")]
+pub async fn leveling_xp_reset(
+    ctx: Context<'_>,
+    #[description = "Member whose XP to reset. Leave empty to reset everyone"] user: Option<User>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let count = ctx.data().database.delete_member_xp(&guild_id, user.as_ref().map(|u| &u.id)).await?;
+    info!("{} reset the XP of {} members in guild ID {}", ctx.author().name, count, guild_id);
+    ctx.send(
+        CreateReply::default()
+            .content(match user {
+                Some(u) => format!("Reset the XP of {}.", u.mention()),
+                None => format!("Reset the XP of {} members.", count),
+            })
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use log::{debug, warn};
+use poise::serenity_prelude::{ChannelId, CreateEmbed, CreateMessage, Http, HttpError};
+use tokio::sync::mpsc;
+use crate::serenity;
+
+/// Most embeds Discord allows in one message
+const MAX_EMBEDS: usize = 10;
+/// Most characters Discord allows across all embeds of one message
+const MAX_EMBED_CHARACTERS: usize = 6000;
+/// How many log messages may wait per channel before new ones get dropped
+const MAX_PENDING: usize = 1000;
+/// How long to wait for more log messages to batch with the first one
+const BATCH_WINDOW: Duration = Duration::from_millis(500);
+/// Delays between retries double from the first to the last, which is then kept
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const LAST_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// How long a batch keeps being retried before it's given up on
+const MAX_OUTAGE: Duration = Duration::from_secs(600);
+
+/// Queue of every log channel in use
+static QUEUES: LazyLock<Mutex<HashMap<ChannelId, mpsc::Sender<CreateEmbed>>>> = LazyLock::new(Mutex::default);
+/// Log messages that were queued, but haven't been sent or given up on yet
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+/// Log messages that were given up on
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+pub fn pending() -> usize {
+    PENDING.load(Ordering::SeqCst)
+}
+
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::SeqCst)
+}
+
+/// Queue a log message for a log channel. It's sent in the background, batched with other log messages for the same
+/// channel that arrive around the same time.
+pub fn send(http: &Arc<Http>, channel_id: ChannelId, embed: CreateEmbed) {
+    let Ok(mut queues) = QUEUES.lock() else {
+        return;
+    };
+    let sender = queues.entry(channel_id).or_insert_with(|| {
+        let (sender, receiver) = mpsc::channel(MAX_PENDING);
+        tokio::spawn(run(http.clone(), channel_id, receiver));
+        sender
+    });
+    PENDING.fetch_add(1, Ordering::SeqCst);
+    if sender.try_send(embed).is_err() {
+        PENDING.fetch_sub(1, Ordering::SeqCst);
+        DROPPED.fetch_add(1, Ordering::SeqCst);
+        warn!("Log queue for channel ID {} is full, dropping a log message", channel_id);
+    }
+}
+
+/// Character count of an embed as Discord counts it towards the per-message limit
+fn embed_length(embed: &CreateEmbed) -> usize {
+    let value = serde_json::to_value(embed).unwrap_or_default();
+    let text = |v: &serde_json::Value| v.as_str().map(|s| s.chars().count()).unwrap_or_default();
+    let fields: usize = value["fields"].as_array()
+        .map(|f| f.iter().map(|f| text(&f["name"]) + text(&f["value"])).sum())
+        .unwrap_or_default();
+    text(&value["title"]) + text(&value["description"]) + text(&value["footer"]["text"])
+        + text(&value["author"]["name"]) + fields
+}
+
+/// Whether sending could succeed if tried again later. Rate limits are already waited out by serenity itself.
+fn is_transient(error: &serenity::Error) -> bool {
+    match error {
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) => {
+            response.status_code.is_server_error() || response.status_code.as_u16() == 429
+        }
+        serenity::Error::Http(HttpError::Request(_)) => true,
+        _ => false,
+    }
+}
+
+/// Send one batch, retrying with backoff while Discord is unreachable. Returns whether it was sent.
+async fn send_batch(http: &Http, channel_id: ChannelId, batch: &[CreateEmbed]) -> bool {
+    let started = Instant::now();
+    let mut delay = FIRST_RETRY_DELAY;
+    loop {
+        let error = match channel_id.send_message(http, CreateMessage::new().embeds(batch.to_vec())).await {
+            Ok(_) => return true,
+            Err(e) => e,
+        };
+        if !is_transient(&error) {
+            warn!("Failed to send {} log message(s) to channel ID {}: {}", batch.len(), channel_id, error);
+            return false;
+        }
+        if started.elapsed() + delay > MAX_OUTAGE {
+            warn!(
+                "Giving up on {} log message(s) for channel ID {} after retrying for {}s: {}",
+                batch.len(),
+                channel_id,
+                started.elapsed().as_secs(),
+                error
+            );
+            return false;
+        }
+        debug!("Failed to send log messages to channel ID {}, retrying in {}s: {}", channel_id, delay.as_secs(), error);
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(LAST_RETRY_DELAY);
+    }
+}
+
+/// Send the log messages of one channel, in order, for as long as the bot runs
+async fn run(http: Arc<Http>, channel_id: ChannelId, mut receiver: mpsc::Receiver<CreateEmbed>) {
+    // Left over from the previous batch because it would have gone over the character limit
+    let mut carry = None;
+    loop {
+        let first = match carry.take() {
+            Some(embed) => embed,
+            None => match receiver.recv().await {
+                Some(embed) => embed,
+                None => return,
+            },
+        };
+        let mut length = embed_length(&first);
+        let mut batch = vec![first];
+
+        let deadline = tokio::time::Instant::now() + BATCH_WINDOW;
+        while batch.len() < MAX_EMBEDS {
+            let embed = match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(embed)) => embed,
+                _ => break,
+            };
+            let embed_length = embed_length(&embed);
+            if length + embed_length > MAX_EMBED_CHARACTERS {
+                carry = Some(embed);
+                break;
+            }
+            length += embed_length;
+            batch.push(embed);
+        }
+
+        if !send_batch(&http, channel_id, &batch).await {
+            DROPPED.fetch_add(batch.len(), Ordering::SeqCst);
+        }
+        PENDING.fetch_sub(batch.len(), Ordering::SeqCst);
+    }
+}
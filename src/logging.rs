@@ -0,0 +1,200 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use chrono::{NaiveDate, Utc};
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Module prefix of our own log records. Everything else only gets logged from the error level up.
+const OWN_TARGET: &str = "discord_bot";
+
+fn level_for(target: &str, own_level: LevelFilter) -> LevelFilter {
+    if target.starts_with(OWN_TARGET) { own_level } else { LevelFilter::Error }
+}
+
+/// Log file settings
+pub struct FileSettings {
+    pub path: String,
+    pub level: LevelFilter,
+    /// Start a new file once the current one reaches this many bytes. 0 disables size based rotation.
+    pub max_size: u64,
+    /// Start a new file every day (UTC)
+    pub daily: bool,
+    /// How many rotated files to keep next to the current one
+    pub retention: usize,
+    /// Write one JSON object per line instead of plain text
+    pub json: bool,
+}
+
+/// A log file that moves itself aside and starts over when it grows too big or a new day starts.
+/// Rotated files are named after the log file plus the time they were rotated, e.g. `bot.log.20240131-235959`.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened_on: NaiveDate,
+    max_size: u64,
+    daily: bool,
+    retention: usize,
+}
+
+impl RotatingFile {
+    fn open(settings: &FileSettings) -> std::io::Result<Self> {
+        let path = PathBuf::from(&settings.path);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        let opened_on = match file.metadata()?.modified() {
+            Ok(m) => chrono::DateTime::<Utc>::from(m).date_naive(),
+            Err(_) => Utc::now().date_naive(),
+        };
+        Ok(Self {
+            path,
+            file,
+            size,
+            opened_on,
+            max_size: settings.max_size,
+            daily: settings.daily,
+            retention: settings.retention,
+        })
+    }
+
+    fn needs_rotation(&self, incoming: u64) -> bool {
+        let too_big = self.max_size > 0 && self.size > 0 && self.size + incoming > self.max_size;
+        let new_day = self.daily && Utc::now().date_naive() != self.opened_on;
+        too_big || new_day
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        let rotated = format!("{}.{}", self.path.display(), Utc::now().format("%Y%m%d-%H%M%S"));
+        std::fs::rename(&self.path, rotated)?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        self.opened_on = Utc::now().date_naive();
+        self.remove_old_files()
+    }
+
+    /// Delete the oldest rotated files, keeping `retention` of them
+    fn remove_old_files(&self) -> std::io::Result<()> {
+        let directory = match self.path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let prefix = match self.path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => format!("{}.", n),
+            None => return Ok(()),
+        };
+        let mut rotated = std::fs::read_dir(&directory)?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_str().is_some_and(|n| n.starts_with(&prefix)))
+            .map(|e| e.path())
+            .collect::<Vec<PathBuf>>();
+        // The timestamp suffix sorts chronologically
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.retention);
+        for old in &rotated[..excess] {
+            std::fs::remove_file(old)?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.needs_rotation(line.len() as u64) {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+fn format_text(record: &Record) -> String {
+    format!(
+        "{} {:<5} {}: {}\n",
+        Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+        record.level(),
+        record.target(),
+        record.args()
+    )
+}
+
+fn format_json(record: &Record) -> String {
+    let line = serde_json::json!({
+        "timestamp": Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    });
+    format!("{}\n", line)
+}
+
+struct FileLogger {
+    level: LevelFilter,
+    json: bool,
+    file: Mutex<RotatingFile>,
+}
+
+/// Logs to the console, and to a file if configured, each with their own level
+struct Logger {
+    console: env_logger::Logger,
+    file: Option<FileLogger>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+            || self.file.as_ref().is_some_and(|f| metadata.level() <= level_for(metadata.target(), f.level))
+    }
+
+    fn log(&self, record: &Record) {
+        self.console.log(record);
+        if let Some(file) = &self.file {
+            if record.level() > level_for(record.target(), file.level) {
+                return;
+            }
+            let line = if file.json { format_json(record) } else { format_text(record) };
+            if let Ok(mut f) = file.file.lock() {
+                // There's nowhere left to report a failing log file to but the console
+                if let Err(e) = f.write_line(&line) {
+                    eprintln!("Failed to write to log file {}: {}", f.path.display(), e);
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        if let Some(file) = &self.file {
+            if let Ok(mut f) = file.file.lock() {
+                let _ = f.file.flush();
+            }
+        }
+    }
+}
+
+/// Set up logging. Fails if the log file can't be opened.
+pub fn init(console_level: LevelFilter, file: Option<FileSettings>) -> Result<(), String> {
+    let console = env_logger::Builder::default()
+        .filter_level(LevelFilter::Error) // Everything except our own code only prints >= error
+        .filter_module(OWN_TARGET, console_level) // Our own code
+        .build();
+
+    let file = match file {
+        Some(settings) => {
+            if let Some(parent) = Path::new(&settings.path).parent() {
+                if !parent.as_os_str().is_empty() && !parent.is_dir() {
+                    return Err(format!("Log file directory {} doesn't exist", parent.display()));
+                }
+            }
+            let rotating = RotatingFile::open(&settings)
+                .map_err(|e| format!("Can't open log file {}: {}", settings.path, e))?;
+            Some(FileLogger { level: settings.level, json: settings.json, file: Mutex::new(rotating) })
+        }
+        None => None,
+    };
+
+    let max_level = console.filter().max(file.as_ref().map(|f| f.level).unwrap_or(LevelFilter::Off));
+    log::set_boxed_logger(Box::new(Logger { console, file })).map_err(|e| e.to_string())?;
+    log::set_max_level(max_level);
+    Ok(())
+}
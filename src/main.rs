@@ -1,52 +1,44 @@
 use std::time::Duration;
-use log::{debug, info, warn, error, LevelFilter};
+use log::{debug, info, warn, error};
 use clap::{Parser, crate_version, crate_description, crate_authors};
 use poise::serenity_prelude as serenity;
 use poise::serenity_prelude::model::Timestamp;
 use poise::serenity_prelude::Settings;
 use tokio::signal;
+use crate::config::{Args, Config};
 use crate::database::Database;
 
 mod commands;
+mod config;
 mod events;
 mod database;
+mod logging;
+mod http;
+mod metrics;
+mod health;
+mod shutdown;
+mod log_queue;
+mod backup;
+mod retention;
+mod mydata;
 mod tools;
 mod ai;
+mod welcome;
+mod autorole;
+mod raid;
+mod automod;
+mod stats;
+mod leveling;
+mod polls;
+mod giveaways;
+mod starboard;
+mod tags;
+mod fortunes;
 
 // Types used by all command functions
 type Error = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Data, Error>;
 
-#[derive(Parser, Debug)]
-#[command(version, about, author)]
-struct Args {
-    /// Directory path to SQLite db storage
-    #[arg(short, long)]
-    db_path: String,
-
-    /// Discord bot token
-    #[arg(short, long)]
-    bot_token: String,
-
-    /// Log level for the CLI. Default is Info.
-    #[arg(long, value_enum)]
-    log_level: Option<LevelFilter>,
-
-    /// Log file path. When omitted, no log file is used.
-    #[arg(long)]
-    log_file_path: Option<String>,
-
-    /// Log level for the log file
-    #[arg(long, value_enum)]
-    log_file_level: Option<LevelFilter>,
-    
-    /// Cooldown in seconds for the fortune command
-    #[arg(long)]
-    fortune_cooldown: Option<i64>,
-
-    // todo AI settings
-}
-
 // Custom user data passed to all command functions
 pub struct Data {
     // Read only attributes
@@ -57,6 +49,13 @@ pub struct Data {
     app_authors: String,
     database: Database,
     fortune_cooldown: i64,
+    fortunes: fortunes::FortunePacks,
+    raid_tracker: raid::RaidTracker,
+    automod: automod::AutomodState,
+    starboard: starboard::StarboardState,
+    backups: backup::BackupSettings,
+    /// Days to keep the data of guilds the bot was removed from
+    guild_retention: u64,
 }
 
 async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
@@ -66,9 +65,17 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
     match error {
         poise::FrameworkError::Setup { error, .. } => panic!("Failed to start bot: {:?}", error),
         poise::FrameworkError::Command { error, ctx, .. } => {
+            shutdown::command_finished(ctx.id());
+            metrics::command_failed(&ctx.command().qualified_name);
             error!("Error in command `{}`: {:?}", ctx.command().name, error,);
         }
+        // Commands are turned away while shutting down, which isn't worth reporting
+        poise::FrameworkError::CommandCheckFailed { error: None, .. } if shutdown::is_requested() => {}
         error => {
+            if let Some(ctx) = error.ctx() {
+                shutdown::command_finished(ctx.id());
+                metrics::command_failed(&ctx.command().qualified_name);
+            }
             if let Err(e) = poise::builtins::on_error(error).await {
                 error!("Error while handling error: {}", e)
             }
@@ -79,25 +86,75 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    let print_config = args.print_config;
+    let backup_now = args.backup_now;
+    let orphan_report = args.orphan_report;
+    let restore = args.restore.clone();
+    let migrate_from_sqlite = args.migrate_from_sqlite.clone();
+    let config = match Config::load(args) {
+        Ok(c) => c,
+        Err(errors) => {
+            // The logger isn't set up yet, since its settings are part of the configuration
+            for e in errors {
+                eprintln!("Configuration error: {}", e);
+            }
+            std::process::exit(2);
+        }
+    };
+    if print_config {
+        print!("{}", config.to_redacted_toml());
+        return;
+    }
 
-    env_logger::Builder::default()
-        .filter_level(LevelFilter::Error) // Everything except our own code only prints >= error
-        .filter_module("discord_bot", args.log_level.unwrap_or(LevelFilter::Info)) // Our own code
-        .init();
+    if let Err(e) = logging::init(config.log_level, config.log_file()) {
+        eprintln!("Failed to set up logging: {}", e);
+        std::process::exit(2);
+    }
     let time_started = Timestamp::now();
     info!("Application starting");
-    debug!("Arguments: {:?}", args);
+    debug!("Configuration:\n{}", config.to_redacted_toml());
 
-    let db_path = args.db_path.clone();
-    let db;
-    match Database::new(&db_path).await {
-        Ok(d) => {
-            db = d;
+    let db_path = config.db_path.clone();
+    if let Some(sqlite_path) = migrate_from_sqlite {
+        if let Err(e) = database::migrate_from_sqlite(&sqlite_path, &db_path).await {
+            error!("Migration failed: {}", e);
+            std::process::exit(1);
         }
+        return;
+    }
+    if let Some(backup_path) = restore {
+        if let Err(e) = backup::restore(&db_path, &backup_path).await {
+            error!("Restore failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let db = match Database::new(&db_path).await {
+        Ok(d) => d,
         Err(e) => {
             error!("Failed to initialize database: {}", e);
             std::process::exit(1);
         }
+    };
+
+    if backup_now {
+        if let Err(e) = backup::create(&db, &config.backups()).await {
+            error!("Backup failed: {}", e);
+            std::process::exit(1);
+        }
+        db.close().await;
+        return;
+    }
+    if orphan_report {
+        match retention::report(&db, config.guild_retention).await {
+            Ok(report) => print!("{}", report),
+            Err(e) => {
+                error!("Orphan report failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        db.close().await;
+        return;
     }
 
     debug!("Configuring Poise");
@@ -109,12 +166,29 @@ async fn main() {
             commands::ping(),
             commands::fortune(),
             commands::fortune_reset(),
+            fortunes::fortunes(),
             // todo fortuneteller only when AI is available
             commands::roll(),
             commands::number(),
             commands::coinflip(),
             commands::yesno(),
             commands::track(),
+            welcome::welcome(),
+            autorole::autorole(),
+            raid::raid(),
+            automod::automod(),
+            stats::stats(),
+            stats::activity(),
+            leveling::rank(),
+            leveling::leaderboard(),
+            leveling::leveling(),
+            polls::poll(),
+            giveaways::giveaway(),
+            starboard::starboard(),
+            tags::tag(),
+            mydata::mydata(),
+            backup::backup(),
+            retention::purgeguild(),
         ],
 
         prefix_options: poise::PrefixFrameworkOptions {
@@ -141,7 +215,7 @@ async fn main() {
         // Every command invocation must pass this check to continue execution
         command_check: Some(|ctx| {
             Box::pin(async move {
-                commands::command_check(ctx)
+                commands::command_check(ctx).await
             })
         }),
 
@@ -156,7 +230,7 @@ async fn main() {
     };
 
     debug!("Gathering client settings");
-    let token = args.bot_token.clone();
+    let token = config.bot_token.clone();
     let intents = serenity::GatewayIntents::non_privileged() |
         serenity::GatewayIntents::MESSAGE_CONTENT | serenity::GatewayIntents::GUILD_MEMBERS;
 
@@ -167,8 +241,14 @@ async fn main() {
         app_version: crate_version!().to_string(),
         app_description: crate_description!().to_string(),
         app_authors: crate_authors!("\n").to_string(),
-        database: db,
-        fortune_cooldown: args.fortune_cooldown.unwrap_or(600),
+        database: db.clone(),
+        fortune_cooldown: config.fortune_cooldown,
+        fortunes: fortunes::FortunePacks::load(&config.fortune_path),
+        raid_tracker: raid::RaidTracker::default(),
+        automod: automod::AutomodState::default(),
+        starboard: starboard::StarboardState::default(),
+        backups: config.backups(),
+        guild_retention: config.guild_retention,
     };
 
     debug!("Setting up Serenity client");
@@ -177,7 +257,29 @@ async fn main() {
     cache_settings.time_to_live = Duration::from_secs(60*60*24*3);
     
     let framework = poise::Framework::builder()
-        .setup(move |_ctx, _ready, _framework| { Box::pin(async move { Ok(global_data) }) })
+        .setup(move |ctx, _ready, _framework| {
+            Box::pin(async move {
+                // Background jobs
+                shutdown::spawn_job("polls", polls::close_expired_polls(ctx.clone(), global_data.database.clone()));
+                shutdown::spawn_job(
+                    "giveaways",
+                    giveaways::end_expired_giveaways(ctx.clone(), global_data.database.clone())
+                );
+                shutdown::spawn_job(
+                    "auto-roles",
+                    autorole::assign_due_roles(ctx.clone(), global_data.database.clone())
+                );
+                shutdown::spawn_job(
+                    "backups",
+                    backup::run_scheduled(global_data.database.clone(), global_data.backups.clone())
+                );
+                shutdown::spawn_job(
+                    "guild retention",
+                    retention::run_scheduled(global_data.database.clone(), ctx.cache.clone(), global_data.guild_retention)
+                );
+                Ok(global_data)
+            })
+        })
         .options(options)
         .build();
     
@@ -195,6 +297,14 @@ async fn main() {
         }
     }
 
+    if let Some(address) = config.metrics_address {
+        tokio::spawn(metrics::serve(address, client.shard_manager.clone(), client.cache.clone()));
+    }
+    if let Some(address) = config.health_address {
+        let thresholds = config.health_thresholds();
+        tokio::spawn(health::serve(address, client.shard_manager.clone(), db.clone(), thresholds));
+    }
+
     // Set up SIGTERM handling (Unix-only)
     #[cfg(unix)]
     let terminate = async {
@@ -207,16 +317,17 @@ async fn main() {
     #[cfg(not(unix))]
     let terminate = std::future::pending::<()>(); // Never resolves on non-Unix platforms
 
+    let grace = Duration::from_secs(config.shutdown_grace);
     info!("Connecting to Discord");
     // Create a future that completes when we receive either Ctrl+C/SIGINT or SIGTERM
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {
             warn!("Received SIGINT, shutting down...");
-            client.shard_manager.shutdown_all().await;
+            shutdown::run(client.shard_manager.clone(), db, grace).await;
         }
         _ = terminate => {
             warn!("Received SIGTERM, shutting down...");
-            client.shard_manager.shutdown_all().await;
+            shutdown::run(client.shard_manager.clone(), db, grace).await;
         }
 
         _ = client.start_autosharded() => {
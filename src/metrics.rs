@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Instant;
+use poise::serenity_prelude::{Cache, ShardManager};
+use crate::http::{self, Response};
+
+/// Metrics are always collected since that's cheap, but only exposed when the metrics server is enabled
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Upper bounds of the query duration histogram buckets, in seconds
+const QUERY_BUCKETS: [f64; 9] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; QUERY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(QUERY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+type Counters = Mutex<BTreeMap<String, u64>>;
+
+#[derive(Default)]
+struct Metrics {
+    commands_started: Counters,
+    commands_completed: Counters,
+    command_errors: Counters,
+    events: Counters,
+    queries: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+fn increment(counters: &Counters, name: &str) {
+    if let Ok(mut c) = counters.lock() {
+        *c.entry(name.to_string()).or_default() += 1;
+    }
+}
+
+pub fn command_started(name: &str) {
+    increment(&METRICS.commands_started, name);
+}
+
+pub fn command_completed(name: &str) {
+    increment(&METRICS.commands_completed, name);
+}
+
+pub fn command_failed(name: &str) {
+    increment(&METRICS.command_errors, name);
+}
+
+pub fn event_received(name: &str) {
+    increment(&METRICS.events, name);
+}
+
+/// Measures a database query from creation until it's dropped
+pub struct QueryTimer {
+    query: &'static str,
+    started: Instant,
+}
+
+impl Drop for QueryTimer {
+    fn drop(&mut self) {
+        if let Ok(mut q) = METRICS.queries.lock() {
+            q.entry(self.query).or_default().observe(self.started.elapsed().as_secs_f64());
+        }
+    }
+}
+
+/// Start timing a database query. Keep the returned timer alive for as long as the query runs.
+pub fn time_query(query: &'static str) -> QueryTimer {
+    QueryTimer { query, started: Instant::now() }
+}
+
+/// Escape a label value for the Prometheus text format
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn render_counters(output: &mut String, name: &str, help: &str, label: &str, counters: &Counters) {
+    let _ = writeln!(output, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    if let Ok(c) = counters.lock() {
+        for (key, value) in c.iter() {
+            let _ = writeln!(output, "{}{{{}=\"{}\"}} {}", name, label, escape(key), value);
+        }
+    }
+}
+
+/// Render all metrics in the Prometheus text format
+async fn render(shard_manager: &ShardManager, cache: &Cache) -> String {
+    let mut output = String::new();
+    render_counters(&mut output, "discord_bot_commands_started_total", "Commands invoked", "command",
+        &METRICS.commands_started);
+    render_counters(&mut output, "discord_bot_commands_completed_total", "Commands that finished successfully",
+        "command", &METRICS.commands_completed);
+    render_counters(&mut output, "discord_bot_command_errors_total", "Commands that failed", "command",
+        &METRICS.command_errors);
+    render_counters(&mut output, "discord_bot_events_total", "Gateway events received", "event", &METRICS.events);
+
+    let name = "discord_bot_db_query_duration_seconds";
+    let _ = writeln!(output, "# HELP {} Time spent on database operations\n# TYPE {} histogram", name, name);
+    if let Ok(queries) = METRICS.queries.lock() {
+        for (query, histogram) in queries.iter() {
+            for (count, bound) in histogram.buckets.iter().zip(QUERY_BUCKETS) {
+                let _ = writeln!(output, "{}_bucket{{query=\"{}\",le=\"{}\"}} {}", name, query, bound, count);
+            }
+            let _ = writeln!(output, "{}_bucket{{query=\"{}\",le=\"+Inf\"}} {}", name, query, histogram.count);
+            let _ = writeln!(output, "{}_sum{{query=\"{}\"}} {}", name, query, histogram.sum);
+            let _ = writeln!(output, "{}_count{{query=\"{}\"}} {}", name, query, histogram.count);
+        }
+    }
+
+    let name = "discord_bot_gateway_latency_seconds";
+    let _ = writeln!(output, "# HELP {} Heartbeat latency of each shard\n# TYPE {} gauge", name, name);
+    for (shard_id, runner) in shard_manager.runners.lock().await.iter() {
+        if let Some(latency) = runner.latency {
+            let _ = writeln!(output, "{}{{shard=\"{}\"}} {}", name, shard_id, latency.as_secs_f64());
+        }
+    }
+
+    let name = "discord_bot_guilds";
+    let _ = writeln!(output, "# HELP {} Guilds the bot is in\n# TYPE {} gauge", name, name);
+    let _ = writeln!(output, "{} {}", name, cache.guild_count());
+    output
+}
+
+/// Serve the metrics at `/metrics`, forever
+pub async fn serve(address: SocketAddr, shard_manager: Arc<ShardManager>, cache: Arc<Cache>) {
+    http::serve("Metrics", address, move |path| {
+        let shard_manager = shard_manager.clone();
+        let cache = cache.clone();
+        async move {
+            match path.as_str() {
+                "/metrics" => Response {
+                    status: 200,
+                    content_type: "text/plain; version=0.0.4; charset=utf-8",
+                    body: render(&shard_manager, &cache).await,
+                },
+                _ => Response::text(404, "Not found\n"),
+            }
+        }
+    }).await
+}
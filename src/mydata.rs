@@ -0,0 +1,105 @@
+use chrono::Utc;
+use log::{info, warn};
+use poise::serenity_prelude::{
+    ButtonStyle, ComponentInteraction, CreateActionRow, CreateAttachment, CreateButton, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage,
+};
+use poise::CreateReply;
+use crate::serenity;
+use crate::{Context, Data, Error};
+
+/// Followed by the ID of the user whose data the button deletes
+pub const DELETE_PREFIX: &str = "mydata_delete:";
+pub const CANCEL_BUTTON_ID: &str = "mydata_cancel";
+/// User values that survive a deletion, so it can't be used to get around the fortune cooldown or to be tracked again
+/// after opting out of activity statistics
+pub const KEPT_KEYS: [&str; 3] = ["fortune_last_time", "fortune_last", "stats_optout"];
+
+async fn respond(ctx: &serenity::Context, component: &ComponentInteraction, text: &str) -> Result<(), Error> {
+    // Replace the confirmation, so its buttons can't be clicked again
+    component.create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new().content(text).components(vec![])
+    )).await?;
+    Ok(())
+}
+
+/// Handle a click on the buttons of the data deletion confirmation
+pub async fn on_component(ctx: &serenity::Context, data: &Data, component: &ComponentInteraction) -> Result<(), Error> {
+    let Some(user_id) = component.data.custom_id.strip_prefix(DELETE_PREFIX) else {
+        return respond(ctx, component, "Nothing was deleted.").await;
+    };
+    // The confirmation is ephemeral, but check anyway that nobody deletes someone else's data
+    if user_id != component.user.id.to_string() {
+        return respond(ctx, component, "You can only delete your own data.").await;
+    }
+    let rows = data.database.delete_user_data(&component.user.id, &KEPT_KEYS).await?;
+    info!("{} deleted their data, {} row(s)", component.user.name, rows);
+    respond(ctx, component, &format!("Deleted your data, {} record(s).", rows)).await
+}
+
+// Commands ->
+
+/// See or delete the data the bot stores about you
+#[poise::command(
+    slash_command,
+    default_member_permissions = "SEND_MESSAGES",
+    subcommands("mydata_export", "mydata_delete"),
+    subcommand_required
+)]
+pub async fn mydata(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Get a file with everything the bot stores about you in a DM
+#[poise::command(slash_command, default_member_permissions = "SEND_MESSAGES", rename = "export")]
+pub async fn mydata_export(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+    let user = ctx.author();
+    let tables = ctx.data().database.export_user_data(&user.id).await?;
+    let export = serde_json::json!({
+        "user_id": user.id.to_string(),
+        "exported_at": Utc::now().to_rfc3339(),
+        "tables": tables,
+    });
+    let file = CreateAttachment::bytes(serde_json::to_vec_pretty(&export)?, format!("mydata-{}.json", user.id));
+
+    let text = match user.direct_message(ctx.http(),
+        CreateMessage::new()
+            .content("Here's everything the bot stores about you, including what you created in servers.")
+            .add_file(file)
+    ).await {
+        Ok(_) => "Sent your data to your DMs.",
+        Err(e) => {
+            warn!("Failed to send data export to user ID {}: {}", user.id, e);
+            "I can't DM you. Allow direct messages from server members and try again."
+        }
+    };
+    ctx.send(CreateReply::default().content(text).ephemeral(true)).await?;
+    Ok(())
+}
+
+/// Delete everything the bot stores about you
+#[poise::command(slash_command, default_member_permissions = "SEND_MESSAGES", rename = "delete")]
+pub async fn mydata_delete(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.send(
+        CreateReply::default()
+            .content(
+                "This deletes everything the bot stores about you in every server: your settings, fortune history, \
+                 activity statistics, XP, poll votes and giveaway entries. Your activity opt-out stays, and so do \
+                 your last fortune and when you got it, so the fortune cooldown still applies. Tags, polls, \
+                 giveaways and fortunes you created stay too, since they belong to their server. This can't be \
+                 undone.",
+            )
+            .components(vec![CreateActionRow::Buttons(vec![
+                CreateButton::new(format!("{}{}", DELETE_PREFIX, ctx.author().id))
+                    .label("Delete my data")
+                    .style(ButtonStyle::Danger),
+                CreateButton::new(CANCEL_BUTTON_ID)
+                    .label("Cancel")
+                    .style(ButtonStyle::Secondary),
+            ])])
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,301 @@
+use std::time::Duration;
+use log::{info, warn};
+use poise::serenity_prelude::{
+    ButtonStyle, Colour, ComponentInteraction, ComponentInteractionDataKind, CreateActionRow, CreateButton,
+    CreateEmbed, CreateEmbedFooter, CreateInteractionResponse, CreateInteractionResponseMessage, CreateSelectMenu,
+    CreateSelectMenuKind, CreateSelectMenuOption, EditMessage, Http, Timestamp,
+};
+use poise::CreateReply;
+use crate::database::{Database, Poll};
+use crate::{serenity, shutdown, tools};
+use crate::{Context, Data, Error};
+
+/// Custom ID prefix of the vote buttons and select menus, followed by the poll ID (and option index)
+pub const VOTE_PREFIX: &str = "poll:";
+/// Custom ID prefix of the close button, followed by the poll ID
+pub const CLOSE_PREFIX: &str = "poll_close:";
+/// How often to check for polls that reached their end time
+const CLOSE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Draw a bar chart line for one option
+fn chart_line(option: &str, votes: usize, total: usize) -> String {
+    let percentage = (votes * 100).checked_div(total).unwrap_or(0);
+    let filled = percentage / 10;
+    format!(
+        "**{}**\n`{}{}` {} ({}%)",
+        option,
+        "█".repeat(filled),
+        "░".repeat(10 - filled),
+        votes,
+        percentage
+    )
+}
+
+/// Build the poll embed from its current votes
+fn poll_embed(poll: &Poll, votes: &[(String, i64)]) -> CreateEmbed {
+    let voters = {
+        let mut users = votes.iter().map(|(u, _)| u).collect::<Vec<&String>>();
+        users.sort();
+        users.dedup();
+        users.len()
+    };
+
+    let mut lines = vec![];
+    for (index, option) in poll.options.iter().enumerate() {
+        let option_voters = votes.iter()
+            .filter(|(_, o)| *o == index as i64)
+            .map(|(u, _)| format!("<@{}>", u))
+            .collect::<Vec<String>>();
+        let mut line = chart_line(option, option_voters.len(), voters);
+        if poll.closed && !poll.anonymous && !option_voters.is_empty() {
+            line += format!("\n{}", tools::truncate(&option_voters.join(" "), 250)).as_str();
+        }
+        lines.push(line);
+    }
+
+    let mut details = vec![
+        format!("{} voter{}", voters, if voters == 1 { "" } else { "s" }),
+        String::from(if poll.multi_choice { "Multiple choice" } else { "Single choice" }),
+        String::from(if poll.anonymous { "Anonymous" } else { "Public" }),
+    ];
+    match (poll.closed, poll.ends_at) {
+        (true, _) => details.push(String::from("Closed")),
+        (false, Some(ends_at)) => details.push(format!("Closes <t:{}:R>", ends_at)),
+        (false, None) => {}
+    }
+
+    CreateEmbed::new()
+        .title(format!("📊 {}", poll.question))
+        .description(lines.join("\n\n"))
+        .field("Details:", details.join(" • "), false)
+        .color(if poll.closed { Colour::DARK_GREY } else { Colour::BLITZ_BLUE })
+        .footer(CreateEmbedFooter::new(format!("Poll ID: {}", poll.poll_id)))
+}
+
+/// Build the voting buttons or select menu, plus the close button
+fn poll_components(poll: &Poll) -> Vec<CreateActionRow> {
+    let mut rows = vec![];
+    if poll.multi_choice {
+        let options = poll.options.iter()
+            .enumerate()
+            .map(|(i, o)| CreateSelectMenuOption::new(o, i.to_string()))
+            .collect::<Vec<CreateSelectMenuOption>>();
+        rows.push(CreateActionRow::SelectMenu(
+            CreateSelectMenu::new(format!("{}{}", VOTE_PREFIX, poll.poll_id), CreateSelectMenuKind::String { options })
+                .placeholder("Choose your options")
+                .min_values(0)
+                .max_values(poll.options.len() as u8)
+        ));
+    } else {
+        let buttons = poll.options.iter()
+            .enumerate()
+            .map(|(i, o)| CreateButton::new(format!("{}{}:{}", VOTE_PREFIX, poll.poll_id, i))
+                .label(tools::truncate(o, 80))
+                .style(ButtonStyle::Primary))
+            .collect::<Vec<CreateButton>>();
+        for chunk in buttons.chunks(5) {
+            rows.push(CreateActionRow::Buttons(chunk.to_vec()));
+        }
+    }
+    rows.push(CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("{}{}", CLOSE_PREFIX, poll.poll_id))
+            .label("Close poll")
+            .style(ButtonStyle::Secondary)
+    ]));
+    rows
+}
+
+/// Close a poll and replace its message with the final results
+pub async fn close_poll(http: &Http, database: &Database, poll: &Poll) -> Result<(), Error> {
+    if !database.close_poll(poll.poll_id).await? {
+        return Ok(()); // Someone beat us to it
+    }
+    let poll = Poll { closed: true, ..poll.clone() };
+    let votes = database.get_poll_votes(poll.poll_id).await?;
+    if let (Some(channel_id), Some(message_id)) = (
+        tools::to_channel(&poll.channel_id),
+        poll.message_id.as_deref().and_then(tools::to_snowflake),
+    ) {
+        channel_id.edit_message(http, message_id,
+            EditMessage::new().embed(poll_embed(&poll, &votes)).components(vec![])
+        ).await?;
+    }
+    info!("Closed poll ID {}", poll.poll_id);
+    Ok(())
+}
+
+/// Close polls that reached their end time, forever. Open polls survive restarts since they're in the database.
+pub async fn close_expired_polls(ctx: serenity::Context, database: Database) {
+    let mut interval = tokio::time::interval(CLOSE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown::requested() => break,
+        }
+        let polls = match database.get_due_polls(chrono::Utc::now().timestamp()).await {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("Failed to get expired polls: {}", e);
+                continue;
+            }
+        };
+        for poll in polls {
+            if let Err(e) = close_poll(&ctx.http, &database, &poll).await {
+                warn!("Failed to close poll ID {}: {}", poll.poll_id, e);
+            }
+        }
+    }
+}
+
+async fn respond_ephemeral(ctx: &serenity::Context, component: &ComponentInteraction, text: &str) -> Result<(), Error> {
+    component.create_response(&ctx.http, CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new().content(text).ephemeral(true)
+    )).await?;
+    Ok(())
+}
+
+// Event handlers ->
+
+/// Handle a vote or a click on the close button
+pub async fn on_component(ctx: &serenity::Context, data: &Data, component: &ComponentInteraction) -> Result<(), Error> {
+    let custom_id = component.data.custom_id.as_str();
+    let (closing, rest) = match custom_id.strip_prefix(CLOSE_PREFIX) {
+        Some(rest) => (true, rest),
+        None => (false, custom_id.strip_prefix(VOTE_PREFIX).unwrap_or_default()),
+    };
+    let mut parts = rest.split(':');
+    let poll = match parts.next().and_then(|id| id.parse::<i64>().ok()) {
+        Some(id) => data.database.get_poll(id).await?,
+        None => None,
+    };
+    let poll = match poll {
+        Some(p) if !p.closed => p,
+        _ => return respond_ephemeral(ctx, component, "This poll is closed.").await,
+    };
+
+    if closing {
+        let is_author = poll.author_id == component.user.id.to_string();
+        let is_moderator = component.member.as_ref()
+            .and_then(|m| m.permissions)
+            .is_some_and(|p| p.manage_messages());
+        if !is_author && !is_moderator {
+            return respond_ephemeral(ctx, component, "Only the creator of the poll can close it.").await;
+        }
+        close_poll(&ctx.http, &data.database, &poll).await?;
+        return respond_ephemeral(ctx, component, "Poll closed.").await;
+    }
+
+    let choices = match &component.data.kind {
+        ComponentInteractionDataKind::StringSelect { values } => values.iter()
+            .filter_map(|v| v.parse::<i64>().ok())
+            .collect::<Vec<i64>>(),
+        ComponentInteractionDataKind::Button => {
+            let choice = parts.next().and_then(|i| i.parse::<i64>().ok()).unwrap_or(-1);
+            // Clicking the option you already voted for takes your vote back
+            let current = data.database.get_poll_votes(poll.poll_id).await?.into_iter()
+                .filter(|(u, _)| *u == component.user.id.to_string())
+                .map(|(_, o)| o)
+                .collect::<Vec<i64>>();
+            if current == [choice] { vec![] } else { vec![choice] }
+        }
+        _ => return Ok(()),
+    };
+    let choices = choices.into_iter()
+        .filter(|c| *c >= 0 && (*c as usize) < poll.options.len())
+        .collect::<Vec<i64>>();
+    data.database.set_poll_votes(poll.poll_id, &component.user.id, &choices).await?;
+
+    let votes = data.database.get_poll_votes(poll.poll_id).await?;
+    component.create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new().embed(poll_embed(&poll, &votes))
+    )).await?;
+    Ok(())
+}
+
+// Commands ->
+
+/// Start a poll
+#[poise::command(slash_command, guild_only, default_member_permissions = "SEND_MESSAGES")]
+#[allow(clippy::too_many_arguments)]
+pub async fn poll(
+    ctx: Context<'_>,
+    #[description = "What the poll is about"]
+    #[max_length = 250]
+    question: String,
+    #[description = "Option 1"]
+    #[max_length = 100]
+    option1: String,
+    #[description = "Option 2"]
+    #[max_length = 100]
+    option2: String,
+    #[description = "Option 3"]
+    #[max_length = 100]
+    option3: Option<String>,
+    #[description = "Option 4"]
+    #[max_length = 100]
+    option4: Option<String>,
+    #[description = "Option 5"]
+    #[max_length = 100]
+    option5: Option<String>,
+    #[description = "Option 6"]
+    #[max_length = 100]
+    option6: Option<String>,
+    #[description = "Option 7"]
+    #[max_length = 100]
+    option7: Option<String>,
+    #[description = "Option 8"]
+    #[max_length = 100]
+    option8: Option<String>,
+    #[description = "Option 9"]
+    #[max_length = 100]
+    option9: Option<String>,
+    #[description = "Option 10"]
+    #[max_length = 100]
+    option10: Option<String>,
+    #[description = "Allow voting for more than one option. Defaults to false"] multi_choice: Option<bool>,
+    #[description = "Hide who voted for what when the poll closes. Defaults to false"] anonymous: Option<bool>,
+    #[description = "Close the poll automatically after this many minutes"]
+    #[min = 1]
+    #[max = 43200]
+    duration: Option<u32>,
+) -> Result<(), Error> {
+    let mut options = vec![option1, option2];
+    options.extend([option3, option4, option5, option6, option7, option8, option9, option10].into_iter().flatten());
+    let ends_at = duration.map(|d| chrono::Utc::now().timestamp() + d as i64 * 60);
+
+    let poll_id = ctx.data()
+        .database
+        .create_poll(
+            &ctx.guild_id().unwrap(),
+            &ctx.channel_id(),
+            &ctx.author().id,
+            &question,
+            &options,
+            multi_choice.unwrap_or(false),
+            anonymous.unwrap_or(false),
+            ends_at,
+        )
+        .await?;
+    let poll = ctx.data().database.get_poll(poll_id).await?.ok_or("Poll disappeared after creating it")?;
+
+    let reply = match ctx.send(
+        CreateReply::default()
+            .embed(poll_embed(&poll, &[]))
+            .components(poll_components(&poll)),
+    )
+    .await {
+        Ok(r) => r,
+        Err(e) => {
+            // Nothing was posted, so don't leave a poll behind for close_expired_polls to find
+            ctx.data().database.delete_poll(poll_id).await?;
+            return Err(e.into());
+        }
+    };
+    let message = reply.message().await?;
+    ctx.data().database.set_poll_message(poll_id, &message.id).await?;
+    info!("{} started poll ID {}{}", ctx.author().name, poll_id, match ends_at {
+        Some(t) => format!(", closing at {}", Timestamp::from_unix_timestamp(t)?),
+        None => String::new(),
+    });
+    Ok(())
+}
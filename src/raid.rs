@@ -0,0 +1,454 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use log::{info, warn};
+use poise::serenity_prelude::{
+    ButtonStyle, ChannelId, Colour, ComponentInteraction, CreateActionRow, CreateButton, CreateEmbed, CreateEmbedFooter,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage, EditInteractionResponse, EditMessage,
+    GuildId, Member, Mentionable, UserId,
+};
+use poise::CreateReply;
+use crate::{commands, serenity, tools};
+use crate::{Context, Data, Error};
+
+/// Followed by `:` and the ID of the alert whose flagged members the button kicks
+pub const KICK_BUTTON_ID: &str = "raid_kick_flagged";
+/// Followed by the alert ID, holds the users flagged during that raid
+const FLAGGED_PREFIX: &str = "raid.flagged.";
+/// Seconds after which the flagged users of an alert are forgotten, and its button stops working
+const FLAGGED_RETENTION: i64 = 60 * 60 * 24 * 7;
+
+/// Thresholds that decide what counts as a raid, configurable per guild
+#[derive(Debug, Clone, Copy)]
+pub struct RaidSettings {
+    /// How many joins within `window` seconds trigger an alert
+    pub joins: usize,
+    pub window: i64,
+    /// Accounts younger than this many days get flagged
+    pub min_account_age: i64,
+    /// Flag accounts that never set an avatar
+    pub flag_default_avatar: bool,
+    /// Lock the server down automatically when a raid is detected
+    pub auto_lockdown: bool,
+}
+
+impl Default for RaidSettings {
+    fn default() -> Self {
+        Self {
+            joins: 10,
+            window: 60,
+            min_account_age: 7,
+            flag_default_avatar: true,
+            auto_lockdown: false,
+        }
+    }
+}
+
+impl RaidSettings {
+    async fn load(data: &Data, guild_id: GuildId) -> Result<Self, Error> {
+        let values = data.database.get_guild_values_with_prefix(&guild_id, "config.raid_").await?;
+        let get = |key: &str| values.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+        let defaults = Self::default();
+        Ok(Self {
+            joins: get("config.raid_joins").and_then(|v| v.parse().ok()).unwrap_or(defaults.joins),
+            window: get("config.raid_window").and_then(|v| v.parse().ok()).unwrap_or(defaults.window),
+            min_account_age: get("config.raid_account_age").and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.min_account_age),
+            flag_default_avatar: get("config.raid_default_avatar").map(|v| v == "true")
+                .unwrap_or(defaults.flag_default_avatar),
+            auto_lockdown: get("config.raid_auto_lockdown").is_some(),
+        })
+    }
+
+    /// Whether a joining account looks suspicious on its own
+    pub fn is_suspicious(&self, account_age_days: i64, has_avatar: bool) -> bool {
+        account_age_days < self.min_account_age || (self.flag_default_avatar && !has_avatar)
+    }
+}
+
+#[derive(Default)]
+struct GuildJoins {
+    /// (unix timestamp, user, flagged)
+    joins: VecDeque<(i64, UserId, bool)>,
+    /// When the current raid was first detected, if one is going on
+    raid_started: Option<i64>,
+}
+
+/// Keeps track of recent joins per guild to detect join bursts
+#[derive(Default)]
+pub struct RaidTracker {
+    guilds: Mutex<HashMap<GuildId, GuildJoins>>,
+}
+
+/// What the tracker concluded about a join
+#[derive(Debug, PartialEq)]
+pub enum JoinVerdict {
+    /// Nothing out of the ordinary
+    Normal,
+    /// A burst was just detected, these are the flagged users that joined during it. The alert ID is the time the raid
+    /// started.
+    RaidDetected(i64, Vec<UserId>),
+    /// A raid is already ongoing and has been reported with this alert ID
+    RaidOngoing(i64),
+}
+
+impl RaidTracker {
+    /// Record a join and decide whether it's part of a raid.
+    ///
+    /// A raid is considered over once a full window passes without reaching the join threshold.
+    pub fn record_join(&self, guild_id: GuildId, user_id: UserId, flagged: bool, now: i64, settings: &RaidSettings)
+        -> JoinVerdict {
+        let mut guilds = self.guilds.lock().unwrap();
+        let guild = guilds.entry(guild_id).or_default();
+
+        guild.joins.push_back((now, user_id, flagged));
+        while guild.joins.front().is_some_and(|(t, _, _)| now - t > settings.window) {
+            guild.joins.pop_front();
+        }
+
+        let burst = guild.joins.len() >= settings.joins;
+        match (burst, guild.raid_started) {
+            (true, None) => {
+                guild.raid_started = Some(now);
+                JoinVerdict::RaidDetected(now, guild.joins.iter().filter(|(_, _, f)| *f).map(|(_, u, _)| *u).collect())
+            }
+            (_, Some(started)) if burst || now - started <= settings.window => JoinVerdict::RaidOngoing(started),
+            (_, Some(_)) => {
+                guild.raid_started = None;
+                JoinVerdict::Normal
+            }
+            (false, None) => JoinVerdict::Normal,
+        }
+    }
+}
+
+async fn is_locked_down(data: &Data, guild_id: GuildId) -> Result<bool, Error> {
+    Ok(data.database.get_guild_value(&guild_id, &"raid.lockdown").await?.is_some())
+}
+
+/// Where the users flagged during a raid are stored. Every alert has its own list, so its button only kicks those.
+fn flagged_key(alert_id: i64) -> String {
+    format!("{}{}", FLAGGED_PREFIX, alert_id)
+}
+
+/// Forget the flagged users of alerts nobody acted on, so their lists don't pile up
+async fn prune_flagged(data: &Data, guild_id: GuildId, now: i64) -> Result<(), Error> {
+    for (key, _) in data.database.get_guild_values_with_prefix(&guild_id, FLAGGED_PREFIX).await? {
+        let alert_id = key[FLAGGED_PREFIX.len()..].parse::<i64>().unwrap_or(0);
+        if now - alert_id > FLAGGED_RETENTION {
+            data.database.delete_guild_value(&guild_id, &key).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Remember flagged users so the kick button can find them, even after a restart
+async fn add_flagged(data: &Data, guild_id: GuildId, alert_id: i64, users: &[UserId]) -> Result<(), Error> {
+    if users.is_empty() {
+        return Ok(());
+    }
+    let key = flagged_key(alert_id);
+    // Joins are handled concurrently, so retry if another join changed the list in the meantime
+    loop {
+        let current = data.database.get_guild_value(&guild_id, &key).await?;
+        let mut flagged = current.clone().unwrap_or_default();
+        for user in users {
+            if !flagged.is_empty() {
+                flagged.push(',');
+            }
+            flagged.push_str(&user.to_string());
+        }
+        if data.database.compare_and_set_guild_value(&guild_id, &key, current.as_deref(), &flagged).await? {
+            return Ok(());
+        }
+    }
+}
+
+// Event handlers ->
+
+/// Check a new member against the raid thresholds. Returns true if the member was removed by the lockdown.
+pub async fn on_member_join(ctx: &serenity::Context, data: &Data, member: &Member) -> Result<bool, Error> {
+    let guild_id = member.guild_id;
+    let alert_channel = match data.database.get_guild_value(&guild_id, &"config.raid_alerts").await? {
+        Some(c) => c,
+        None => return Ok(false),
+    };
+
+    if is_locked_down(data, guild_id).await? {
+        guild_id.kick_with_reason(&ctx.http, member.user.id, "Server is in raid lockdown").await?;
+        info!("Kicked user ID {} from guild ID {}: lockdown", member.user.id, guild_id);
+        return Ok(true);
+    }
+
+    let settings = RaidSettings::load(data, guild_id).await?;
+    let flagged = settings.is_suspicious(tools::user_account_days(member.user.id), member.user.avatar.is_some());
+    let now = chrono::Utc::now().timestamp();
+
+    match data.raid_tracker.record_join(guild_id, member.user.id, flagged, now, &settings) {
+        JoinVerdict::Normal => {}
+        JoinVerdict::RaidOngoing(alert_id) => {
+            if flagged {
+                add_flagged(data, guild_id, alert_id, &[member.user.id]).await?;
+            }
+        }
+        JoinVerdict::RaidDetected(alert_id, users) => {
+            warn!("Possible raid detected in guild ID {}", guild_id);
+            prune_flagged(data, guild_id, now).await?;
+            add_flagged(data, guild_id, alert_id, &users).await?;
+            if settings.auto_lockdown {
+                data.database.set_guild_value(&guild_id, &"raid.lockdown", &"").await?;
+            }
+
+            let mut mentions = users.iter().take(30).map(|u| u.mention().to_string()).collect::<Vec<String>>().join(" ");
+            if users.len() > 30 {
+                mentions += format!(" and {} more", users.len() - 30).as_str();
+            }
+            if let Some(channel_id) = tools::to_channel(alert_channel.as_str()) {
+                channel_id.send_message(&ctx.http,
+                    CreateMessage::new()
+                        .embed(
+                            CreateEmbed::new()
+                                .title("🚨 Possible raid detected")
+                                .description(format!(
+                                    "**{}** or more members joined within {} seconds.",
+                                    settings.joins, settings.window
+                                ))
+                                .field("Flagged members:", if mentions.is_empty() { String::from("None") } else { mentions }, false)
+                                .field("Lockdown:", if settings.auto_lockdown {
+                                    "Enabled, new members will be kicked until it's lifted with `/raid lockdown`"
+                                } else {
+                                    "Not enabled"
+                                }, false)
+                                .color(Colour::RED)
+                                .footer(CreateEmbedFooter::new(
+                                    "Members who join while the raid is ongoing are flagged too."
+                                ))
+                        )
+                        .components(vec![CreateActionRow::Buttons(vec![
+                            CreateButton::new(format!("{}:{}", KICK_BUTTON_ID, alert_id))
+                                .label("Kick all flagged")
+                                .style(ButtonStyle::Danger)
+                        ])])
+                ).await?;
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Handle a click on the "kick all flagged" button
+pub async fn on_kick_button(ctx: &serenity::Context, data: &Data, component: &ComponentInteraction) -> Result<(), Error> {
+    let guild_id = match component.guild_id {
+        Some(g) => g,
+        None => return Ok(()),
+    };
+    let allowed = component.member.as_ref()
+        .and_then(|m| m.permissions)
+        .is_some_and(|p| p.kick_members());
+    if !allowed {
+        component.create_response(&ctx.http, CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need the Kick Members permission to do that.")
+                .ephemeral(true)
+        )).await?;
+        return Ok(());
+    }
+    let Some(alert_id) = component.data.custom_id.strip_prefix(KICK_BUTTON_ID)
+        .and_then(|id| id.strip_prefix(':'))
+        .and_then(|id| id.parse::<i64>().ok())
+    else {
+        return Ok(());
+    };
+    let key = flagged_key(alert_id);
+    // Kicking a whole raid takes longer than Discord waits for a response
+    component.defer_ephemeral(&ctx.http).await?;
+
+    let mut attempted = HashSet::new();
+    let mut kicked = 0;
+    while let Some(flagged) = data.database.get_guild_value(&guild_id, &key).await? {
+        for user_id in flagged.split(',').filter_map(tools::to_snowflake) {
+            if !attempted.insert(user_id) {
+                continue;
+            }
+            let reason = format!("Flagged during raid, kicked by {}", component.user.name);
+            match guild_id.kick_with_reason(&ctx.http, UserId::from(user_id), &reason).await {
+                Ok(_) => kicked += 1,
+                Err(e) => warn!("Failed to kick flagged user ID {}: {}", user_id, e), // Probably already gone
+            }
+        }
+        // Members flagged while kicking were added to the list, kick them too before deleting it
+        if data.database.compare_and_delete_guild_value(&guild_id, &key, &flagged).await? {
+            break;
+        }
+    }
+    prune_flagged(data, guild_id, chrono::Utc::now().timestamp()).await?;
+    info!("{} kicked {} flagged members from guild ID {}", component.user.name, kicked, guild_id);
+
+    let mut message = component.message.clone();
+    message.edit(&ctx.http, EditMessage::new().components(vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(component.data.custom_id.clone())
+            .label("Flagged members kicked")
+            .style(ButtonStyle::Danger)
+            .disabled(true)
+    ])])).await?;
+    component.edit_response(&ctx.http,
+        EditInteractionResponse::new().content(format!("Kicked {} flagged members.", kicked))
+    ).await?;
+    Ok(())
+}
+
+// Commands ->
+
+/// Detect join raids and alert moderators
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    subcommands("raid_enable", "raid_disable", "raid_settings", "raid_lockdown"),
+    subcommand_required
+)]
+pub async fn raid(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Enable raid detection
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "enable"
+)]
+pub async fn raid_enable(
+    ctx: Context<'_>,
+    #[description = "Channel to send raid alerts to"]
+    channel: ChannelId,
+) -> Result<(), Error> {
+    if !commands::check_channel_writable(ctx, channel).await? {
+        return Ok(());
+    }
+    ctx.data()
+        .database
+        .set_guild_value(&ctx.guild_id().unwrap(), &"config.raid_alerts", &channel)
+        .await?;
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Raid detection enabled. Alerting in {}", channel.mention()))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Disable raid detection
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "disable"
+)]
+pub async fn raid_disable(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.data()
+        .database
+        .delete_guild_value(&ctx.guild_id().unwrap(), &"config.raid_alerts")
+        .await?;
+    ctx.send(
+        CreateReply::default()
+            .content("Raid detection disabled.".to_string())
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Change or show the raid detection thresholds
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "settings"
+)]
+pub async fn raid_settings(
+    ctx: Context<'_>,
+    #[description = "How many joins count as a raid"]
+    #[min = 2]
+    joins: Option<u32>,
+    #[description = "Within how many seconds"]
+    #[min = 1]
+    seconds: Option<u32>,
+    #[description = "Flag accounts younger than this many days"]
+    min_account_age: Option<u32>,
+    #[description = "Flag accounts without an avatar"]
+    flag_default_avatar: Option<bool>,
+    #[description = "Lock the server down automatically when a raid is detected"]
+    auto_lockdown: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let database = &ctx.data().database;
+    if let Some(v) = joins {
+        database.set_guild_value(&guild_id, &"config.raid_joins", &v).await?;
+    }
+    if let Some(v) = seconds {
+        database.set_guild_value(&guild_id, &"config.raid_window", &v).await?;
+    }
+    if let Some(v) = min_account_age {
+        database.set_guild_value(&guild_id, &"config.raid_account_age", &v).await?;
+    }
+    if let Some(v) = flag_default_avatar {
+        database.set_guild_value(&guild_id, &"config.raid_default_avatar", &v).await?;
+    }
+    match auto_lockdown {
+        Some(true) => database.set_guild_value(&guild_id, &"config.raid_auto_lockdown", &"").await?,
+        Some(false) => {
+            database.delete_guild_value(&guild_id, &"config.raid_auto_lockdown").await?;
+        }
+        None => {}
+    }
+
+    let settings = RaidSettings::load(ctx.data(), guild_id).await?;
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title("🚨 Raid detection settings")
+                    .field("Threshold:", format!("{} joins in {} seconds", settings.joins, settings.window), false)
+                    .field("Minimum account age:", format!("{} days", settings.min_account_age), true)
+                    .field("Flag default avatars:", if settings.flag_default_avatar { "Yes" } else { "No" }, true)
+                    .field("Automatic lockdown:", if settings.auto_lockdown { "Yes" } else { "No" }, true)
+                    .color(Colour::BLITZ_BLUE),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Kick every new member until the lockdown is lifted
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "lockdown"
+)]
+pub async fn raid_lockdown(
+    ctx: Context<'_>,
+    #[description = "Whether the server is locked down"]
+    enabled: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    if enabled {
+        ctx.data().database.set_guild_value(&guild_id, &"raid.lockdown", &"").await?;
+    } else {
+        ctx.data().database.delete_guild_value(&guild_id, &"raid.lockdown").await?;
+    }
+    info!("{} {} lockdown in guild ID {}", ctx.author().name, if enabled { "enabled" } else { "lifted" }, guild_id);
+    ctx.send(
+        CreateReply::default()
+            .content(if enabled {
+                "Lockdown enabled. New members will be kicked while raid detection is enabled.".to_string()
+            } else {
+                "Lockdown lifted.".to_string()
+            })
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
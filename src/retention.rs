@@ -0,0 +1,176 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use poise::CreateReply;
+use poise::serenity_prelude::{Cache, GuildId};
+use crate::database::Database;
+use crate::{shutdown, tools};
+use crate::{Context, Error};
+
+/// Unix time the bot was removed from a guild. Cleared when it's added again.
+const LEFT_AT: &str = "stats.left_at";
+/// Set when the bot was removed from a guild. Departures from before the time was recorded only have this.
+const KICKED_FROM: &str = "stats.kicked_from";
+/// How often to look for guild data that's due to be purged
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Start the retention period of a guild the bot was removed from
+pub async fn on_guild_removed(database: &Database, guild_id: GuildId) -> Result<(), Error> {
+    let now = Utc::now().timestamp().to_string();
+    database.set_guild_values(&guild_id, &[(KICKED_FROM, String::new()), (LEFT_AT, now)]).await?;
+    Ok(())
+}
+
+/// Keep the data of a guild the bot is in again
+pub async fn on_guild_joined(database: &Database, guild_id: GuildId) -> Result<(), Error> {
+    let mut transaction = database.begin().await?;
+    transaction.delete_guild_value(&guild_id, &LEFT_AT).await?;
+    transaction.delete_guild_value(&guild_id, &KICKED_FROM).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Guilds the bot has data of, but isn't in. Those that were left before departures were timed have no time.
+async fn departed_guilds(database: &Database) -> Result<Vec<(String, Option<i64>)>, Error> {
+    let mut departed = vec![];
+    for (guild_id, _) in database.get_guilds_with_value(&KICKED_FROM).await? {
+        departed.push((guild_id.clone(), database.get_guild_value_typed(&guild_id, &LEFT_AT).await?));
+    }
+    Ok(departed)
+}
+
+/// Purge the data of guilds that were left more than `retention_days` ago, unless the bot is back in them
+async fn purge_due(database: &Database, cache: &Cache, retention_days: u64) -> Result<(), Error> {
+    let now = Utc::now().timestamp();
+    for (guild_id, left_at) in departed_guilds(database).await? {
+        let Some(id) = tools::to_snowflake(&guild_id).map(GuildId::from) else {
+            continue;
+        };
+        if cache.guild(id).is_some() {
+            continue;
+        }
+        match left_at {
+            Some(left_at) if now - left_at >= retention_days as i64 * SECONDS_PER_DAY => {
+                let rows = database.purge_guild(&guild_id).await?;
+                info!("Purged {} row(s) of guild ID {}, which was left {} day(s) ago", rows, guild_id,
+                    (now - left_at) / SECONDS_PER_DAY);
+            }
+            Some(_) => {}
+            // Left before departures were timed, so the retention period starts now
+            None => {
+                database.compare_and_set_guild_value(&guild_id, &LEFT_AT, None, &now).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Purge guild data that's past its retention period on a schedule, until the bot shuts down
+pub async fn run_scheduled(database: Database, cache: Arc<Cache>, retention_days: u64) {
+    if retention_days == 0 {
+        return;
+    }
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    // The first tick completes immediately, skip it so the guilds the bot is in are known by the first check
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown::requested() => break,
+        }
+        if let Err(e) = purge_due(&database, &cache, retention_days).await {
+            error!("Purging guild data failed: {}", e);
+        }
+    }
+}
+
+fn format_date(unix: i64) -> String {
+    DateTime::from_timestamp(unix, 0).map(|d| d.format("%Y-%m-%d").to_string()).unwrap_or_default()
+}
+
+/// Describe the data of guilds the bot isn't in, and when it gets purged
+pub async fn report(database: &Database, retention_days: u64) -> Result<String, Error> {
+    let now = Utc::now().timestamp();
+    let counts = database.get_guild_data_counts().await?;
+    let departed = departed_guilds(database).await?;
+    // Data without any record of the guild means the bot never saw it join, so it isn't in it either
+    let joined = database.get_guilds_with_value(&"stats.first_join").await?;
+    let unknown = counts.keys()
+        .filter(|g| !joined.iter().any(|(j, _)| j == *g))
+        .filter(|g| !departed.iter().any(|(d, _)| d == *g))
+        .map(|g| (g.clone(), None));
+
+    let mut lines = vec![];
+    for (guild_id, left_at) in departed.iter().map(|(g, l)| (g.clone(), Some(*l))).chain(unknown) {
+        let tables = counts.get(&guild_id).map(Vec::as_slice).unwrap_or_default();
+        let rows: i64 = tables.iter().map(|(_, rows)| rows).sum();
+        let name = database.get_guild_value(&guild_id, &"stats.name").await?.unwrap_or(String::from("Unknown"));
+        let status = match left_at {
+            None => String::from("never seen joining, only purged by hand"),
+            Some(left_at) if retention_days == 0 => {
+                format!("left {}, kept forever", left_at.map(format_date).unwrap_or(String::from("at an unknown time")))
+            }
+            Some(None) => String::from("left at an unknown time, the retention period starts on the next check"),
+            Some(Some(left_at)) => {
+                let due = left_at + retention_days as i64 * SECONDS_PER_DAY;
+                let overdue = if due <= now { " (overdue)" } else { "" };
+                format!("left {}, purged after {}{}", format_date(left_at), format_date(due), overdue)
+            }
+        };
+        let breakdown = tables.iter().map(|(table, rows)| format!("{} {}", table, rows)).collect::<Vec<String>>();
+        lines.push(format!("{} \"{}\": {}, {} row(s) [{}]", guild_id, name, status, rows, breakdown.join(", ")));
+    }
+
+    if lines.is_empty() {
+        return Ok(String::from("No orphaned guild data.\n"));
+    }
+    let retention = match retention_days {
+        0 => String::from("kept forever"),
+        days => format!("{} day(s)", days),
+    };
+    Ok(format!(
+        "Data of {} guild(s) the bot isn't in (retention: {}):\n{}\n",
+        lines.len(),
+        retention,
+        lines.join("\n")
+    ))
+}
+
+// Commands ->
+
+/// Delete everything stored for a guild the bot isn't in
+#[poise::command(
+    slash_command,
+    owners_only,
+    hide_in_help,
+    default_member_permissions = "ADMINISTRATOR"
+)]
+pub async fn purgeguild(
+    ctx: Context<'_>,
+    #[description = "ID of the guild to delete the data of"] guild_id: String,
+) -> Result<(), Error> {
+    let Some(id) = tools::to_snowflake(guild_id.trim()).map(GuildId::from) else {
+        ctx.send(CreateReply::default().content("That's not a guild ID.").ephemeral(true)).await?;
+        return Ok(());
+    };
+    if ctx.cache().guild(id).is_some() {
+        ctx.send(
+            CreateReply::default()
+                .content("The bot is still in that guild. Remove it from the guild first.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+    let rows = ctx.data().database.purge_guild(&id).await?;
+    info!("{} purged {} row(s) of guild ID {}", ctx.author().name, rows, id);
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Deleted {} row(s) of guild ID {}.", rows, id))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use poise::serenity_prelude::ShardManager;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use crate::database::Database;
+use crate::log_queue;
+
+/// How often to check whether the in-flight work is done while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Flips to true once when shutting down
+static REQUESTED: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+/// IDs of the commands that are currently running
+static COMMANDS: LazyLock<Mutex<HashSet<u64>>> = LazyLock::new(Mutex::default);
+/// Number of event handlers that are currently running
+static EVENTS: AtomicUsize = AtomicUsize::new(0);
+/// Background jobs by name, which stop once a shutdown is requested
+static JOBS: Mutex<Vec<Job>> = Mutex::new(Vec::new());
+
+type Job = (&'static str, JoinHandle<()>);
+
+pub fn is_requested() -> bool {
+    *REQUESTED.borrow()
+}
+
+/// Completes once a shutdown is requested. Background jobs select on this to stop between runs.
+pub async fn requested() {
+    let mut receiver = REQUESTED.subscribe();
+    let _ = receiver.wait_for(|r| *r).await;
+}
+
+/// Spawn a background job that gets waited for on shutdown
+pub fn spawn_job<F>(name: &'static str, job: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let handle = tokio::spawn(job);
+    if let Ok(mut jobs) = JOBS.lock() {
+        jobs.push((name, handle));
+    }
+}
+
+pub fn command_started(id: u64) {
+    if let Ok(mut commands) = COMMANDS.lock() {
+        commands.insert(id);
+    }
+}
+
+/// Called when a command finished, successfully or not
+pub fn command_finished(id: u64) {
+    if let Ok(mut commands) = COMMANDS.lock() {
+        commands.remove(&id);
+    }
+}
+
+fn running_commands() -> usize {
+    COMMANDS.lock().map(|c| c.len()).unwrap_or_default()
+}
+
+/// Counts an event handler as running for as long as it's alive
+pub struct EventGuard;
+
+impl Drop for EventGuard {
+    fn drop(&mut self) {
+        EVENTS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn event_started() -> EventGuard {
+    EVENTS.fetch_add(1, Ordering::SeqCst);
+    EventGuard
+}
+
+/// Shut down gracefully: stop taking new commands and events, wait up to `grace` for running commands, event
+/// handlers, background jobs and queued log channel messages, then disconnect from Discord, flush the logs and close
+/// the database.
+pub async fn run(shard_manager: Arc<ShardManager>, database: Database, grace: Duration) {
+    let started = Instant::now();
+    REQUESTED.send_replace(true);
+    let jobs = JOBS.lock().map(|mut j| std::mem::take(&mut *j)).unwrap_or_default();
+    info!(
+        "Shutting down, waiting up to {}s for {} command(s), {} event handler(s), {} background job(s) and {} queued \
+        log message(s)",
+        grace.as_secs(),
+        running_commands(),
+        EVENTS.load(Ordering::SeqCst),
+        jobs.len(),
+        log_queue::pending()
+    );
+
+    let deadline = started + grace;
+    while Instant::now() < deadline
+        && (running_commands() > 0
+            || EVENTS.load(Ordering::SeqCst) > 0
+            || jobs.iter().any(|(_, j)| !j.is_finished())
+            || log_queue::pending() > 0)
+    {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+
+    let abandoned_commands = running_commands();
+    let abandoned_events = EVENTS.load(Ordering::SeqCst);
+    let unsent_logs = log_queue::pending() + log_queue::dropped();
+    let mut abandoned_jobs = vec![];
+    for (name, job) in jobs {
+        if !job.is_finished() {
+            job.abort();
+            abandoned_jobs.push(name);
+        }
+    }
+
+    shard_manager.shutdown_all().await;
+    database.close().await;
+
+    if abandoned_commands == 0 && abandoned_events == 0 && abandoned_jobs.is_empty() && unsent_logs == 0 {
+        info!("Shut down cleanly in {:.1}s", started.elapsed().as_secs_f64());
+    } else {
+        warn!(
+            "Shut down in {:.1}s, abandoning {} command(s), {} event handler(s) and background job(s) [{}] that \
+            didn't finish in time. {} log channel message(s) were never sent.",
+            started.elapsed().as_secs_f64(),
+            abandoned_commands,
+            abandoned_events,
+            abandoned_jobs.join(", "),
+            unsent_logs
+        );
+    }
+    log::logger().flush();
+}
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use log::{info, warn};
+use poise::serenity_prelude::{
+    ChannelId, Colour, CreateEmbed, CreateEmbedAuthor, CreateMessage, EditMessage, GuildId, Mentionable, Message,
+    MessageId, Reaction, ReactionType, UserId,
+};
+use poise::CreateReply;
+use tokio::sync::OwnedMutexGuard;
+use crate::{commands, serenity, tools};
+use crate::{Context, Data, Error};
+
+const STAR: &str = "⭐";
+const DEFAULT_THRESHOLD: u64 = 3;
+
+/// Serializes starboard updates per message, so a burst of reactions can't post the same message twice
+#[derive(Default)]
+pub struct StarboardState {
+    locks: Mutex<HashMap<MessageId, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl StarboardState {
+    /// Wait for other updates of a message to finish. Updates of other messages don't wait for each other.
+    async fn lock(&self, message_id: MessageId) -> MessageLock<'_> {
+        let lock = self.locks.lock().unwrap().entry(message_id).or_default().clone();
+        MessageLock { state: self, message_id, guard: Some(lock.lock_owned().await) }
+    }
+}
+
+/// Held while a message's starboard post is updated
+struct MessageLock<'a> {
+    state: &'a StarboardState,
+    message_id: MessageId,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for MessageLock<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+        // Forget the lock once nobody holds or waits for it, messages are rarely starred again
+        let mut locks = self.state.locks.lock().unwrap();
+        if locks.get(&self.message_id).is_some_and(|l| Arc::strong_count(l) == 1) {
+            locks.remove(&self.message_id);
+        }
+    }
+}
+
+/// Per guild starboard configuration
+struct Settings {
+    channel: ChannelId,
+    threshold: u64,
+    excluded: Vec<ChannelId>,
+}
+
+impl Settings {
+    /// Load the settings of a guild, or None if it has no starboard
+    async fn load(data: &Data, guild_id: GuildId) -> Result<Option<Self>, Error> {
+        let database = &data.database;
+        let channel = match database.get_guild_value(&guild_id, &"config.starboard_channel").await? {
+            Some(c) => match tools::to_channel(&c) {
+                Some(c) => c,
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        let threshold = database.get_guild_value(&guild_id, &"config.starboard_threshold").await?
+            .and_then(|t| t.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_THRESHOLD);
+        let excluded = excluded_channels(data, guild_id).await?;
+        Ok(Some(Self { channel, threshold, excluded }))
+    }
+}
+
+async fn excluded_channels(data: &Data, guild_id: GuildId) -> Result<Vec<ChannelId>, Error> {
+    Ok(match data.database.get_guild_value(&guild_id, &"config.starboard_exclude").await? {
+        Some(v) => v.split(',').filter_map(tools::to_channel).collect(),
+        None => vec![],
+    })
+}
+
+/// Format a list of channel IDs for storage in the database
+fn join_channels(channels: &[ChannelId]) -> String {
+    channels.iter().map(|c| c.to_string()).collect::<Vec<String>>().join(",")
+}
+
+fn is_star(emoji: &ReactionType) -> bool {
+    matches!(emoji, ReactionType::Unicode(e) if e == STAR)
+}
+
+/// Count the stars on a message, not counting the author and bots
+async fn count_stars(ctx: &serenity::Context, message: &Message) -> Result<u64, Error> {
+    let has_stars = message.reactions.iter().any(|r| is_star(&r.reaction_type) && r.count > 0);
+    if !has_stars {
+        return Ok(0);
+    }
+
+    let mut stars = 0;
+    let mut after: Option<UserId> = None;
+    loop {
+        let users = message.channel_id
+            .reaction_users(&ctx.http, message.id, ReactionType::Unicode(STAR.to_string()), Some(100), after)
+            .await?;
+        stars += users.iter().filter(|u| !u.bot && u.id != message.author.id).count() as u64;
+        match users.last() {
+            Some(last) if users.len() == 100 => after = Some(last.id),
+            _ => break,
+        }
+    }
+    Ok(stars)
+}
+
+fn starboard_content(stars: u64, channel_id: ChannelId) -> String {
+    format!("{} **{}** | {}", STAR, stars, channel_id.mention())
+}
+
+fn starboard_embed(message: &Message) -> CreateEmbed {
+    let mut embed = CreateEmbed::new()
+        .author(CreateEmbedAuthor::new(message.author.display_name()).icon_url(message.author.face()))
+        .field("Source:", format!("[Jump to message]({})", message.link()), false)
+        .timestamp(message.timestamp)
+        .color(Colour::GOLD);
+    if !message.content.is_empty() {
+        embed = embed.description(tools::truncate(&message.content, 4000));
+    }
+
+    // Show the first image, whether it was uploaded or embedded
+    let image = message.attachments.iter()
+        .find(|a| a.content_type.as_deref().is_some_and(|t| t.starts_with("image/")))
+        .map(|a| a.url.clone())
+        .or_else(|| message.embeds.iter().find_map(|e| {
+            e.image.as_ref().map(|i| i.url.clone()).or_else(|| e.thumbnail.as_ref().map(|t| t.url.clone()))
+        }));
+    if let Some(url) = image {
+        embed = embed.image(url);
+    }
+    embed
+}
+
+/// Remove the starboard post of a message, if it has one
+async fn remove_post(ctx: &serenity::Context, data: &Data, message_id: MessageId) -> Result<(), Error> {
+    if let Some((channel, post)) = data.database.get_starboard_entry(&message_id).await? {
+        if let (Some(channel_id), Some(post_id)) = (tools::to_channel(&channel), tools::to_snowflake(&post)) {
+            if let Err(e) = channel_id.delete_message(&ctx.http, post_id).await {
+                warn!("Failed to delete starboard post {}: {}", post_id, e);
+            }
+        }
+        data.database.delete_starboard_entry(&message_id).await?;
+    }
+    Ok(())
+}
+
+/// Post, update or remove the starboard post of a message based on its current stars
+async fn update_message(
+    ctx: &serenity::Context,
+    data: &Data,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> Result<(), Error> {
+    let settings = match Settings::load(data, guild_id).await? {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+    if channel_id == settings.channel || settings.excluded.contains(&channel_id) {
+        return Ok(());
+    }
+
+    let _guard = data.starboard.lock(message_id).await;
+    let message = channel_id.message(ctx, message_id).await?;
+    let stars = count_stars(ctx, &message).await?;
+    if stars < settings.threshold {
+        return remove_post(ctx, data, message_id).await;
+    }
+
+    let content = starboard_content(stars, channel_id);
+    if let Some((channel, post)) = data.database.get_starboard_entry(&message_id).await? {
+        if let (Some(post_channel), Some(post_id)) = (tools::to_channel(&channel), tools::to_snowflake(&post)) {
+            let edited = post_channel.edit_message(&ctx.http, post_id,
+                EditMessage::new().content(&content).embed(starboard_embed(&message))
+            ).await;
+            match edited {
+                Ok(_) => return Ok(()),
+                // Most likely deleted by someone, post it again below
+                Err(e) => warn!("Failed to update starboard post {}: {}", post_id, e),
+            }
+        }
+    }
+
+    let post = settings.channel.send_message(&ctx.http,
+        CreateMessage::new().content(content).embed(starboard_embed(&message))
+    ).await?;
+    data.database.set_starboard_entry(&guild_id, &message_id, &settings.channel, &post.id).await?;
+    info!("Starred message {} in guild {}", message_id, guild_id);
+    Ok(())
+}
+
+// Event handlers ->
+
+/// Handle a star being added or removed, or all stars being removed at once
+pub async fn on_reaction(ctx: &serenity::Context, data: &Data, reaction: &Reaction) -> Result<(), Error> {
+    let guild_id = match reaction.guild_id {
+        Some(g) => g,
+        None => return Ok(()),
+    };
+    if !is_star(&reaction.emoji) {
+        return Ok(());
+    }
+    update_message(ctx, data, guild_id, reaction.channel_id, reaction.message_id).await
+}
+
+/// Handle all reactions being removed from a message
+pub async fn on_reactions_cleared(ctx: &serenity::Context, data: &Data, message_id: MessageId) -> Result<(), Error> {
+    let _guard = data.starboard.lock(message_id).await;
+    remove_post(ctx, data, message_id).await
+}
+
+// Commands ->
+
+/// Repost popular messages to a starboard channel
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    subcommands("starboard_enable", "starboard_disable", "starboard_threshold", "starboard_exclude", "starboard_include"),
+    subcommand_required
+)]
+pub async fn starboard(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Enable the starboard in a channel
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "enable"
+)]
+pub async fn starboard_enable(
+    ctx: Context<'_>,
+    #[description = "Channel to repost starred messages in"]
+    channel: ChannelId,
+) -> Result<(), Error> {
+    if !commands::check_channel_writable(ctx, channel).await? {
+        return Ok(());
+    }
+    ctx.data()
+        .database
+        .set_guild_value(&ctx.guild_id().unwrap(), &"config.starboard_channel", &channel)
+        .await?;
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Messages with enough {} reactions will be reposted in {}", STAR, channel.mention()))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Disable the starboard
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "disable"
+)]
+pub async fn starboard_disable(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.data()
+        .database
+        .delete_guild_value(&ctx.guild_id().unwrap(), &"config.starboard_channel")
+        .await?;
+    ctx.send(
+        CreateReply::default()
+            .content("Starboard disabled.".to_string())
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Set how many stars a message needs to make it to the starboard
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "threshold"
+)]
+pub async fn starboard_threshold(
+    ctx: Context<'_>,
+    #[description = "Number of stars. The author's own star doesn't count"]
+    #[min = 1]
+    #[max = 100]
+    stars: u32,
+) -> Result<(), Error> {
+    ctx.data()
+        .database
+        .set_guild_value(&ctx.guild_id().unwrap(), &"config.starboard_threshold", &stars)
+        .await?;
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Messages now need {} {} to make it to the starboard.", stars, STAR))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Stop messages in a channel from being starred
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "exclude"
+)]
+pub async fn starboard_exclude(
+    ctx: Context<'_>,
+    #[description = "Channel to exclude from the starboard"]
+    channel: ChannelId,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let mut channels = excluded_channels(ctx.data(), guild_id).await?;
+    if !channels.contains(&channel) {
+        channels.push(channel);
+        ctx.data()
+            .database
+            .set_guild_value(&guild_id, &"config.starboard_exclude", &join_channels(&channels))
+            .await?;
+    }
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Messages in {} will no longer be starred.", channel.mention()))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Allow messages in an excluded channel to be starred again
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "include"
+)]
+pub async fn starboard_include(
+    ctx: Context<'_>,
+    #[description = "Channel to include in the starboard again"]
+    channel: ChannelId,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let mut channels = excluded_channels(ctx.data(), guild_id).await?;
+    channels.retain(|c| *c != channel);
+    if channels.is_empty() {
+        ctx.data().database.delete_guild_value(&guild_id, &"config.starboard_exclude").await?;
+    } else {
+        ctx.data()
+            .database
+            .set_guild_value(&guild_id, &"config.starboard_exclude", &join_channels(&channels))
+            .await?;
+    }
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Messages in {} can be starred again.", channel.mention()))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
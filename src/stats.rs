@@ -0,0 +1,182 @@
+use poise::serenity_prelude::{Colour, CreateEmbed, CreateEmbedFooter, GuildId, Member, Mentionable, Message, User};
+use poise::CreateReply;
+use crate::{Context, Data, Error};
+
+/// How many entries the leaderboards show
+const LEADERBOARD_SIZE: i64 = 10;
+
+/// The UTC date of `days_ago` days ago, in the format used by the statistics tables
+fn day_string(days_ago: i64) -> String {
+    (chrono::Utc::now() - chrono::Duration::days(days_ago)).format("%Y-%m-%d").to_string()
+}
+
+async fn is_opted_out(data: &Data, user: &User) -> Result<bool, Error> {
+    Ok(data.database.get_user_value(&user.id, &"stats_optout").await?.is_some())
+}
+
+// Event handlers ->
+
+/// Count a message towards the guild's activity statistics
+pub async fn on_message(data: &Data, message: &Message) -> Result<(), Error> {
+    let guild_id = match message.guild_id {
+        Some(g) => g,
+        None => return Ok(()),
+    };
+    if message.author.bot {
+        return Ok(());
+    }
+
+    // Opted out users still count towards the anonymous channel totals
+    let user_id = if is_opted_out(data, &message.author).await? {
+        None
+    } else {
+        Some(message.author.id)
+    };
+    data.database.record_message_stat(&guild_id, &message.channel_id, user_id.as_ref(), &day_string(0)).await?;
+    Ok(())
+}
+
+/// Count a member joining
+pub async fn on_member_join(data: &Data, member: &Member) -> Result<(), Error> {
+    data.database.record_join_stat(&member.guild_id, &day_string(0), true).await?;
+    Ok(())
+}
+
+/// Count a member leaving
+pub async fn on_member_leave(data: &Data, guild_id: GuildId) -> Result<(), Error> {
+    data.database.record_join_stat(&guild_id, &day_string(0), false).await?;
+    Ok(())
+}
+
+// Commands ->
+
+/// Show server activity statistics
+#[poise::command(slash_command, guild_only, default_member_permissions = "SEND_MESSAGES")]
+pub async fn stats(
+    ctx: Context<'_>,
+    #[description = "How many days to look back. Defaults to 30"]
+    #[min = 1]
+    #[max = 365]
+    days: Option<u32>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let days = days.unwrap_or(30);
+    let guild_id = ctx.guild_id().unwrap();
+    let since = day_string(days as i64 - 1);
+    let database = &ctx.data().database;
+
+    let channels = database.get_channel_message_stats(&guild_id, &since).await?;
+    let active_members = database.get_active_member_count(&guild_id, &since).await?;
+    let (joins, leaves) = database.get_join_stats(&guild_id, &since).await?;
+    let total_messages = channels.iter().map(|(_, c)| c).sum::<i64>();
+    let member_count = ctx.guild().map(|g| g.member_count).unwrap_or(0);
+
+    let top_channels = if channels.is_empty() {
+        String::from("No messages yet")
+    } else {
+        channels.iter()
+            .take(LEADERBOARD_SIZE as usize)
+            .enumerate()
+            .map(|(i, (channel, count))| format!("{}. <#{}> - {} messages", i + 1, channel, count))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    let mut embed = CreateEmbed::new()
+        .title(format!("📊 Server statistics, last {} days", days))
+        .field("Members:", member_count.to_string(), true)
+        .field("Active members:", active_members.to_string(), true)
+        .field("Messages:", total_messages.to_string(), true)
+        .field("Joins:", joins.to_string(), true)
+        .field("Leaves:", leaves.to_string(), true)
+        .field("Busiest channels:", top_channels, false)
+        .color(Colour::BLITZ_BLUE)
+        .footer(CreateEmbedFooter::new("Members can opt out of activity tracking with /activity optout"));
+    if let Some(first_join) = database.get_guild_value(&guild_id, &"stats.first_join").await? {
+        embed = embed.field("Bot joined:", first_join, false);
+    }
+
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Member activity leaderboards and privacy settings
+#[poise::command(
+    slash_command,
+    default_member_permissions = "SEND_MESSAGES",
+    subcommands("activity_top", "activity_optout", "activity_optin"),
+    subcommand_required
+)]
+pub async fn activity(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Show the most active members
+#[poise::command(slash_command, guild_only, default_member_permissions = "SEND_MESSAGES", rename = "top")]
+pub async fn activity_top(
+    ctx: Context<'_>,
+    #[description = "How many days to look back. Defaults to 30"]
+    #[min = 1]
+    #[max = 365]
+    days: Option<u32>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let days = days.unwrap_or(30);
+    let guild_id = ctx.guild_id().unwrap();
+    let members = ctx.data()
+        .database
+        .get_member_message_stats(&guild_id, &day_string(days as i64 - 1), LEADERBOARD_SIZE)
+        .await?;
+
+    let text = if members.is_empty() {
+        String::from("Nobody has said anything yet.")
+    } else {
+        members.iter()
+            .enumerate()
+            .map(|(i, (user, count))| format!("{}. <@{}> - {} messages", i + 1, user, count))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    ctx.send(
+        CreateReply::default().embed(
+            CreateEmbed::new()
+                .title(format!("🏆 Most active members, last {} days", days))
+                .description(text)
+                .color(Colour::GOLD),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Stop tracking your activity and delete your activity statistics
+#[poise::command(slash_command, default_member_permissions = "SEND_MESSAGES", rename = "optout")]
+pub async fn activity_optout(ctx: Context<'_>) -> Result<(), Error> {
+    let database = &ctx.data().database;
+    database.set_user_value(&ctx.author().id, &"stats_optout", &"").await?;
+    database.delete_member_stats(&ctx.author().id).await?;
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "{}, your activity will no longer be tracked and your existing statistics have been deleted.",
+                ctx.author().mention()
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Allow your activity to be tracked again
+#[poise::command(slash_command, default_member_permissions = "SEND_MESSAGES", rename = "optin")]
+pub async fn activity_optin(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.data().database.delete_user_value(&ctx.author().id, &"stats_optout").await?;
+    ctx.send(
+        CreateReply::default()
+            .content("Your activity will be tracked again.".to_string())
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
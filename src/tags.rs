@@ -0,0 +1,282 @@
+use log::info;
+use poise::serenity_prelude::{
+    Colour, CreateAllowedMentions, CreateEmbed, CreateEmbedFooter, Mentionable, Permissions, Role, User,
+};
+use poise::CreateReply;
+use crate::database::Tag;
+use crate::{tools, welcome};
+use crate::{Context, Error};
+
+/// How many names the autocomplete suggests, Discord shows no more than this
+const AUTOCOMPLETE_LIMIT: i64 = 25;
+
+/// Tag names are case insensitive
+fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Suggest tag names of the current guild
+async fn autocomplete_tag(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let guild_id = match ctx.guild_id() {
+        Some(g) => g,
+        None => return vec![],
+    };
+    ctx.data()
+        .database
+        .get_tag_names(&guild_id, &normalize_name(partial), AUTOCOMPLETE_LIMIT)
+        .await
+        .unwrap_or_default()
+}
+
+async fn reply_ephemeral(ctx: Context<'_>, text: impl Into<String>) -> Result<(), Error> {
+    ctx.send(CreateReply::default().content(text).ephemeral(true)).await?;
+    Ok(())
+}
+
+async fn has_permission(ctx: Context<'_>, check: fn(&Permissions) -> bool) -> bool {
+    ctx.author_member()
+        .await
+        .and_then(|m| m.permissions)
+        .is_some_and(|p| check(&p))
+}
+
+/// Whether the author may create tags. Members who can manage the server always can.
+async fn can_create(ctx: Context<'_>) -> Result<bool, Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let role = match ctx.data().database.get_guild_value(&guild_id, &"config.tag_role").await? {
+        Some(r) => match tools::to_role(&r) {
+            Some(r) => r,
+            None => return Ok(true),
+        },
+        None => return Ok(true),
+    };
+    if has_permission(ctx, |p| p.manage_guild()).await {
+        return Ok(true);
+    }
+    Ok(ctx.author_member().await.is_some_and(|m| m.roles.contains(&role)))
+}
+
+/// Whether the author may change a tag: its creator and moderators can
+async fn can_manage(ctx: Context<'_>, tag: &Tag) -> bool {
+    tag.author_id == ctx.author().id.to_string() || has_permission(ctx, |p| p.manage_messages()).await
+}
+
+// Commands ->
+
+/// Saved responses for frequently given answers
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "SEND_MESSAGES",
+    subcommands("tag_show", "tag_create", "tag_edit", "tag_delete", "tag_list", "tag_info", "tag_role"),
+    subcommand_required
+)]
+pub async fn tag(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Post a tag
+///
+/// Placeholders: {user}, {username}, {server}, {member_count} and {account_age}. They refer to the
+/// mentioned user, or to you if nobody is mentioned.
+#[poise::command(slash_command, guild_only, default_member_permissions = "SEND_MESSAGES", rename = "show")]
+pub async fn tag_show(
+    ctx: Context<'_>,
+    #[description = "Name of the tag"]
+    #[autocomplete = "autocomplete_tag"]
+    name: String,
+    #[description = "Who the tag is for"] user: Option<User>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let tag = match ctx.data().database.get_tag(&guild_id, &normalize_name(&name)).await? {
+        Some(t) => t,
+        None => return reply_ephemeral(ctx, format!("There's no tag called `{}`.", normalize_name(&name))).await,
+    };
+
+    let target = user.as_ref().unwrap_or(ctx.author());
+    let values = welcome::TemplateValues::new(ctx.cache(), guild_id, target);
+    let text = welcome::render_template(&tag.content, &values);
+    // Anyone may be able to create tags, so they must not ping more than the user they're for
+    let mentions = CreateAllowedMentions::new().users([target.id]);
+    let reply = if tag.embed {
+        let mut reply = CreateReply::default().allowed_mentions(mentions).embed(
+            CreateEmbed::new()
+                .description(text)
+                .color(Colour::BLITZ_BLUE)
+                .footer(CreateEmbedFooter::new(format!("Tag: {}", tag.name)))
+        );
+        if let Some(user) = &user {
+            reply = reply.content(user.mention().to_string());
+        }
+        reply
+    } else {
+        CreateReply::default().content(text).allowed_mentions(mentions)
+    };
+    ctx.send(reply).await?;
+    ctx.data().database.increment_tag_uses(&guild_id, &tag.name).await?;
+    Ok(())
+}
+
+/// Create a tag
+#[poise::command(slash_command, guild_only, default_member_permissions = "SEND_MESSAGES", rename = "create")]
+pub async fn tag_create(
+    ctx: Context<'_>,
+    #[description = "Name of the tag"]
+    #[max_length = 32]
+    name: String,
+    #[description = "What the tag says. Supports markdown and placeholders like {user} and {server}"]
+    #[max_length = 2000]
+    content: String,
+    #[description = "Show the tag as an embed. Defaults to false"] embed: Option<bool>,
+) -> Result<(), Error> {
+    if !can_create(ctx).await? {
+        return reply_ephemeral(ctx, "You don't have the role required to create tags.").await;
+    }
+    let name = normalize_name(&name);
+    if name.is_empty() {
+        return reply_ephemeral(ctx, "Tag names can't be empty.").await;
+    }
+    let content = content.replace("\\n", "\n");
+
+    let created = ctx.data()
+        .database
+        .create_tag(&ctx.guild_id().unwrap(), &name, &content, embed.unwrap_or(false), &ctx.author().id)
+        .await?;
+    if created {
+        info!("{} created tag {}", ctx.author().name, name);
+        reply_ephemeral(ctx, format!("Created tag `{}`.", name)).await
+    } else {
+        reply_ephemeral(ctx, format!("A tag called `{}` already exists.", name)).await
+    }
+}
+
+/// Change what a tag says
+#[poise::command(slash_command, guild_only, default_member_permissions = "SEND_MESSAGES", rename = "edit")]
+pub async fn tag_edit(
+    ctx: Context<'_>,
+    #[description = "Name of the tag"]
+    #[autocomplete = "autocomplete_tag"]
+    name: String,
+    #[description = "What the tag says. Supports markdown and placeholders like {user} and {server}"]
+    #[max_length = 2000]
+    content: String,
+    #[description = "Show the tag as an embed. Keeps the current setting if not given"] embed: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let name = normalize_name(&name);
+    let tag = match ctx.data().database.get_tag(&guild_id, &name).await? {
+        Some(t) => t,
+        None => return reply_ephemeral(ctx, format!("There's no tag called `{}`.", name)).await,
+    };
+    if !can_manage(ctx, &tag).await {
+        return reply_ephemeral(ctx, "Only the creator of the tag or a moderator can edit it.").await;
+    }
+
+    let content = content.replace("\\n", "\n");
+    ctx.data().database.edit_tag(&guild_id, &name, &content, embed.unwrap_or(tag.embed)).await?;
+    info!("{} edited tag {}", ctx.author().name, name);
+    reply_ephemeral(ctx, format!("Updated tag `{}`.", name)).await
+}
+
+/// Delete a tag
+#[poise::command(slash_command, guild_only, default_member_permissions = "SEND_MESSAGES", rename = "delete")]
+pub async fn tag_delete(
+    ctx: Context<'_>,
+    #[description = "Name of the tag"]
+    #[autocomplete = "autocomplete_tag"]
+    name: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let name = normalize_name(&name);
+    let tag = match ctx.data().database.get_tag(&guild_id, &name).await? {
+        Some(t) => t,
+        None => return reply_ephemeral(ctx, format!("There's no tag called `{}`.", name)).await,
+    };
+    if !can_manage(ctx, &tag).await {
+        return reply_ephemeral(ctx, "Only the creator of the tag or a moderator can delete it.").await;
+    }
+
+    ctx.data().database.delete_tag(&guild_id, &name).await?;
+    info!("{} deleted tag {}", ctx.author().name, name);
+    reply_ephemeral(ctx, format!("Deleted tag `{}`.", name)).await
+}
+
+/// List all tags of this server
+#[poise::command(slash_command, guild_only, default_member_permissions = "SEND_MESSAGES", rename = "list")]
+pub async fn tag_list(ctx: Context<'_>) -> Result<(), Error> {
+    let names = ctx.data().database.get_tag_names(&ctx.guild_id().unwrap(), "", i64::MAX).await?;
+    let text = if names.is_empty() {
+        String::from("This server has no tags yet. Create one with `/tag create`.")
+    } else {
+        tools::truncate(&names.iter().map(|n| format!("`{}`", n)).collect::<Vec<String>>().join(", "), 4000)
+    };
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title(format!("🏷️ Tags ({})", names.len()))
+                    .description(text)
+                    .color(Colour::BLITZ_BLUE),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Show who made a tag and how often it was used
+#[poise::command(slash_command, guild_only, default_member_permissions = "SEND_MESSAGES", rename = "info")]
+pub async fn tag_info(
+    ctx: Context<'_>,
+    #[description = "Name of the tag"]
+    #[autocomplete = "autocomplete_tag"]
+    name: String,
+) -> Result<(), Error> {
+    let name = normalize_name(&name);
+    let tag = match ctx.data().database.get_tag(&ctx.guild_id().unwrap(), &name).await? {
+        Some(t) => t,
+        None => return reply_ephemeral(ctx, format!("There's no tag called `{}`.", name)).await,
+    };
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title(format!("🏷️ {}", tag.name))
+                    .field("Created by:", format!("<@{}>", tag.author_id), true)
+                    .field("Created:", format!("<t:{}:R>", tag.created_at), true)
+                    .field("Uses:", tag.uses.to_string(), true)
+                    .field("Embed:", if tag.embed { "Yes" } else { "No" }, true)
+                    .field("Content:", tools::truncate(&tag.content, 1000), false)
+                    .color(Colour::BLITZ_BLUE),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Restrict creating tags to a role
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD",
+    required_permissions = "MANAGE_GUILD",
+    rename = "role"
+)]
+pub async fn tag_role(
+    ctx: Context<'_>,
+    #[description = "Role required to create tags. Leave empty to let everyone create tags"]
+    role: Option<Role>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    match role {
+        Some(role) => {
+            ctx.data().database.set_guild_value(&guild_id, &"config.tag_role", &role.id).await?;
+            reply_ephemeral(ctx, format!("Only members with {} can create tags now.", role.mention())).await
+        }
+        None => {
+            ctx.data().database.delete_guild_value(&guild_id, &"config.tag_role").await?;
+            reply_ephemeral(ctx, "Everyone can create tags now.").await
+        }
+    }
+}
@@ -8,13 +8,36 @@ pub fn to_snowflake(snowflake_string: &str) -> Option<NonZeroU64> {
 
 pub fn to_channel(snowflake_string: &str)
     -> Option<poise::serenity_prelude::model::id::ChannelId> {
-    let snowflake = match to_snowflake(snowflake_string){
-        Some(s) => s,
-        None => return None,
-    };
+    let snowflake = to_snowflake(snowflake_string)?;
     Some(poise::serenity_prelude::model::id::ChannelId::from(snowflake))
 }
 
+pub fn to_role(snowflake_string: &str)
+    -> Option<poise::serenity_prelude::model::id::RoleId> {
+    let snowflake = to_snowflake(snowflake_string)?;
+    Some(poise::serenity_prelude::model::id::RoleId::from(snowflake))
+}
+
+/// Shorten text to at most `max` characters, ending in an ellipsis if anything was cut off
+pub fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut result = text.chars().take(max.saturating_sub(1)).collect::<String>();
+    result.push('…');
+    result
+}
+
+pub fn user_account_days(user_id: poise::serenity_prelude::UserId) -> i64 {
+    let timestamp = user_id.created_at().timestamp();
+    (chrono::Utc::now().timestamp() - timestamp) / (60 * 60 * 24)
+}
+
+/// How long ago a Discord timestamp was
+pub fn time_since(timestamp: poise::serenity_prelude::Timestamp) -> chrono::Duration {
+    chrono::Utc::now().signed_duration_since(*timestamp)
+}
+
 pub fn user_account_age(user_id: poise::serenity_prelude::UserId) -> String {
     // Get the timestamp from the user ID
     let timestamp = user_id.created_at().timestamp();
@@ -0,0 +1,362 @@
+use log::warn;
+use poise::serenity_prelude::{
+    ChannelId, Colour, CreateAllowedMentions, CreateEmbed, CreateEmbedFooter, CreateMessage, GuildId, Member,
+    Mentionable, User,
+};
+use poise::CreateReply;
+use crate::{commands, serenity, tools};
+use crate::{Context, Data, Error};
+
+const DEFAULT_WELCOME: &str = "Welcome to **{server}**, {user}! You are member #{member_count}.";
+const DEFAULT_GOODBYE: &str = "**{username}** has left **{server}**.";
+/// Discord's limit for the content of a message
+const MAX_MESSAGE_LENGTH: usize = 2000;
+/// Discord's limit for the value of an embed field, which the preview shows the messages in
+const MAX_FIELD_LENGTH: usize = 1024;
+
+/// Values that can be substituted into a welcome/goodbye template
+pub struct TemplateValues {
+    pub user: String,
+    pub username: String,
+    pub server: String,
+    pub member_count: u64,
+    pub account_age: String,
+}
+
+impl TemplateValues {
+    pub fn new(cache: &serenity::Cache, guild_id: GuildId, user: &User) -> Self {
+        let (server, member_count) = match cache.guild(guild_id) {
+            Some(g) => (g.name.clone(), g.member_count),
+            None => (String::from("Unknown"), 0),
+        };
+        Self {
+            user: user.mention().to_string(),
+            username: user.name.clone(),
+            server,
+            member_count,
+            account_age: tools::user_account_age(user.id),
+        }
+    }
+}
+
+/// Replace all known placeholders in a template. Unknown placeholders are left as-is.
+pub fn render_template(template: &str, values: &TemplateValues) -> String {
+    template
+        .replace("{user}", &values.user)
+        .replace("{username}", &values.username)
+        .replace("{server}", &values.server)
+        .replace("{member_count}", &values.member_count.to_string())
+        .replace("{account_age}", &values.account_age)
+}
+
+/// Wrap a rendered template in a message, as an embed if the guild has that enabled
+fn build_message(text: String, as_embed: bool, user: &User, joined: bool) -> CreateMessage {
+    // Placeholders can make the text longer than the template
+    let text = tools::truncate(&text, MAX_MESSAGE_LENGTH);
+    // Only the member the message is about gets pinged, whatever the template says
+    let mentions = CreateAllowedMentions::new().users([user.id]);
+    if !as_embed {
+        return CreateMessage::new().content(text).allowed_mentions(mentions);
+    }
+    CreateMessage::new().allowed_mentions(mentions).embed(
+        CreateEmbed::new()
+            .description(text)
+            .thumbnail(user.face())
+            .color(if joined { Colour::DARK_GREEN } else { Colour::DARK_RED })
+            .footer(CreateEmbedFooter::new(format!("User ID: {}", user.id)))
+    )
+}
+
+async fn is_embed_enabled(data: &Data, guild_id: GuildId) -> Result<bool, Error> {
+    Ok(data.database.get_guild_value(&guild_id, &"config.welcome_embed").await?.is_some())
+}
+
+// Event handlers ->
+
+/// Post the welcome message and send the welcome DM, if configured
+pub async fn on_member_join(ctx: &serenity::Context, data: &Data, member: &Member) -> Result<(), Error> {
+    let guild_id = member.guild_id;
+    let values = TemplateValues::new(&ctx.cache, guild_id, &member.user);
+
+    if let Some(channel) = data.database.get_guild_value(&guild_id, &"config.welcome_channel").await? {
+        let template = data.database.get_guild_value(&guild_id, &"config.welcome_message").await?
+            .unwrap_or(DEFAULT_WELCOME.to_string());
+        let as_embed = is_embed_enabled(data, guild_id).await?;
+        if let Some(channel_id) = tools::to_channel(channel.as_str()) {
+            channel_id.send_message(&ctx.http,
+                build_message(render_template(&template, &values), as_embed, &member.user, true)
+            ).await?;
+        }
+    }
+
+    if let Some(template) = data.database.get_guild_value(&guild_id, &"config.welcome_dm").await? {
+        // Users can have DMs from server members disabled, that's not our problem
+        if let Err(e) = member.user.direct_message(&ctx.http,
+            CreateMessage::new().content(tools::truncate(&render_template(&template, &values), MAX_MESSAGE_LENGTH))
+        ).await {
+            warn!("Failed to send welcome DM to user ID {}: {}", member.user.id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Post the goodbye message, if configured
+pub async fn on_member_leave(ctx: &serenity::Context, data: &Data, guild_id: GuildId, user: &User) -> Result<(), Error> {
+    let channel = match data.database.get_guild_value(&guild_id, &"config.welcome_channel").await? {
+        Some(c) => c,
+        None => return Ok(()),
+    };
+    let template = match data.database.get_guild_value(&guild_id, &"config.goodbye_message").await? {
+        Some(t) => t,
+        None => return Ok(()), // Goodbye messages are opt-in
+    };
+    let as_embed = is_embed_enabled(data, guild_id).await?;
+    let values = TemplateValues::new(&ctx.cache, guild_id, user);
+    if let Some(channel_id) = tools::to_channel(channel.as_str()) {
+        channel_id.send_message(&ctx.http,
+            build_message(render_template(&template, &values), as_embed, user, false)
+        ).await?;
+    }
+    Ok(())
+}
+
+// Commands ->
+
+/// Greet new members and say goodbye to leaving ones
+///
+/// Templates can contain these placeholders: {user}, {username}, {server}, {member_count}, {account_age}
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    subcommands(
+        "welcome_enable",
+        "welcome_disable",
+        "welcome_message",
+        "welcome_goodbye",
+        "welcome_embed",
+        "welcome_dm",
+        "welcome_test"
+    ),
+    subcommand_required
+)]
+pub async fn welcome(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Enable welcome messages in a channel
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "enable"
+)]
+pub async fn welcome_enable(
+    ctx: Context<'_>,
+    #[description = "Channel to post welcome and goodbye messages in"]
+    channel: ChannelId,
+) -> Result<(), Error> {
+    if !commands::check_channel_writable(ctx, channel).await? {
+        return Ok(());
+    }
+    ctx.data()
+        .database
+        .set_guild_value(&ctx.guild_id().unwrap(), &"config.welcome_channel", &channel)
+        .await?;
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Welcome messages enabled in {}", channel.mention()))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Disable welcome and goodbye messages
+///
+/// The configured templates are kept.
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "disable"
+)]
+pub async fn welcome_disable(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.data()
+        .database
+        .delete_guild_value(&ctx.guild_id().unwrap(), &"config.welcome_channel")
+        .await?;
+    ctx.send(
+        CreateReply::default()
+            .content("Welcome messages disabled.".to_string())
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Set the welcome message template
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "message"
+)]
+pub async fn welcome_message(
+    ctx: Context<'_>,
+    #[description = "Message template. Leave empty to restore the default"]
+    #[max_length = 1000]
+    template: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    match template {
+        Some(t) => ctx.data().database.set_guild_value(&guild_id, &"config.welcome_message", &t).await?,
+        None => {
+            ctx.data().database.delete_guild_value(&guild_id, &"config.welcome_message").await?;
+        }
+    }
+    ctx.send(
+        CreateReply::default()
+            .content("Welcome message updated.".to_string())
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Enable or disable goodbye messages
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "goodbye"
+)]
+pub async fn welcome_goodbye(
+    ctx: Context<'_>,
+    #[description = "Post a message when a member leaves"]
+    enabled: bool,
+    #[description = "Message template. Leave empty to use the default"]
+    #[max_length = 1000]
+    template: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let text = if enabled {
+        let template = template.unwrap_or(DEFAULT_GOODBYE.to_string());
+        ctx.data().database.set_guild_value(&guild_id, &"config.goodbye_message", &template).await?;
+        "Goodbye messages enabled."
+    } else {
+        ctx.data().database.delete_guild_value(&guild_id, &"config.goodbye_message").await?;
+        "Goodbye messages disabled."
+    };
+    ctx.send(
+        CreateReply::default()
+            .content(text.to_string())
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Choose whether messages are posted as an embed
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "embed"
+)]
+pub async fn welcome_embed(
+    ctx: Context<'_>,
+    #[description = "Post welcome and goodbye messages as an embed"]
+    enabled: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    if enabled {
+        ctx.data().database.set_guild_value(&guild_id, &"config.welcome_embed", &"").await?;
+    } else {
+        ctx.data().database.delete_guild_value(&guild_id, &"config.welcome_embed").await?;
+    }
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Welcome embeds {}.", if enabled { "enabled" } else { "disabled" }))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Set the direct message new members receive
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "dm"
+)]
+pub async fn welcome_dm(
+    ctx: Context<'_>,
+    #[description = "Message template. Leave empty to disable welcome DMs"]
+    #[max_length = 1000]
+    template: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let text = match template {
+        Some(t) => {
+            ctx.data().database.set_guild_value(&guild_id, &"config.welcome_dm", &t).await?;
+            "Welcome DM updated."
+        }
+        None => {
+            ctx.data().database.delete_guild_value(&guild_id, &"config.welcome_dm").await?;
+            "Welcome DMs disabled."
+        }
+    };
+    ctx.send(
+        CreateReply::default()
+            .content(text.to_string())
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Preview the welcome, goodbye and DM messages as if you just joined
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    rename = "test"
+)]
+pub async fn welcome_test(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+    let guild_id = ctx.guild_id().unwrap();
+    let database = &ctx.data().database;
+    let values = TemplateValues::new(ctx.cache(), guild_id, ctx.author());
+
+    let channel = match database.get_guild_value(&guild_id, &"config.welcome_channel").await? {
+        Some(c) => format!("<#{}>", c),
+        None => String::from("None (disabled)"),
+    };
+    let welcome = database.get_guild_value(&guild_id, &"config.welcome_message").await?
+        .unwrap_or(DEFAULT_WELCOME.to_string());
+    let goodbye = database.get_guild_value(&guild_id, &"config.goodbye_message").await?;
+    let dm = database.get_guild_value(&guild_id, &"config.welcome_dm").await?;
+    let as_embed = is_embed_enabled(ctx.data(), guild_id).await?;
+
+    let render_optional = |t: Option<String>| match t {
+        Some(t) => tools::truncate(&render_template(&t, &values), MAX_FIELD_LENGTH),
+        None => String::from("*Disabled*"),
+    };
+
+    ctx.send(
+        CreateReply::default().embed(
+            CreateEmbed::new()
+                .title("👋 Welcome message preview")
+                .field("Channel:", channel, true)
+                .field("Embed:", if as_embed { "Yes" } else { "No" }, true)
+                .field("Welcome:", tools::truncate(&render_template(&welcome, &values), MAX_FIELD_LENGTH), false)
+                .field("Goodbye:", render_optional(goodbye), false)
+                .field("DM:", render_optional(dm), false)
+                .color(Colour::BLITZ_BLUE)
+        ),
+    )
+    .await?;
+    Ok(())
+}